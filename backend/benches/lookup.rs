@@ -0,0 +1,111 @@
+//! Benchmarks for the lookup hot path: word encoding, contract assembly and request
+//! handling against a stand-in client worker.
+//!
+//! Run with `cargo bench`, the MASM sources are read from `masm/`.
+
+use std::hint::black_box;
+use std::sync::Arc;
+
+use axum::extract::{Query, State};
+use axum::http::HeaderMap;
+use axum::response::IntoResponse;
+use criterion::{Criterion, criterion_group, criterion_main};
+use miden_lib::transaction::TransactionKernel;
+use tokio::runtime::Runtime;
+use tokio::sync::mpsc;
+
+use miden_name_service::db::Database;
+use miden_name_service::handler::{
+    AppState, ClientRequest, LookupOrigin, LookupParams, LookupResponse, LookupSource,
+    ResolverRecord, User, lookup_handler,
+};
+use miden_name_service::serde::{RecordVersion, str_to_word, word_to_str};
+use miden_name_service::utils::create_library;
+
+fn bench_word_encoding(c: &mut Criterion) {
+    c.bench_function("str_to_word", |b| {
+        b.iter(|| str_to_word(black_box("paulhenry.miden")))
+    });
+
+    let word = str_to_word("paulhenry.miden");
+    c.bench_function("word_to_str", |b| b.iter(|| word_to_str(black_box(word))));
+}
+
+fn bench_create_library(c: &mut Criterion) {
+    let source = std::fs::read_to_string("masm/mns.masm").expect("masm/mns.masm is readable");
+    let assembler = TransactionKernel::assembler().with_debug_mode(true);
+
+    c.bench_function("create_library", |b| {
+        b.iter(|| {
+            create_library(assembler.clone(), "mns::mns_contract", black_box(&source)).unwrap()
+        })
+    });
+}
+
+// State with 10k database users and a worker that answers every contract lookup at once
+fn bench_state(rt: &Runtime) -> AppState {
+    let db = Database::new(":memory:").unwrap();
+    let users: Vec<User> = (0..10_000)
+        .map(|i| User {
+            name: format!("user{}.miden", i),
+            address: format!("0x{:030x}", i),
+            version: RecordVersion::Web2,
+        })
+        .collect();
+    db.insert_users(&users).unwrap();
+
+    let (tx, mut rx) = mpsc::channel(1024);
+    rt.spawn(async move {
+        while let Some(request) = rx.recv().await {
+            if let ClientRequest::Lookup { respond, .. } = request {
+                let _ = respond.send(Ok(LookupResponse {
+                    address: "0xdde9bd696d7c6400000432b139e732".to_string(),
+                    version: RecordVersion::Web2_5,
+                    records: vec![ResolverRecord {
+                        record_type: "web2.5".to_string(),
+                        value: "0xdde9bd696d7c6400000432b139e732".to_string(),
+                    }],
+                    source: LookupOrigin::Contract,
+                    history: None,
+                    coin: None,
+                }));
+            }
+        }
+    });
+
+    AppState::for_tests(tx, Some(Arc::new(db)))
+}
+
+fn bench_lookup(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let state = bench_state(&rt);
+
+    let lookup = |name: &str, source: LookupSource| {
+        let params = LookupParams {
+            name: name.to_string(),
+            source,
+            ..Default::default()
+        };
+        rt.block_on(lookup_handler(
+            State(state.clone()),
+            HeaderMap::new(),
+            Query(params),
+        ))
+        .into_response()
+    };
+
+    c.bench_function("lookup_db", |b| {
+        b.iter(|| lookup(black_box("user5000.miden"), LookupSource::Db))
+    });
+    c.bench_function("lookup_contract_mocked", |b| {
+        b.iter(|| lookup(black_box("alice.miden"), LookupSource::Contract))
+    });
+}
+
+criterion_group!(
+    benches,
+    bench_word_encoding,
+    bench_create_library,
+    bench_lookup
+);
+criterion_main!(benches);
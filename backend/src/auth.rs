@@ -0,0 +1,147 @@
+use axum::{
+    extract::{Request, State},
+    http::HeaderMap,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use crate::error::{AppError, Result};
+
+/// Header carrying the API key on requests to mutating endpoints
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// Header carrying the owner token of the name a request changes
+pub const OWNER_TOKEN_HEADER: &str = "x-owner-token";
+
+/// Fresh secret proving control of a database name, returned once by its registration
+pub fn new_owner_token() -> String {
+    let bytes: [u8; 32] = rand::random();
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// What the database keeps of an owner token, its hex SHA-256, so a leaked database
+/// does not hand out control of every name
+pub fn hash_owner_token(token: &str) -> String {
+    let digest = Sha256::digest(token.as_bytes());
+    digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Whether a provided secret equals the expected one, compared by SHA-256 digest so the
+/// time taken tells nothing about how much of the secret matched
+pub fn secrets_match(provided: &str, expected: &str) -> bool {
+    Sha256::digest(provided.as_bytes()) == Sha256::digest(expected.as_bytes())
+}
+
+/// Keys accepted by [`require_api_key`], set with `--api-key`
+#[derive(Clone)]
+pub struct ApiKeys(Arc<HashSet<String>>);
+
+impl ApiKeys {
+    pub fn new(keys: impl IntoIterator<Item = String>) -> Self {
+        Self(Arc::new(keys.into_iter().collect()))
+    }
+
+    fn check(&self, headers: &HeaderMap) -> Result<()> {
+        self.check_key(
+            headers
+                .get(API_KEY_HEADER)
+                .and_then(|value| value.to_str().ok()),
+        )
+    }
+
+    /// Accept `provided` if it is one of the keys, shared by HTTP and gRPC
+    pub fn check_key(&self, provided: Option<&str>) -> Result<()> {
+        match provided {
+            Some(key) if self.0.contains(key) => Ok(()),
+            Some(_) => Err(AppError::Unauthorized("Invalid API key".to_string())),
+            None => Err(AppError::Unauthorized("Missing API key".to_string())),
+        }
+    }
+}
+
+/// Middleware answering 401 to requests without one of the configured API keys
+pub async fn require_api_key(
+    State(keys): State<ApiKeys>,
+    request: Request,
+    next: Next,
+) -> Response {
+    match keys.check(request.headers()) {
+        Ok(()) => next.run(request).await,
+        Err(e) => e.into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        Router,
+        body::Body,
+        http::{Request, StatusCode},
+        middleware::from_fn_with_state,
+        routing::{get, put},
+    };
+    use tower::ServiceExt;
+
+    use super::{
+        API_KEY_HEADER, ApiKeys, hash_owner_token, new_owner_token, require_api_key, secrets_match,
+    };
+
+    #[tokio::test]
+    async fn test_api_key_guards_mutating_routes_only() {
+        let keys = ApiKeys::new(["first".to_string(), "second".to_string()]);
+        let app = Router::new()
+            .route("/register", put(|| async { "registered" }))
+            .route_layer(from_fn_with_state(keys, require_api_key))
+            .route("/lookup", get(|| async { "found" }));
+
+        let status = |method: &str, uri: &str, key: Option<&str>| {
+            let mut request = Request::builder().method(method).uri(uri);
+            if let Some(key) = key {
+                request = request.header(API_KEY_HEADER, key);
+            }
+            let app = app.clone();
+            async move {
+                app.oneshot(request.body(Body::empty()).unwrap())
+                    .await
+                    .unwrap()
+                    .status()
+            }
+        };
+
+        assert_eq!(
+            status("PUT", "/register", None).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            status("PUT", "/register", Some("wrong")).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            status("PUT", "/register", Some("second")).await,
+            StatusCode::OK
+        );
+        assert_eq!(status("GET", "/lookup", None).await, StatusCode::OK);
+    }
+
+    #[test]
+    fn test_owner_tokens_are_random_and_hashed() {
+        let token = new_owner_token();
+        assert_eq!(token.len(), 64);
+        assert_ne!(token, new_owner_token());
+
+        let hash = hash_owner_token(&token);
+        assert_eq!(hash.len(), 64);
+        assert_ne!(hash, token);
+        assert_eq!(hash, hash_owner_token(&token));
+    }
+
+    #[test]
+    fn test_secrets_match() {
+        assert!(secrets_match("admin", "admin"));
+        assert!(!secrets_match("admin", "admin2"));
+        assert!(!secrets_match("", "admin"));
+    }
+}
@@ -0,0 +1,332 @@
+//! Offline export and import of the `users` table.
+//!
+//! Only database registrations are covered, names registered on-chain live in the
+//! contract storage and are not part of a backup. Each row keeps the parent of a subname
+//! and the hash of its owner token, so restored names can still be changed by their
+//! owners. Resolver records other than the address, coin addresses and history are not
+//! carried over.
+
+use log::info;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use crate::db::{Database, UserRow};
+use crate::error::{AppError, Result};
+use crate::handler::User;
+use crate::serde::{Name, RecordVersion};
+
+const CSV_HEADER: &str = "name,address,version,parent,owner_token_hash";
+
+/// Header of backups written before rows carried a parent and owner token hash
+const LEGACY_CSV_HEADER: &str = "name,address,version";
+
+/// File format of a backup, picked from the file extension
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Csv,
+}
+
+impl Format {
+    /// `.csv` files are CSV, anything else is JSON
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("csv") => Format::Csv,
+            _ => Format::Json,
+        }
+    }
+}
+
+fn io_error(path: &Path, e: impl std::fmt::Display) -> AppError {
+    AppError::Internal(format!("{}: {}", path.display(), e))
+}
+
+/// Write every user to `path`, returning how many were exported
+pub fn export_users(db: &Database, path: &Path) -> Result<u64> {
+    let format = Format::from_path(path);
+    let file = File::create(path).map_err(|e| io_error(path, e))?;
+    let mut out = BufWriter::new(file);
+
+    let exported = match format {
+        Format::Json => {
+            out.write_all(b"[").map_err(|e| io_error(path, e))?;
+            let mut first = true;
+            let exported = db.iter_all_users(|row| {
+                let separator: &[u8] = if first { b"\n" } else { b",\n" };
+                first = false;
+                out.write_all(separator).map_err(|e| io_error(path, e))?;
+                serde_json::to_writer(&mut out, &row).map_err(|e| io_error(path, e))
+            })?;
+            out.write_all(b"\n]\n").map_err(|e| io_error(path, e))?;
+            exported
+        }
+        Format::Csv => {
+            writeln!(out, "{}", CSV_HEADER).map_err(|e| io_error(path, e))?;
+            db.iter_all_users(|row| {
+                writeln!(
+                    out,
+                    "{},{},{},{},{}",
+                    csv_field(path, &row.user.name)?,
+                    csv_field(path, &row.user.address)?,
+                    row.user.version,
+                    csv_field(path, row.parent.as_deref().unwrap_or_default())?,
+                    row.owner_token_hash.as_deref().unwrap_or_default()
+                )
+                .map_err(|e| io_error(path, e))
+            })?
+        }
+    };
+
+    out.flush().map_err(|e| io_error(path, e))?;
+    info!("Exported {} users to {}", exported, path.display());
+    Ok(exported)
+}
+
+/// Insert every user from `path` in a single transaction, returning how many were imported.
+///
+/// Names are validated like API registrations, and the import fails as a whole with
+/// `Conflict` if any name is already registered.
+pub fn import_users(db: &Database, path: &Path, max_name_len: usize) -> Result<usize> {
+    let file = File::open(path).map_err(|e| io_error(path, e))?;
+    let reader = BufReader::new(file);
+
+    let rows = match Format::from_path(path) {
+        Format::Json => {
+            serde_json::from_reader::<_, Vec<UserRow>>(reader).map_err(|e| io_error(path, e))?
+        }
+        Format::Csv => parse_csv(reader, path)?,
+    };
+
+    for row in &rows {
+        Name::parse(&row.user.name, max_name_len)?;
+    }
+
+    db.restore_users(&rows)?;
+    info!("Imported {} users from {}", rows.len(), path.display());
+    Ok(rows.len())
+}
+
+fn parse_csv(reader: impl BufRead, path: &Path) -> Result<Vec<UserRow>> {
+    let mut lines = reader.lines();
+    let columns = match lines.next() {
+        Some(Ok(header)) if header.trim_end() == CSV_HEADER => 5,
+        Some(Ok(header)) if header.trim_end() == LEGACY_CSV_HEADER => 3,
+        Some(Err(e)) => return Err(io_error(path, e)),
+        _ => {
+            return Err(AppError::BadRequest(format!(
+                "{}: expected a '{}' header",
+                path.display(),
+                CSV_HEADER
+            )));
+        }
+    };
+
+    let mut rows = Vec::new();
+    for (i, line) in lines.enumerate() {
+        let line = line.map_err(|e| io_error(path, e))?;
+        if line.is_empty() {
+            continue;
+        }
+
+        let mut fields = split_csv_line(&line).ok_or_else(|| {
+            AppError::BadRequest(format!("{}:{}: unterminated quote", path.display(), i + 2))
+        })?;
+        if fields.len() != columns {
+            return Err(AppError::BadRequest(format!(
+                "{}:{}: expected {} fields",
+                path.display(),
+                i + 2,
+                columns
+            )));
+        }
+        // legacy rows have neither a parent nor an owner token hash
+        fields.resize(5, String::new());
+        let [name, address, version, parent, owner_token_hash]: [String; 5] =
+            fields.try_into().unwrap_or_default();
+        // empty fields are unset columns, as in the export
+        let optional = |value: String| Some(value).filter(|value| !value.is_empty());
+
+        let version = version
+            .parse::<RecordVersion>()
+            .map_err(|e| AppError::BadRequest(format!("{}:{}: {}", path.display(), i + 2, e)))?;
+        rows.push(UserRow {
+            user: User {
+                name,
+                address,
+                version,
+            },
+            parent: optional(parent),
+            owner_token_hash: optional(owner_token_hash),
+        });
+    }
+
+    Ok(rows)
+}
+
+// Quote a field if it contains a separator or quote. The import reads one row per line,
+// so a value with a line break can only be backed up as JSON.
+fn csv_field(path: &Path, value: &str) -> Result<String> {
+    if value.contains(['\n', '\r']) {
+        return Err(AppError::BadRequest(format!(
+            "{}: '{}' contains a line break, export to a .json file instead",
+            path.display(),
+            value.escape_default()
+        )));
+    }
+    if value.contains([',', '"']) {
+        Ok(format!("\"{}\"", value.replace('"', "\"\"")))
+    } else {
+        Ok(value.to_string())
+    }
+}
+
+// Split one CSV line, `None` if a quoted field is not closed
+fn split_csv_line(line: &str) -> Option<Vec<String>> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (c, quoted) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            ('"', true) => quoted = false,
+            ('"', false) if field.is_empty() => quoted = true,
+            (',', false) => fields.push(std::mem::take(&mut field)),
+            (c, _) => field.push(c),
+        }
+    }
+
+    if quoted {
+        return None;
+    }
+    fields.push(field);
+    Some(fields)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::backup::{export_users, import_users, split_csv_line};
+    use crate::db::Database;
+    use crate::error::AppError;
+    use crate::handler::User;
+    use crate::serde::{MAX_NAME_BYTES, RecordVersion};
+
+    fn temp_path(file: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("mns-backup-{}-{}", std::process::id(), file))
+    }
+
+    fn user(name: &str, address: &str) -> User {
+        User {
+            name: name.to_string(),
+            address: address.to_string(),
+            version: RecordVersion::Web2,
+        }
+    }
+
+    fn seeded_db() -> Database {
+        let db = Database::new(":memory:").unwrap();
+        db.insert_owned_user(&user("alice.miden", "0xabc"), Some("alice-hash"))
+            .unwrap();
+        db.insert_user(&user("a,\"b\".miden", "0xdef")).unwrap();
+        db.insert_subname(
+            &user("sub.alice.miden", "0x123"),
+            "alice.miden",
+            "alice-hash",
+            "sub-hash",
+        )
+        .unwrap();
+        db
+    }
+
+    // Every row as (name, parent, owner token hash)
+    fn rows(db: &Database) -> Vec<(String, Option<String>, Option<String>)> {
+        let mut rows = Vec::new();
+        db.iter_all_users(|row| {
+            rows.push((row.user.name, row.parent, row.owner_token_hash));
+            Ok(())
+        })
+        .unwrap();
+        rows
+    }
+
+    #[test]
+    fn test_export_import_round_trip() {
+        for file in ["users.json", "users.csv"] {
+            let path = temp_path(file);
+            let db = seeded_db();
+            assert_eq!(export_users(&db, &path).unwrap(), 3);
+
+            let restored = Database::new(":memory:").unwrap();
+            assert_eq!(import_users(&restored, &path, MAX_NAME_BYTES).unwrap(), 3);
+            let user = restored.lookup_user("a,\"b\".miden").unwrap().unwrap();
+            assert_eq!(user.address, "0xdef");
+            // subnames keep their parent and owners keep control of their names
+            assert_eq!(rows(&restored), rows(&db));
+
+            // importing again hits the existing names and changes nothing
+            assert!(matches!(
+                import_users(&restored, &path, MAX_NAME_BYTES),
+                Err(AppError::Conflict(_))
+            ));
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_export_empty_database_is_valid_json() {
+        let path = temp_path("empty.json");
+        let db = Database::new(":memory:").unwrap();
+        assert_eq!(export_users(&db, &path).unwrap(), 0);
+        assert_eq!(import_users(&db, &path, MAX_NAME_BYTES).unwrap(), 0);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_import_legacy_csv() {
+        let path = temp_path("legacy.csv");
+        std::fs::write(&path, "name,address,version\nalice.miden,0xabc,2\n").unwrap();
+        let db = Database::new(":memory:").unwrap();
+        assert_eq!(import_users(&db, &path, MAX_NAME_BYTES).unwrap(), 1);
+        assert_eq!(rows(&db), [("alice.miden".to_string(), None, None)]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_csv_export_rejects_line_breaks() {
+        let db = Database::new(":memory:").unwrap();
+        db.insert_user(&user("alice.miden", "0xabc\n0xdef"))
+            .unwrap();
+
+        let path = temp_path("multiline.csv");
+        assert!(matches!(
+            export_users(&db, &path),
+            Err(AppError::BadRequest(_))
+        ));
+        std::fs::remove_file(&path).unwrap();
+
+        // JSON escapes the line break
+        let path = temp_path("multiline.json");
+        assert_eq!(export_users(&db, &path).unwrap(), 1);
+        let restored = Database::new(":memory:").unwrap();
+        import_users(&restored, &path, MAX_NAME_BYTES).unwrap();
+        let user = restored.lookup_user("alice.miden").unwrap().unwrap();
+        assert_eq!(user.address, "0xabc\n0xdef");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_split_csv_line() {
+        assert_eq!(
+            split_csv_line("a,\"b,\"\"c\"\"\",d").unwrap(),
+            vec!["a", "b,\"c\"", "d"]
+        );
+        assert!(split_csv_line("a,\"b").is_none());
+    }
+}
@@ -0,0 +1,84 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+/// Fixed-size bloom filter over names, used to short-circuit lookups of unknown names
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    num_bits: u64,
+    num_hashes: u32,
+}
+
+impl BloomFilter {
+    /// Size the filter for `capacity` items at the given false-positive rate
+    pub fn new(capacity: usize, false_positive_rate: f64) -> Self {
+        let capacity = capacity.max(1) as f64;
+        let ln2 = std::f64::consts::LN_2;
+        let num_bits = (-capacity * false_positive_rate.ln() / (ln2 * ln2))
+            .ceil()
+            .max(64.0) as u64;
+        let num_hashes = ((num_bits as f64 / capacity) * ln2).round().max(1.0) as u32;
+
+        Self {
+            bits: vec![0; num_bits.div_ceil(64) as usize],
+            num_bits,
+            num_hashes,
+        }
+    }
+
+    pub fn insert(&mut self, name: &str) {
+        for bit in self.bit_indexes(name) {
+            self.bits[(bit / 64) as usize] |= 1 << (bit % 64);
+        }
+    }
+
+    /// Returns false only if the name was definitely never inserted
+    pub fn might_contain(&self, name: &str) -> bool {
+        self.bit_indexes(name)
+            .all(|bit| self.bits[(bit / 64) as usize] & (1 << (bit % 64)) != 0)
+    }
+
+    // Double hashing: the i-th index is h1 + i * h2
+    fn bit_indexes(&self, name: &str) -> impl Iterator<Item = u64> + use<> {
+        let h1 = hash_with_seed(name, 0);
+        let h2 = hash_with_seed(name, 1) | 1;
+        let num_bits = self.num_bits;
+        (0..self.num_hashes as u64).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % num_bits)
+    }
+}
+
+fn hash_with_seed(name: &str, seed: u64) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    seed.hash(&mut hasher);
+    name.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bloom::BloomFilter;
+
+    #[test]
+    fn test_no_false_negatives() {
+        let mut filter = BloomFilter::new(1_000, 0.01);
+        let names: Vec<String> = (0..1_000).map(|i| format!("name{}.miden", i)).collect();
+        for name in &names {
+            filter.insert(name);
+        }
+
+        assert!(names.iter().all(|name| filter.might_contain(name)));
+    }
+
+    #[test]
+    fn test_false_positive_rate() {
+        let mut filter = BloomFilter::new(1_000, 0.01);
+        for i in 0..1_000 {
+            filter.insert(&format!("name{}.miden", i));
+        }
+
+        let false_positives = (0..10_000)
+            .filter(|i| filter.might_contain(&format!("other{}.miden", i)))
+            .count();
+        // allow generous slack over the configured 1%
+        assert!(false_positives < 300, "{} false positives", false_positives);
+    }
+}
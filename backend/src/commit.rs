@@ -0,0 +1,115 @@
+use sha2::{Digest, Sha256};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::error::{AppError, Result};
+
+/// Commitment to register `name`, the hex SHA-256 of `<name>:<secret>`.
+///
+/// Clients post it to `/commit` and later reveal the name and secret to `/register`, so a
+/// name waiting to be registered is never visible to anyone who could take it first.
+pub fn commitment(name: &str, secret: &str) -> String {
+    let digest = Sha256::digest(format!("{}:{}", name, secret).as_bytes());
+    digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Normalize a commitment sent by a client, which must be 32 hex-encoded bytes
+pub fn parse_commitment(commitment: &str) -> Result<String> {
+    let commitment = commitment.strip_prefix("0x").unwrap_or(commitment);
+    if commitment.len() != 64 || !commitment.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(AppError::BadRequest(
+            "Commitment must be a hex-encoded SHA-256 digest".to_string(),
+        ));
+    }
+    Ok(commitment.to_ascii_lowercase())
+}
+
+/// Seconds since the Unix epoch, the clock commitments are timed with
+pub fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// When a commitment may be revealed, and whether registering needs one at all
+#[derive(Clone, Copy, Debug)]
+pub struct CommitPolicy {
+    /// Time a commitment must wait before it can be revealed
+    pub min_age: Duration,
+    /// Time after which an unrevealed commitment expires
+    pub max_age: Duration,
+    /// Refuse registrations that do not reveal a commitment
+    pub required: bool,
+}
+
+impl Default for CommitPolicy {
+    fn default() -> Self {
+        Self {
+            min_age: Duration::from_secs(60),
+            max_age: Duration::from_secs(24 * 60 * 60),
+            required: false,
+        }
+    }
+}
+
+impl CommitPolicy {
+    /// Earliest time a commitment made at `created_at` can be revealed
+    pub fn reveal_after(&self, created_at: u64) -> u64 {
+        created_at + self.min_age.as_secs()
+    }
+
+    /// Time a commitment made at `created_at` expires
+    pub fn expires_at(&self, created_at: u64) -> u64 {
+        created_at + self.max_age.as_secs()
+    }
+
+    /// Check that a commitment made at `created_at` can be revealed at `now`
+    pub fn check_reveal(&self, created_at: u64, now: u64) -> Result<()> {
+        let reveal_after = self.reveal_after(created_at);
+        if now < reveal_after {
+            return Err(AppError::BadRequest(format!(
+                "Commitment cannot be revealed for another {} seconds",
+                reveal_after - now
+            )));
+        }
+        if now > self.expires_at(created_at) {
+            return Err(AppError::BadRequest(
+                "Commitment has expired, commit again".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::commit::{CommitPolicy, commitment, parse_commitment};
+
+    #[test]
+    fn test_commitment() {
+        let hash = commitment("alice.miden", "hunter2");
+        assert_eq!(parse_commitment(&hash).unwrap(), hash);
+        assert_eq!(
+            parse_commitment(&format!("0x{}", hash.to_ascii_uppercase())).unwrap(),
+            hash
+        );
+        assert_ne!(commitment("alice.miden", "hunter3"), hash);
+        assert!(parse_commitment("abc").is_err());
+    }
+
+    #[test]
+    fn test_check_reveal() {
+        let policy = CommitPolicy {
+            min_age: Duration::from_secs(60),
+            max_age: Duration::from_secs(600),
+            required: false,
+        };
+
+        assert!(policy.check_reveal(1_000, 1_059).is_err());
+        assert!(policy.check_reveal(1_000, 1_060).is_ok());
+        assert!(policy.check_reveal(1_000, 1_600).is_ok());
+        assert!(policy.check_reveal(1_000, 1_601).is_err());
+    }
+}
@@ -1,10 +1,162 @@
 use log::{error, info};
-use rusqlite::{Connection, Error as SqliteError, Result as SqliteResult, params};
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+use rusqlite::{
+    Connection, Error as SqliteError, OptionalExtension, Result as SqliteResult, Transaction, ffi,
+    params,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::path::Path;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc;
 
 use crate::error::{AppError, Result};
-use crate::handler::User;
+use crate::handler::{
+    HistoryEntry, ResolverRecord, TransactionRecord, User, record_type_for_version,
+};
+use crate::serde::RecordVersion;
+
+/// A full `users` row, with the columns API responses leave out, as backups carry it
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct UserRow {
+    #[serde(flatten)]
+    pub user: User,
+    /// Name a subname was registered under
+    #[serde(default)]
+    pub parent: Option<String>,
+    /// Hash of the owner token, see [`Database::owner_token_hash`]
+    #[serde(default)]
+    pub owner_token_hash: Option<String>,
+}
+
+/// How long a connection waits on a locked database by default
+pub const DEFAULT_BUSY_TIMEOUT: Duration = Duration::from_millis(5_000);
+
+/// Ordered schema migrations, entry `i` upgrades the schema from version `i` to `i + 1`.
+///
+/// Never edit an entry once released, append a new one instead. The first step uses
+/// `IF NOT EXISTS` so databases created before versioning was introduced adopt it as-is.
+const MIGRATIONS: &[&str] = &[
+    // 1: users, resolver records and registration transactions
+    "CREATE TABLE IF NOT EXISTS users (
+        id INTEGER PRIMARY KEY,
+        name TEXT NOT NULL UNIQUE,
+        address TEXT NOT NULL,
+        version TEXT NOT NULL,
+        created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+        updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+    );
+    CREATE INDEX IF NOT EXISTS idx_users_name ON users (name);
+    CREATE TABLE IF NOT EXISTS records (
+        id INTEGER PRIMARY KEY,
+        name TEXT NOT NULL,
+        type TEXT NOT NULL,
+        value TEXT NOT NULL,
+        created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+        updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+        UNIQUE (name, type)
+    );
+    CREATE TABLE IF NOT EXISTS transactions (
+        id INTEGER PRIMARY KEY,
+        name TEXT NOT NULL,
+        transaction_id TEXT NOT NULL,
+        created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+    );",
+    // 2: audit trail of every change to a name, written with the change itself
+    "CREATE TABLE name_history (
+        id INTEGER PRIMARY KEY,
+        name TEXT NOT NULL,
+        old_address TEXT,
+        new_address TEXT,
+        action TEXT NOT NULL,
+        at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+    );
+    CREATE INDEX idx_name_history_name ON name_history (name, id);",
+    // 3: subnames point at the name they were registered under
+    "ALTER TABLE users ADD COLUMN parent TEXT;
+    CREATE INDEX idx_users_parent ON users (parent);",
+    // 4: commit-reveal registrations, timed in Unix seconds
+    "CREATE TABLE commits (
+        commitment TEXT PRIMARY KEY,
+        created_at INTEGER NOT NULL
+    );
+    CREATE INDEX idx_commits_created_at ON commits (created_at);",
+    // 5: addresses on other chains, keyed by coin identifier such as `btc`
+    "CREATE TABLE coin_addresses (
+        name TEXT NOT NULL,
+        coin TEXT NOT NULL,
+        address TEXT NOT NULL,
+        updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+        PRIMARY KEY (name, coin)
+    );",
+    // 6: hash of the owner token authorizing changes to a name, unset for older rows
+    "ALTER TABLE users ADD COLUMN owner_token_hash TEXT;",
+];
+
+/// A `UNIQUE` or primary key constraint rejected the write
+fn is_unique_violation(e: &SqliteError) -> bool {
+    matches!(
+        e,
+        SqliteError::SqliteFailure(failure, _)
+            if failure.extended_code == ffi::SQLITE_CONSTRAINT_UNIQUE
+                || failure.extended_code == ffi::SQLITE_CONSTRAINT_PRIMARYKEY
+    )
+}
+
+// Lets statements inside `with_transaction` use `?`, callers refine the message
+impl From<SqliteError> for AppError {
+    fn from(e: SqliteError) -> Self {
+        if is_unique_violation(&e) {
+            AppError::Conflict(e.to_string())
+        } else {
+            AppError::Database(e.to_string())
+        }
+    }
+}
+
+// Versions are stored as their wire strings, so existing rows read back unchanged
+impl ToSql for RecordVersion {
+    fn to_sql(&self) -> SqliteResult<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.as_str()))
+    }
+}
+
+impl FromSql for RecordVersion {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        value
+            .as_str()?
+            .parse()
+            .map_err(|e: AppError| FromSqlError::Other(Box::new(e)))
+    }
+}
+
+// Insert or replace the record of one type on a name
+fn upsert_record(tx: &Transaction<'_>, name: &str, record_type: &str, value: &str) -> Result<()> {
+    tx.execute(
+        "INSERT INTO records (name, type, value) VALUES (?1, ?2, ?3)
+         ON CONFLICT (name, type)
+         DO UPDATE SET value = excluded.value, updated_at = CURRENT_TIMESTAMP",
+        params![name, record_type, value],
+    )?;
+    Ok(())
+}
+
+// Append a change of `name` to its history, inside the transaction making the change
+fn record_history(
+    tx: &Transaction<'_>,
+    name: &str,
+    old_address: Option<&str>,
+    new_address: Option<&str>,
+    action: &str,
+) -> Result<()> {
+    tx.execute(
+        "INSERT INTO name_history (name, old_address, new_address, action)
+         VALUES (?1, ?2, ?3, ?4)",
+        params![name, old_address, new_address, action],
+    )?;
+    Ok(())
+}
 
 /// Database manager for handling SQLite operations
 pub struct Database {
@@ -12,8 +164,16 @@ pub struct Database {
 }
 
 impl Database {
-    /// Initialize a new database connection and create tables if they don't exist
+    /// Initialize a new database connection and create tables if they don't exist.
+    ///
+    /// Passing `:memory:` as the path opens an isolated in-memory database.
     pub fn new(db_path: impl AsRef<Path>) -> Result<Self> {
+        Self::with_busy_timeout(db_path, DEFAULT_BUSY_TIMEOUT)
+    }
+
+    /// Like [`Database::new`], waiting up to `busy_timeout` on a locked database before
+    /// failing with `SQLITE_BUSY`.
+    pub fn with_busy_timeout(db_path: impl AsRef<Path>, busy_timeout: Duration) -> Result<Self> {
         let conn = match Connection::open(db_path) {
             Ok(conn) => conn,
             Err(e) => {
@@ -25,6 +185,14 @@ impl Database {
             }
         };
 
+        if let Err(e) = Self::configure(&conn, busy_timeout) {
+            error!("Failed to configure database connection: {}", e);
+            return Err(AppError::Database(format!(
+                "Failed to configure database: {}",
+                e
+            )));
+        }
+
         // Initialize the database with our schema
         match Self::init_db(&conn) {
             Ok(_) => {
@@ -43,32 +211,57 @@ impl Database {
         }
     }
 
-    /// Create the necessary tables if they don't exist
+    /// WAL lets readers proceed while a write is in progress, and the busy timeout
+    /// retries transient locks instead of failing immediately
+    fn configure(conn: &Connection, busy_timeout: Duration) -> SqliteResult<()> {
+        conn.busy_timeout(busy_timeout)?;
+        // in-memory databases report "memory" and keep their journal mode
+        let mode: String =
+            conn.pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get(0))?;
+        info!("Database journal mode: {}", mode);
+        Ok(())
+    }
+
+    /// Bring the schema up to date by applying every pending migration in order
     fn init_db(conn: &Connection) -> SqliteResult<()> {
         conn.execute(
-            "CREATE TABLE IF NOT EXISTS users (
-                id INTEGER PRIMARY KEY,
-                name TEXT NOT NULL UNIQUE,
-                address TEXT NOT NULL,
-                version TEXT NOT NULL,
-                created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
-                updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+            "CREATE TABLE IF NOT EXISTS schema_version (
+                version INTEGER NOT NULL,
+                applied_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
             )",
             [],
         )?;
 
-        // Create an index on the name for faster lookups
-        conn.execute(
-            "CREATE INDEX IF NOT EXISTS idx_users_name ON users (name)",
+        let current: usize = conn.query_row(
+            "SELECT COALESCE(MAX(version), 0) FROM schema_version",
             [],
+            |row| row.get(0),
         )?;
 
+        for (index, migration) in MIGRATIONS.iter().enumerate().skip(current) {
+            let version = index + 1;
+            // each step and its version bump commit together or not at all
+            let tx = conn.unchecked_transaction()?;
+            tx.execute_batch(migration)?;
+            tx.execute(
+                "INSERT INTO schema_version (version) VALUES (?1)",
+                params![version],
+            )?;
+            tx.commit()?;
+            info!("Applied database migration {}", version);
+        }
+
         Ok(())
     }
 
-    /// Insert a new user or update an existing one
-    pub fn insert_user(&self, user: &User) -> Result<()> {
-        let conn = match self.conn.lock() {
+    /// Run `f` in a transaction on the shared connection, committing if it returns `Ok`.
+    ///
+    /// An error from `f` or from the commit rolls back every statement `f` executed.
+    pub fn with_transaction<F, T>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&Transaction<'_>) -> Result<T>,
+    {
+        let mut conn = match self.conn.lock() {
             Ok(conn) => conn,
             Err(e) => {
                 error!("Failed to acquire database lock: {}", e);
@@ -78,16 +271,52 @@ impl Database {
             }
         };
 
-        // Using INSERT OR REPLACE to handle both new insertions and updates
-        match conn.execute(
-            "INSERT OR REPLACE INTO users (name, address, version, updated_at) 
-             VALUES (?1, ?2, ?3, CURRENT_TIMESTAMP)",
-            params![user.name, user.address, user.version],
-        ) {
-            Ok(_) => {
+        // dropping the transaction without committing rolls it back
+        let tx = conn.transaction()?;
+        let value = f(&tx)?;
+        tx.commit()?;
+        Ok(value)
+    }
+
+    /// Insert a new user and its primary record, failing with `Conflict` if the name is
+    /// taken.
+    ///
+    /// The `UNIQUE` constraint on the name makes this the authoritative duplicate check,
+    /// earlier lookups can race with a concurrent registration.
+    pub fn insert_user(&self, user: &User) -> Result<()> {
+        self.insert_owned_user(user, None)
+    }
+
+    /// Insert a new user like [`Self::insert_user`], along with the hash of the owner
+    /// token that authorizes later changes to it
+    pub fn insert_owned_user(&self, user: &User, owner_token_hash: Option<&str>) -> Result<()> {
+        let result = self.with_transaction(|tx| {
+            tx.execute(
+                "INSERT INTO users (name, address, version, owner_token_hash, updated_at)
+                 VALUES (?1, ?2, ?3, ?4, CURRENT_TIMESTAMP)",
+                params![user.name, user.address, user.version, owner_token_hash],
+            )?;
+            upsert_record(
+                tx,
+                &user.name,
+                record_type_for_version(user.version),
+                &user.address,
+            )?;
+            record_history(tx, &user.name, None, Some(&user.address), "register")
+        });
+
+        match result {
+            Ok(()) => {
                 info!("User '{}' stored in database", user.name);
                 Ok(())
             }
+            Err(AppError::Conflict(_)) => {
+                info!("User '{}' already exists in database", user.name);
+                Err(AppError::Conflict(format!(
+                    "Name '{}' has already been registered",
+                    user.name
+                )))
+            }
             Err(e) => {
                 error!("Database error when saving user '{}': {}", user.name, e);
                 Err(AppError::Database(format!("Failed to save user: {}", e)))
@@ -95,6 +324,295 @@ impl Database {
         }
     }
 
+    /// Change the address of an existing user and its primary record in place, keeping
+    /// its `created_at`.
+    ///
+    /// Returns `false` when no user has this name.
+    pub fn update_address(&self, name: &str, address: &str) -> Result<bool> {
+        let result = self.with_transaction(|tx| {
+            let current: Option<(String, RecordVersion)> = tx
+                .query_row(
+                    "SELECT address, version FROM users WHERE name = ?1",
+                    params![name],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )
+                .optional()?;
+            let Some((old_address, version)) = current else {
+                return Ok(false);
+            };
+
+            tx.execute(
+                "UPDATE users SET address = ?1, updated_at = CURRENT_TIMESTAMP WHERE name = ?2",
+                params![address, name],
+            )?;
+            upsert_record(tx, name, record_type_for_version(version), address)?;
+            record_history(tx, name, Some(&old_address), Some(address), "update")?;
+            Ok(true)
+        });
+
+        match result {
+            Ok(updated) => {
+                info!("Address of '{}' updated in database", name);
+                Ok(updated)
+            }
+            Err(e) => {
+                error!("Database error when updating user '{}': {}", name, e);
+                Err(AppError::Database(format!("Failed to update user: {}", e)))
+            }
+        }
+    }
+
+    /// Insert `user` as a subname of `parent`, in the same transaction that checks the
+    /// caller controls the parent.
+    ///
+    /// Control is shown by the hash of the parent's owner token, the subname gets its own
+    /// `owner_token_hash`. Fails with `NotFound` when the parent is not registered and with
+    /// `Forbidden` when the parent has no owner token or a different one.
+    pub fn insert_subname(
+        &self,
+        user: &User,
+        parent: &str,
+        parent_token_hash: &str,
+        owner_token_hash: &str,
+    ) -> Result<()> {
+        let result = self.with_transaction(|tx| {
+            let current: Option<Option<String>> = tx
+                .query_row(
+                    "SELECT owner_token_hash FROM users WHERE name = ?1",
+                    params![parent],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            match current {
+                None => {
+                    return Err(AppError::NotFound(format!(
+                        "Parent name '{}' is not registered",
+                        parent
+                    )));
+                }
+                Some(Some(hash)) if hash == parent_token_hash => {}
+                Some(_) => {
+                    return Err(AppError::Forbidden(format!(
+                        "Only the owner of '{}' can register names under it",
+                        parent
+                    )));
+                }
+            }
+
+            tx.execute(
+                "INSERT INTO users (name, address, version, parent, owner_token_hash, updated_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, CURRENT_TIMESTAMP)",
+                params![
+                    user.name,
+                    user.address,
+                    user.version,
+                    parent,
+                    owner_token_hash
+                ],
+            )?;
+            upsert_record(
+                tx,
+                &user.name,
+                record_type_for_version(user.version),
+                &user.address,
+            )?;
+            record_history(tx, &user.name, None, Some(&user.address), "register")
+        });
+
+        match result {
+            Ok(()) => {
+                info!("Subname '{}' of '{}' stored in database", user.name, parent);
+                Ok(())
+            }
+            Err(AppError::Conflict(_)) => Err(AppError::Conflict(format!(
+                "Name '{}' has already been registered",
+                user.name
+            ))),
+            Err(e @ (AppError::NotFound(_) | AppError::Forbidden(_))) => Err(e),
+            Err(e) => {
+                error!("Database error when saving subname '{}': {}", user.name, e);
+                Err(AppError::Database(format!("Failed to save user: {}", e)))
+            }
+        }
+    }
+
+    /// Store a registration commitment made at `created_at`, dropping the ones made before
+    /// `expired_before` on the way.
+    ///
+    /// Fails with `Conflict` if the same commitment is already waiting to be revealed.
+    pub fn insert_commit(
+        &self,
+        commitment: &str,
+        created_at: u64,
+        expired_before: u64,
+    ) -> Result<()> {
+        let result = self.with_transaction(|tx| {
+            tx.execute(
+                "DELETE FROM commits WHERE created_at < ?1",
+                params![expired_before],
+            )?;
+            tx.execute(
+                "INSERT INTO commits (commitment, created_at) VALUES (?1, ?2)",
+                params![commitment, created_at],
+            )?;
+            Ok(())
+        });
+
+        match result {
+            Ok(()) => Ok(()),
+            Err(AppError::Conflict(_)) => Err(AppError::Conflict(
+                "This commitment has already been made".to_string(),
+            )),
+            Err(e) => {
+                error!("Database error when saving commitment: {}", e);
+                Err(AppError::Database(format!(
+                    "Failed to save commitment: {}",
+                    e
+                )))
+            }
+        }
+    }
+
+    /// Remove a commitment so it is revealed at most once, if `check` accepts its
+    /// creation time, and return that time.
+    ///
+    /// Fails with `NotFound` for an unknown commitment, errors from `check` are passed
+    /// through and leave the commitment in place.
+    pub fn take_commit(
+        &self,
+        commitment: &str,
+        check: impl FnOnce(u64) -> Result<()>,
+    ) -> Result<u64> {
+        let result = self.with_transaction(|tx| {
+            let created_at: Option<u64> = tx
+                .query_row(
+                    "SELECT created_at FROM commits WHERE commitment = ?1",
+                    params![commitment],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            let Some(created_at) = created_at else {
+                return Err(AppError::NotFound(
+                    "No commitment matches this name and secret".to_string(),
+                ));
+            };
+            check(created_at)?;
+
+            tx.execute(
+                "DELETE FROM commits WHERE commitment = ?1",
+                params![commitment],
+            )?;
+            Ok(created_at)
+        });
+
+        match result {
+            Ok(created_at) => Ok(created_at),
+            Err(e @ (AppError::NotFound(_) | AppError::BadRequest(_))) => Err(e),
+            Err(e) => {
+                error!("Database error when revealing commitment: {}", e);
+                Err(AppError::Database(format!(
+                    "Failed to reveal commitment: {}",
+                    e
+                )))
+            }
+        }
+    }
+
+    /// Insert several new users atomically in a single transaction
+    pub fn insert_users(&self, users: &[User]) -> Result<()> {
+        self.insert_rows(users.len(), users.iter().map(|user| (user, None, None)))
+    }
+
+    /// Insert backed up rows atomically in a single transaction, keeping their parent and
+    /// owner token hash
+    pub fn restore_users(&self, rows: &[UserRow]) -> Result<()> {
+        self.insert_rows(
+            rows.len(),
+            rows.iter().map(|row| {
+                (
+                    &row.user,
+                    row.parent.as_deref(),
+                    row.owner_token_hash.as_deref(),
+                )
+            }),
+        )
+    }
+
+    // Insert `count` users, each with its parent and owner token hash
+    fn insert_rows<'a>(
+        &self,
+        count: usize,
+        rows: impl Iterator<Item = (&'a User, Option<&'a str>, Option<&'a str>)>,
+    ) -> Result<()> {
+        let result = self.with_transaction(|tx| {
+            let mut stmt = tx.prepare(
+                "INSERT INTO users (name, address, version, parent, owner_token_hash, updated_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, CURRENT_TIMESTAMP)",
+            )?;
+            for (user, parent, owner_token_hash) in rows {
+                stmt.execute(params![
+                    user.name,
+                    user.address,
+                    user.version,
+                    parent,
+                    owner_token_hash
+                ])?;
+                upsert_record(
+                    tx,
+                    &user.name,
+                    record_type_for_version(user.version),
+                    &user.address,
+                )?;
+                record_history(tx, &user.name, None, Some(&user.address), "register")?;
+            }
+            Ok(())
+        });
+
+        match result {
+            Ok(()) => {
+                info!("{} users stored in database", count);
+                Ok(())
+            }
+            Err(AppError::Conflict(_)) => {
+                info!("Bulk insert of {} users hit an existing name", count);
+                Err(AppError::Conflict(
+                    "A name in the batch has already been registered".to_string(),
+                ))
+            }
+            Err(e) => {
+                error!("Database error when saving {} users: {}", count, e);
+                Err(AppError::Database(format!("Failed to save users: {}", e)))
+            }
+        }
+    }
+
+    /// Hash of the owner token of `name`, `None` for unknown names and for names
+    /// registered without one
+    pub fn owner_token_hash(&self, name: &str) -> Result<Option<String>> {
+        let conn = match self.conn.lock() {
+            Ok(conn) => conn,
+            Err(e) => {
+                error!("Failed to acquire database lock: {}", e);
+                return Err(AppError::Database(
+                    "Failed to acquire database lock".to_string(),
+                ));
+            }
+        };
+
+        let hash: Option<Option<String>> = conn
+            .query_row(
+                "SELECT owner_token_hash FROM users WHERE name = ?1",
+                params![name],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(|e| {
+                error!("Database error when reading owner of '{}': {}", name, e);
+                AppError::Database(format!("Error reading owner: {}", e))
+            })?;
+        Ok(hash.flatten())
+    }
+
     /// Lookup a user by name
     pub fn lookup_user(&self, name: &str) -> Result<Option<User>> {
         let conn = match self.conn.lock() {
@@ -142,4 +660,952 @@ impl Database {
             }
         }
     }
+
+    /// Insert a resolver record, replacing any existing record of the same type
+    pub fn insert_record(&self, name: &str, record_type: &str, value: &str) -> Result<()> {
+        let conn = match self.conn.lock() {
+            Ok(conn) => conn,
+            Err(e) => {
+                error!("Failed to acquire database lock: {}", e);
+                return Err(AppError::Database(
+                    "Failed to acquire database lock".to_string(),
+                ));
+            }
+        };
+
+        match conn.execute(
+            "INSERT INTO records (name, type, value) VALUES (?1, ?2, ?3)
+             ON CONFLICT (name, type)
+             DO UPDATE SET value = excluded.value, updated_at = CURRENT_TIMESTAMP",
+            params![name, record_type, value],
+        ) {
+            Ok(_) => {
+                info!("Record '{}' for '{}' stored in database", record_type, name);
+                Ok(())
+            }
+            Err(e) => {
+                error!(
+                    "Database error when saving record '{}' for '{}': {}",
+                    record_type, name, e
+                );
+                Err(AppError::Database(format!("Failed to save record: {}", e)))
+            }
+        }
+    }
+
+    /// Set the address of a name on another chain, replacing any earlier one for `coin`
+    pub fn set_coin_address(&self, name: &str, coin: &str, address: &str) -> Result<()> {
+        let conn = match self.conn.lock() {
+            Ok(conn) => conn,
+            Err(e) => {
+                error!("Failed to acquire database lock: {}", e);
+                return Err(AppError::Database(
+                    "Failed to acquire database lock".to_string(),
+                ));
+            }
+        };
+
+        match conn.execute(
+            "INSERT INTO coin_addresses (name, coin, address) VALUES (?1, ?2, ?3)
+             ON CONFLICT (name, coin)
+             DO UPDATE SET address = excluded.address, updated_at = CURRENT_TIMESTAMP",
+            params![name, coin, address],
+        ) {
+            Ok(_) => {
+                info!("{} address for '{}' stored in database", coin, name);
+                Ok(())
+            }
+            Err(e) => {
+                error!(
+                    "Database error when saving {} address for '{}': {}",
+                    coin, name, e
+                );
+                Err(AppError::Database(format!(
+                    "Failed to save coin address: {}",
+                    e
+                )))
+            }
+        }
+    }
+
+    /// Address of a name on the chain identified by `coin`, if one was set
+    pub fn coin_address(&self, name: &str, coin: &str) -> Result<Option<String>> {
+        let conn = match self.conn.lock() {
+            Ok(conn) => conn,
+            Err(e) => {
+                error!("Failed to acquire database lock: {}", e);
+                return Err(AppError::Database(
+                    "Failed to acquire database lock".to_string(),
+                ));
+            }
+        };
+
+        conn.query_row(
+            "SELECT address FROM coin_addresses WHERE name = ?1 AND coin = ?2",
+            params![name, coin],
+            |row| row.get(0),
+        )
+        .optional()
+        .map_err(|e| {
+            error!(
+                "Database error when reading {} address of '{}': {}",
+                coin, name, e
+            );
+            AppError::Database(format!("Error reading coin address: {}", e))
+        })
+    }
+
+    /// All resolver records of a name, in insertion order
+    pub fn lookup_records(&self, name: &str) -> Result<Vec<ResolverRecord>> {
+        let conn = match self.conn.lock() {
+            Ok(conn) => conn,
+            Err(e) => {
+                error!("Failed to acquire database lock: {}", e);
+                return Err(AppError::Database(
+                    "Failed to acquire database lock".to_string(),
+                ));
+            }
+        };
+
+        let mut stmt =
+            match conn.prepare("SELECT type, value FROM records WHERE name = ?1 ORDER BY id") {
+                Ok(stmt) => stmt,
+                Err(e) => {
+                    error!("Failed to prepare statement: {}", e);
+                    return Err(AppError::Database(format!(
+                        "Query preparation failed: {}",
+                        e
+                    )));
+                }
+            };
+
+        let rows = stmt.query_map(params![name], |row| {
+            Ok(ResolverRecord {
+                record_type: row.get(0)?,
+                value: row.get(1)?,
+            })
+        });
+
+        match rows.and_then(|rows| rows.collect::<SqliteResult<Vec<_>>>()) {
+            Ok(records) => Ok(records),
+            Err(e) => {
+                error!(
+                    "Database error when looking up records of '{}': {}",
+                    name, e
+                );
+                Err(AppError::Database(format!(
+                    "Error looking up records: {}",
+                    e
+                )))
+            }
+        }
+    }
+
+    /// Search registered names starting with the given prefix, in alphabetical order
+    pub fn search_users(&self, prefix: &str, limit: u32) -> Result<Vec<String>> {
+        let conn = match self.conn.lock() {
+            Ok(conn) => conn,
+            Err(e) => {
+                error!("Failed to acquire database lock: {}", e);
+                return Err(AppError::Database(
+                    "Failed to acquire database lock".to_string(),
+                ));
+            }
+        };
+
+        // Escape LIKE wildcards so user input only ever matches literally
+        let escaped = prefix
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_");
+
+        let mut stmt = match conn.prepare(
+            "SELECT name FROM users WHERE name LIKE ?1 || '%' ESCAPE '\\'
+             ORDER BY name LIMIT ?2",
+        ) {
+            Ok(stmt) => stmt,
+            Err(e) => {
+                error!("Failed to prepare statement: {}", e);
+                return Err(AppError::Database(format!(
+                    "Query preparation failed: {}",
+                    e
+                )));
+            }
+        };
+
+        let rows = stmt.query_map(params![escaped, limit], |row| row.get(0));
+
+        match rows.and_then(|rows| rows.collect::<SqliteResult<Vec<String>>>()) {
+            Ok(names) => Ok(names),
+            Err(e) => {
+                error!("Database error when searching for '{}': {}", prefix, e);
+                Err(AppError::Database(format!("Error searching users: {}", e)))
+            }
+        }
+    }
+
+    /// Names registered in the database to `address`, in alphabetical order
+    pub fn names_for_address(&self, address: &str) -> Result<Vec<String>> {
+        let conn = match self.conn.lock() {
+            Ok(conn) => conn,
+            Err(e) => {
+                error!("Failed to acquire database lock: {}", e);
+                return Err(AppError::Database(
+                    "Failed to acquire database lock".to_string(),
+                ));
+            }
+        };
+
+        let mut stmt = match conn.prepare("SELECT name FROM users WHERE address = ?1 ORDER BY name")
+        {
+            Ok(stmt) => stmt,
+            Err(e) => {
+                error!("Failed to prepare statement: {}", e);
+                return Err(AppError::Database(format!(
+                    "Query preparation failed: {}",
+                    e
+                )));
+            }
+        };
+
+        let rows = stmt.query_map(params![address], |row| row.get(0));
+
+        match rows.and_then(|rows| rows.collect::<SqliteResult<Vec<String>>>()) {
+            Ok(names) => Ok(names),
+            Err(e) => {
+                error!("Database error when reversing '{}': {}", address, e);
+                Err(AppError::Database(format!(
+                    "Error reversing address: {}",
+                    e
+                )))
+            }
+        }
+    }
+
+    /// All names known to this server: database users plus names registered on-chain
+    pub fn registered_names(&self) -> Result<Vec<String>> {
+        let conn = match self.conn.lock() {
+            Ok(conn) => conn,
+            Err(e) => {
+                error!("Failed to acquire database lock: {}", e);
+                return Err(AppError::Database(
+                    "Failed to acquire database lock".to_string(),
+                ));
+            }
+        };
+
+        let mut stmt =
+            match conn.prepare("SELECT name FROM users UNION SELECT name FROM transactions") {
+                Ok(stmt) => stmt,
+                Err(e) => {
+                    error!("Failed to prepare statement: {}", e);
+                    return Err(AppError::Database(format!(
+                        "Query preparation failed: {}",
+                        e
+                    )));
+                }
+            };
+
+        let rows = stmt.query_map([], |row| row.get(0));
+
+        match rows.and_then(|rows| rows.collect::<SqliteResult<Vec<String>>>()) {
+            Ok(names) => Ok(names),
+            Err(e) => {
+                error!("Database error when listing names: {}", e);
+                Err(AppError::Database(format!("Error listing names: {}", e)))
+            }
+        }
+    }
+
+    /// Visit every user ordered by id, one row at a time, returning how many were visited.
+    ///
+    /// Rows are streamed from the query so large tables are never loaded at once, the
+    /// database lock is held until the walk ends or `visit` fails.
+    pub fn iter_all_users(&self, mut visit: impl FnMut(UserRow) -> Result<()>) -> Result<u64> {
+        let conn = match self.conn.lock() {
+            Ok(conn) => conn,
+            Err(e) => {
+                error!("Failed to acquire database lock: {}", e);
+                return Err(AppError::Database(
+                    "Failed to acquire database lock".to_string(),
+                ));
+            }
+        };
+
+        let mut stmt = match conn.prepare(
+            "SELECT name, address, version, parent, owner_token_hash FROM users ORDER BY id",
+        ) {
+            Ok(stmt) => stmt,
+            Err(e) => {
+                error!("Failed to prepare statement: {}", e);
+                return Err(AppError::Database(format!(
+                    "Query preparation failed: {}",
+                    e
+                )));
+            }
+        };
+
+        let rows = match stmt.query_map([], |row| {
+            Ok(UserRow {
+                user: User {
+                    name: row.get(0)?,
+                    address: row.get(1)?,
+                    version: row.get(2)?,
+                },
+                parent: row.get(3)?,
+                owner_token_hash: row.get(4)?,
+            })
+        }) {
+            Ok(rows) => rows,
+            Err(e) => {
+                error!("Database error when listing users: {}", e);
+                return Err(AppError::Database(format!("Error listing users: {}", e)));
+            }
+        };
+
+        let mut visited = 0;
+        for row in rows {
+            match row {
+                Ok(user) => visit(user)?,
+                Err(e) => {
+                    error!("Database error when reading user: {}", e);
+                    return Err(AppError::Database(format!("Error reading user: {}", e)));
+                }
+            }
+            visited += 1;
+        }
+
+        Ok(visited)
+    }
+
+    /// Up to `limit` users with an id above `after_id`, ordered by id, each with its id
+    pub fn users_after(&self, after_id: i64, limit: u32) -> Result<Vec<(i64, User)>> {
+        let conn = match self.conn.lock() {
+            Ok(conn) => conn,
+            Err(e) => {
+                error!("Failed to acquire database lock: {}", e);
+                return Err(AppError::Database(
+                    "Failed to acquire database lock".to_string(),
+                ));
+            }
+        };
+
+        let mut stmt = match conn.prepare(
+            "SELECT id, name, address, version FROM users WHERE id > ?1 ORDER BY id LIMIT ?2",
+        ) {
+            Ok(stmt) => stmt,
+            Err(e) => {
+                error!("Failed to prepare statement: {}", e);
+                return Err(AppError::Database(format!(
+                    "Query preparation failed: {}",
+                    e
+                )));
+            }
+        };
+
+        let rows = stmt.query_map(params![after_id, limit], |row| {
+            Ok((
+                row.get(0)?,
+                User {
+                    name: row.get(1)?,
+                    address: row.get(2)?,
+                    version: row.get(3)?,
+                },
+            ))
+        });
+
+        match rows.and_then(|rows| rows.collect::<SqliteResult<Vec<_>>>()) {
+            Ok(users) => Ok(users),
+            Err(e) => {
+                error!("Database error when listing users: {}", e);
+                Err(AppError::Database(format!("Error listing users: {}", e)))
+            }
+        }
+    }
+
+    /// Stream every user ordered by id from a blocking thread, reading `page_size` rows
+    /// per query.
+    ///
+    /// Unlike [`Database::iter_all_users`] the lock is released between pages, so a slow
+    /// reader does not stall other queries. The walk stops once the receiver is dropped.
+    pub fn stream_users(self: Arc<Self>, page_size: u32) -> mpsc::Receiver<Result<User>> {
+        let page_size = page_size.max(1);
+        let (tx, rx) = mpsc::channel(page_size as usize);
+        tokio::task::spawn_blocking(move || {
+            let mut after_id = 0;
+            loop {
+                let page = match self.users_after(after_id, page_size) {
+                    Ok(page) => page,
+                    Err(e) => {
+                        let _ = tx.blocking_send(Err(e));
+                        return;
+                    }
+                };
+                let last_page = page.len() < page_size as usize;
+                for (id, user) in page {
+                    after_id = id;
+                    if tx.blocking_send(Ok(user)).is_err() {
+                        return;
+                    }
+                }
+                if last_page {
+                    return;
+                }
+            }
+        });
+        rx
+    }
+
+    /// Count all registered users
+    pub fn count_users(&self) -> Result<u64> {
+        let conn = match self.conn.lock() {
+            Ok(conn) => conn,
+            Err(e) => {
+                error!("Failed to acquire database lock: {}", e);
+                return Err(AppError::Database(
+                    "Failed to acquire database lock".to_string(),
+                ));
+            }
+        };
+
+        match conn.query_row("SELECT COUNT(*) FROM users", [], |row| row.get(0)) {
+            Ok(count) => Ok(count),
+            Err(e) => {
+                error!("Database error when counting users: {}", e);
+                Err(AppError::Database(format!("Error counting users: {}", e)))
+            }
+        }
+    }
+
+    /// Count registered users grouped by version
+    pub fn count_by_version(&self) -> Result<BTreeMap<String, u64>> {
+        let conn = match self.conn.lock() {
+            Ok(conn) => conn,
+            Err(e) => {
+                error!("Failed to acquire database lock: {}", e);
+                return Err(AppError::Database(
+                    "Failed to acquire database lock".to_string(),
+                ));
+            }
+        };
+
+        let mut stmt = match conn.prepare("SELECT version, COUNT(*) FROM users GROUP BY version") {
+            Ok(stmt) => stmt,
+            Err(e) => {
+                error!("Failed to prepare statement: {}", e);
+                return Err(AppError::Database(format!(
+                    "Query preparation failed: {}",
+                    e
+                )));
+            }
+        };
+
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)));
+
+        match rows.and_then(|rows| rows.collect::<SqliteResult<BTreeMap<_, _>>>()) {
+            Ok(counts) => Ok(counts),
+            Err(e) => {
+                error!("Database error when counting users by version: {}", e);
+                Err(AppError::Database(format!("Error counting users: {}", e)))
+            }
+        }
+    }
+
+    /// Record a submitted registration transaction
+    pub fn insert_transaction(&self, name: &str, transaction_id: &str) -> Result<()> {
+        let conn = match self.conn.lock() {
+            Ok(conn) => conn,
+            Err(e) => {
+                error!("Failed to acquire database lock: {}", e);
+                return Err(AppError::Database(
+                    "Failed to acquire database lock".to_string(),
+                ));
+            }
+        };
+
+        match conn.execute(
+            "INSERT INTO transactions (name, transaction_id) VALUES (?1, ?2)",
+            params![name, transaction_id],
+        ) {
+            Ok(_) => {
+                info!(
+                    "Transaction '{}' for '{}' stored in database",
+                    transaction_id, name
+                );
+                Ok(())
+            }
+            Err(e) => {
+                error!(
+                    "Database error when saving transaction '{}': {}",
+                    transaction_id, e
+                );
+                Err(AppError::Database(format!(
+                    "Failed to save transaction: {}",
+                    e
+                )))
+            }
+        }
+    }
+
+    /// List the most recent registration transactions, newest first
+    pub fn recent_transactions(&self, limit: u32) -> Result<Vec<TransactionRecord>> {
+        let conn = match self.conn.lock() {
+            Ok(conn) => conn,
+            Err(e) => {
+                error!("Failed to acquire database lock: {}", e);
+                return Err(AppError::Database(
+                    "Failed to acquire database lock".to_string(),
+                ));
+            }
+        };
+
+        let mut stmt = match conn.prepare(
+            "SELECT name, transaction_id, created_at FROM transactions
+             ORDER BY id DESC LIMIT ?1",
+        ) {
+            Ok(stmt) => stmt,
+            Err(e) => {
+                error!("Failed to prepare statement: {}", e);
+                return Err(AppError::Database(format!(
+                    "Query preparation failed: {}",
+                    e
+                )));
+            }
+        };
+
+        let rows = stmt.query_map(params![limit], |row| {
+            Ok(TransactionRecord {
+                name: row.get(0)?,
+                transaction_id: row.get(1)?,
+                created_at: row.get(2)?,
+            })
+        });
+
+        match rows.and_then(|rows| rows.collect::<SqliteResult<Vec<_>>>()) {
+            Ok(transactions) => Ok(transactions),
+            Err(e) => {
+                error!("Database error when listing transactions: {}", e);
+                Err(AppError::Database(format!(
+                    "Error listing transactions: {}",
+                    e
+                )))
+            }
+        }
+    }
+
+    /// Changes to a name, oldest first, skipping the first `offset`
+    pub fn name_history(&self, name: &str, offset: u32, limit: u32) -> Result<Vec<HistoryEntry>> {
+        let conn = match self.conn.lock() {
+            Ok(conn) => conn,
+            Err(e) => {
+                error!("Failed to acquire database lock: {}", e);
+                return Err(AppError::Database(
+                    "Failed to acquire database lock".to_string(),
+                ));
+            }
+        };
+
+        let mut stmt = match conn.prepare(
+            "SELECT old_address, new_address, action, at FROM name_history
+             WHERE name = ?1 ORDER BY id LIMIT ?2 OFFSET ?3",
+        ) {
+            Ok(stmt) => stmt,
+            Err(e) => {
+                error!("Failed to prepare statement: {}", e);
+                return Err(AppError::Database(format!(
+                    "Query preparation failed: {}",
+                    e
+                )));
+            }
+        };
+
+        let rows = stmt.query_map(params![name, limit, offset], |row| {
+            Ok(HistoryEntry {
+                old_address: row.get(0)?,
+                new_address: row.get(1)?,
+                action: row.get(2)?,
+                at: row.get(3)?,
+            })
+        });
+
+        match rows.and_then(|rows| rows.collect::<SqliteResult<Vec<_>>>()) {
+            Ok(entries) => Ok(entries),
+            Err(e) => {
+                error!("Database error when reading history of '{}': {}", name, e);
+                Err(AppError::Database(format!("Error reading history: {}", e)))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+
+    use rusqlite::Connection;
+
+    use crate::db::{DEFAULT_BUSY_TIMEOUT, Database, MIGRATIONS};
+    use crate::error::AppError;
+    use crate::handler::User;
+    use crate::serde::RecordVersion;
+
+    #[test]
+    fn test_in_memory_insert_and_lookup() {
+        let db = Database::new(":memory:").unwrap();
+        let user = User {
+            name: "alice.miden".to_string(),
+            address: "0xabc".to_string(),
+            version: RecordVersion::Web2,
+        };
+
+        db.insert_user(&user).unwrap();
+
+        let found = db.lookup_user("alice.miden").unwrap().unwrap();
+        assert_eq!(found.address, "0xabc");
+        assert_eq!(found.version, RecordVersion::Web2);
+        assert!(db.lookup_user("bob.miden").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_search_users_escapes_wildcards() {
+        let db = Database::new(":memory:").unwrap();
+        for name in ["alice.miden", "alicia.miden", "a_b.miden", "axb.miden"] {
+            db.insert_user(&User {
+                name: name.to_string(),
+                address: "0xabc".to_string(),
+                version: RecordVersion::Web2,
+            })
+            .unwrap();
+        }
+
+        assert_eq!(
+            db.search_users("ali", 10).unwrap(),
+            vec!["alice.miden", "alicia.miden"]
+        );
+        assert_eq!(db.search_users("ali", 1).unwrap(), vec!["alice.miden"]);
+        // `_` must not act as a single-character wildcard
+        assert_eq!(db.search_users("a_", 10).unwrap(), vec!["a_b.miden"]);
+        assert!(db.search_users("%", 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_records_replace_by_type() {
+        let db = Database::new(":memory:").unwrap();
+        db.insert_record("alice.miden", "web2", "https://alice.dev")
+            .unwrap();
+        db.insert_record("alice.miden", "web2.5", "0xabc").unwrap();
+        db.insert_record("alice.miden", "web2", "https://alice.xyz")
+            .unwrap();
+
+        let records = db.lookup_records("alice.miden").unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].record_type, "web2");
+        assert_eq!(records[0].value, "https://alice.xyz");
+        assert_eq!(records[1].value, "0xabc");
+    }
+
+    #[test]
+    fn test_concurrent_insert_and_lookup() {
+        let path =
+            std::env::temp_dir().join(format!("mns-concurrent-{}.sqlite3", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        // separate connections to the same file contend on the SQLite lock
+        let handles: Vec<_> = (0..4)
+            .map(|writer| {
+                let db = Database::with_busy_timeout(&path, DEFAULT_BUSY_TIMEOUT).unwrap();
+                thread::spawn(move || {
+                    for i in 0..50 {
+                        let name = format!("w{}-{}.miden", writer, i);
+                        db.insert_user(&User {
+                            name: name.clone(),
+                            address: "0xabc".to_string(),
+                            version: RecordVersion::Web2,
+                        })
+                        .unwrap();
+                        assert!(db.lookup_user(&name).unwrap().is_some());
+                    }
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let db = Database::new(&path).unwrap();
+        assert_eq!(db.registered_names().unwrap().len(), 200);
+
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+    }
+
+    #[test]
+    fn test_migrations_upgrade_unversioned_database() {
+        let path = std::env::temp_dir().join(format!("mns-migrate-{}.sqlite3", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        // schema as created before migrations existed
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE users (
+                id INTEGER PRIMARY KEY,
+                name TEXT NOT NULL UNIQUE,
+                address TEXT NOT NULL,
+                version TEXT NOT NULL,
+                created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+                updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+            );
+            INSERT INTO users (name, address, version) VALUES ('alice.miden', '0xabc', '2');",
+        )
+        .unwrap();
+        drop(conn);
+
+        let db = Database::new(&path).unwrap();
+        assert!(db.lookup_user("alice.miden").unwrap().is_some());
+        drop(db);
+
+        // reopening applies nothing twice
+        drop(Database::new(&path).unwrap());
+        let conn = Connection::open(&path).unwrap();
+        let (count, version): (usize, usize) = conn
+            .query_row(
+                "SELECT COUNT(*), MAX(version) FROM schema_version",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(count, MIGRATIONS.len());
+        assert_eq!(version, MIGRATIONS.len());
+        drop(conn);
+
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+    }
+
+    #[test]
+    fn test_insert_user_conflicts_on_existing_name() {
+        let db = Arc::new(Database::new(":memory:").unwrap());
+
+        let handles: Vec<_> = (0..2)
+            .map(|i| {
+                let db = db.clone();
+                thread::spawn(move || {
+                    db.insert_user(&User {
+                        name: "alice.miden".to_string(),
+                        address: format!("0x{}", i),
+                        version: RecordVersion::Web2,
+                    })
+                })
+            })
+            .collect();
+
+        let results: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        assert_eq!(results.iter().filter(|r| r.is_ok()).count(), 1);
+        assert!(
+            results
+                .iter()
+                .any(|r| matches!(r, Err(AppError::Conflict(_))))
+        );
+    }
+
+    #[test]
+    fn test_update_address_keeps_created_at() {
+        let db = Database::new(":memory:").unwrap();
+        db.insert_user(&User {
+            name: "alice.miden".to_string(),
+            address: "0xabc".to_string(),
+            version: RecordVersion::Web2,
+        })
+        .unwrap();
+
+        let created_at = |db: &Database| -> String {
+            let conn = db.conn.lock().unwrap();
+            conn.query_row(
+                "SELECT created_at FROM users WHERE name = 'alice.miden'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap()
+        };
+
+        // backdate the row so a re-insert would be visible
+        db.conn
+            .lock()
+            .unwrap()
+            .execute("UPDATE users SET created_at = '2024-01-01 00:00:00'", [])
+            .unwrap();
+
+        assert!(db.update_address("alice.miden", "0xdef").unwrap());
+        assert_eq!(created_at(&db), "2024-01-01 00:00:00");
+        assert_eq!(
+            db.lookup_user("alice.miden").unwrap().unwrap().address,
+            "0xdef"
+        );
+        assert!(!db.update_address("bob.miden", "0xdef").unwrap());
+    }
+
+    #[test]
+    fn test_with_transaction_rolls_back_on_error() {
+        let db = Database::new(":memory:").unwrap();
+        let result: Result<(), AppError> = db.with_transaction(|tx| {
+            tx.execute(
+                "INSERT INTO users (name, address, version) VALUES ('alice.miden', '0xabc', '2')",
+                [],
+            )?;
+            Err(AppError::BadRequest("abort".to_string()))
+        });
+        assert!(matches!(result, Err(AppError::BadRequest(_))));
+        assert!(db.lookup_user("alice.miden").unwrap().is_none());
+
+        // the primary record is written together with the user
+        let user = User {
+            name: "alice.miden".to_string(),
+            address: "0xabc".to_string(),
+            version: RecordVersion::Web2,
+        };
+        db.insert_user(&user).unwrap();
+        db.update_address("alice.miden", "0xdef").unwrap();
+        let records = db.lookup_records("alice.miden").unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].record_type, "web2");
+        assert_eq!(records[0].value, "0xdef");
+    }
+
+    #[test]
+    fn test_name_history() {
+        let db = Database::new(":memory:").unwrap();
+        db.insert_user(&User {
+            name: "alice.miden".to_string(),
+            address: "0xabc".to_string(),
+            version: RecordVersion::Web2,
+        })
+        .unwrap();
+        db.update_address("alice.miden", "0xdef").unwrap();
+        db.update_address("alice.miden", "0x123").unwrap();
+
+        let history = db.name_history("alice.miden", 0, 10).unwrap();
+        let actions: Vec<_> = history.iter().map(|entry| entry.action.as_str()).collect();
+        assert_eq!(actions, ["register", "update", "update"]);
+        assert_eq!(history[0].old_address, None);
+        assert_eq!(history[1].old_address.as_deref(), Some("0xabc"));
+        assert_eq!(history[2].new_address.as_deref(), Some("0x123"));
+
+        let page = db.name_history("alice.miden", 1, 1).unwrap();
+        assert_eq!(page.len(), 1);
+        assert_eq!(page[0].new_address.as_deref(), Some("0xdef"));
+        assert!(db.name_history("bob.miden", 0, 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_names_for_address() {
+        let db = Database::new(":memory:").unwrap();
+        for (name, address) in [
+            ("bob.miden", "0xabc"),
+            ("alice.miden", "0xabc"),
+            ("carol.miden", "0xdef"),
+        ] {
+            db.insert_user(&User {
+                name: name.to_string(),
+                address: address.to_string(),
+                version: RecordVersion::Web2,
+            })
+            .unwrap();
+        }
+
+        assert_eq!(
+            db.names_for_address("0xabc").unwrap(),
+            vec!["alice.miden", "bob.miden"]
+        );
+        assert!(db.names_for_address("0x123").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_commit_is_taken_once() {
+        let db = Database::new(":memory:").unwrap();
+        db.insert_commit("c1", 1_000, 0).unwrap();
+        assert!(matches!(
+            db.insert_commit("c1", 1_001, 0),
+            Err(AppError::Conflict(_))
+        ));
+
+        // a rejected reveal keeps the commitment
+        let early = db.take_commit("c1", |_| Err(AppError::BadRequest("early".to_string())));
+        assert!(matches!(early, Err(AppError::BadRequest(_))));
+        db.take_commit("c1", |created_at| {
+            assert_eq!(created_at, 1_000);
+            Ok(())
+        })
+        .unwrap();
+        assert!(matches!(
+            db.take_commit("c1", |_| Ok(())),
+            Err(AppError::NotFound(_))
+        ));
+
+        // expired commitments are dropped when a new one is made
+        db.insert_commit("c2", 1_000, 0).unwrap();
+        db.insert_commit("c3", 2_000, 1_500).unwrap();
+        assert!(matches!(
+            db.take_commit("c2", |_| Ok(())),
+            Err(AppError::NotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_stream_users_pages_through_table() {
+        let db = Arc::new(Database::new(":memory:").unwrap());
+        for i in 0..5 {
+            db.insert_user(&User {
+                name: format!("user{}.miden", i),
+                address: format!("0x{}", i),
+                version: RecordVersion::Web2,
+            })
+            .unwrap();
+        }
+
+        // a page size that does not divide the table exercises the last partial page
+        let mut rx = db.stream_users(2);
+        let mut names = Vec::new();
+        while let Some(user) = rx.recv().await {
+            names.push(user.unwrap().name);
+        }
+        let expected: Vec<_> = (0..5).map(|i| format!("user{}.miden", i)).collect();
+        assert_eq!(names, expected);
+    }
+
+    #[test]
+    fn test_coin_addresses_replace_by_coin() {
+        let db = Database::new(":memory:").unwrap();
+        db.set_coin_address("alice.miden", "btc", "bc1old").unwrap();
+        db.set_coin_address("alice.miden", "btc", "bc1new").unwrap();
+        db.set_coin_address("alice.miden", "eth", "0xeth").unwrap();
+
+        let address = |coin: &str| db.coin_address("alice.miden", coin).unwrap();
+        assert_eq!(address("btc").as_deref(), Some("bc1new"));
+        assert_eq!(address("eth").as_deref(), Some("0xeth"));
+        assert_eq!(address("sol"), None);
+    }
+
+    #[test]
+    fn test_owner_token_hash_is_stored_with_user() {
+        let db = Database::new(":memory:").unwrap();
+        let user = |name: &str| User {
+            name: name.to_string(),
+            address: "0xabc".to_string(),
+            version: RecordVersion::Web2,
+        };
+        db.insert_owned_user(&user("alice.miden"), Some("hash"))
+            .unwrap();
+        db.insert_user(&user("bob.miden")).unwrap();
+
+        assert_eq!(
+            db.owner_token_hash("alice.miden").unwrap().as_deref(),
+            Some("hash")
+        );
+        assert_eq!(db.owner_token_hash("bob.miden").unwrap(), None);
+        assert_eq!(db.owner_token_hash("carol.miden").unwrap(), None);
+    }
 }
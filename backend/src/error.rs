@@ -1,17 +1,122 @@
 use axum::{
     Json,
-    http::StatusCode,
+    http::{StatusCode, header},
     response::{IntoResponse, Response},
 };
 use serde::{Deserialize, Serialize};
 use std::fmt;
 
-#[derive(Debug)]
+use crate::serde::RecordVersion;
+
+#[derive(Clone, Debug)]
 pub enum AppError {
     BadRequest(String),
     NotFound(String),
+    Unauthorized(String),
+    Forbidden(String),
+    Conflict(String),
+    // The name is taken, `existing` describes the current registration when it may be shared
+    AlreadyRegistered {
+        message: String,
+        existing: Option<ExistingRegistration>,
+    },
+    ServiceUnavailable(String),
     Internal(String),
     Database(String),
+    // A client error with a specific code, the HTTP status follows from the code
+    Coded {
+        code: ErrorCode,
+        message: String,
+    },
+}
+
+/// Machine-readable cause of an error, sent as `code` so clients can branch on it
+/// without matching the message.
+///
+/// The generic codes mirror the [`AppError`] variants, the others name a specific rule.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    BadRequest,
+    NotFound,
+    Unauthorized,
+    Forbidden,
+    Conflict,
+    ServiceUnavailable,
+    Internal,
+    Database,
+    // a required request parameter is missing or empty
+    MissingParameter,
+    // the name has a character or label that names cannot contain
+    InvalidName,
+    // the name exceeds the configured or the encoding length limit
+    NameTooLong,
+    // the address cannot be stored, e.g. a malformed account id
+    InvalidAddress,
+    // the registration version is unknown or not supported here
+    InvalidVersion,
+    // the name is already registered
+    NameTaken,
+    // the name is on the operator's reserved list
+    NameReserved,
+    // the idempotency key was already used for a different request
+    IdempotencyKeyReused,
+}
+
+impl ErrorCode {
+    pub fn status(self) -> StatusCode {
+        match self {
+            ErrorCode::BadRequest
+            | ErrorCode::MissingParameter
+            | ErrorCode::InvalidName
+            | ErrorCode::NameTooLong
+            | ErrorCode::InvalidAddress
+            | ErrorCode::InvalidVersion => StatusCode::BAD_REQUEST,
+            ErrorCode::NotFound => StatusCode::NOT_FOUND,
+            ErrorCode::Unauthorized => StatusCode::UNAUTHORIZED,
+            ErrorCode::Forbidden => StatusCode::FORBIDDEN,
+            ErrorCode::Conflict | ErrorCode::NameTaken | ErrorCode::NameReserved => {
+                StatusCode::CONFLICT
+            }
+            ErrorCode::IdempotencyKeyReused => StatusCode::UNPROCESSABLE_ENTITY,
+            ErrorCode::ServiceUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+            ErrorCode::Internal | ErrorCode::Database => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl AppError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            AppError::BadRequest(_) => ErrorCode::BadRequest,
+            AppError::NotFound(_) => ErrorCode::NotFound,
+            AppError::Unauthorized(_) => ErrorCode::Unauthorized,
+            AppError::Forbidden(_) => ErrorCode::Forbidden,
+            AppError::Conflict(_) => ErrorCode::Conflict,
+            AppError::AlreadyRegistered { .. } => ErrorCode::NameTaken,
+            AppError::ServiceUnavailable(_) => ErrorCode::ServiceUnavailable,
+            AppError::Internal(_) => ErrorCode::Internal,
+            AppError::Database(_) => ErrorCode::Database,
+            AppError::Coded { code, .. } => *code,
+        }
+    }
+
+    // The message without the status prefix `Display` adds
+    fn message(&self) -> &str {
+        match self {
+            AppError::BadRequest(msg)
+            | AppError::NotFound(msg)
+            | AppError::Unauthorized(msg)
+            | AppError::Forbidden(msg)
+            | AppError::Conflict(msg)
+            | AppError::ServiceUnavailable(msg)
+            | AppError::Internal(msg)
+            | AppError::Database(msg) => msg,
+            AppError::AlreadyRegistered { message, .. } | AppError::Coded { message, .. } => {
+                message
+            }
+        }
+    }
 }
 
 // Human-friendly error messages
@@ -20,8 +125,18 @@ impl fmt::Display for AppError {
         let message = match self {
             AppError::NotFound(msg) => format!("Not Found: {}", msg),
             AppError::BadRequest(msg) => format!("Bad Request: {}", msg),
+            AppError::Unauthorized(msg) => format!("Unauthorized: {}", msg),
+            AppError::Forbidden(msg) => format!("Forbidden: {}", msg),
+            AppError::Conflict(msg) => format!("Conflict: {}", msg),
+            AppError::AlreadyRegistered { message, .. } => format!("Conflict: {}", message),
+            AppError::ServiceUnavailable(msg) => format!("Service Unavailable: {}", msg),
             AppError::Internal(msg) => format!("Internal Server Error: {}", msg),
             AppError::Database(msg) => format!("Database Error: {}", msg),
+            AppError::Coded { code, message } => format!(
+                "{}: {}",
+                code.status().canonical_reason().unwrap_or("Error"),
+                message
+            ),
         };
         write!(f, "{}", message)
     }
@@ -30,36 +145,134 @@ impl fmt::Display for AppError {
 // Implement std::error::Error for AppError
 impl std::error::Error for AppError {}
 
+// Current registration of a name a client tried to register again
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExistingRegistration {
+    pub address: String,
+    pub version: RecordVersion,
+}
+
 // JSON response structure
 #[derive(Serialize, Deserialize)]
 pub struct ErrorResponse {
     pub error: String,
     pub status: u16,
+    pub code: ErrorCode,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub existing: Option<ExistingRegistration>,
 }
 
 // Convert AppError to axum Response
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
-        let (status, error_message) = match &self {
-            AppError::NotFound(msg) => (StatusCode::NOT_FOUND, msg.clone()),
-            AppError::BadRequest(msg) => (StatusCode::BAD_REQUEST, msg.clone()),
-            AppError::Internal(msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg.clone()),
-            AppError::Database(msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg.clone()),
-        };
+        let code = self.code();
+        let status = code.status();
 
-        // Log internal server errors
+        // Log internal server errors, and report them when Sentry is configured. The
+        // request's scope already carries the endpoint and, once parsed, the name
         if matches!(self, AppError::Internal(_)) {
             log::error!("{}", self);
+            sentry::capture_message(&self.to_string(), sentry::Level::Error);
         }
 
+        let existing = match &self {
+            AppError::AlreadyRegistered { existing, .. } => existing.clone(),
+            _ => None,
+        };
         let body = Json(ErrorResponse {
-            error: error_message,
+            error: self.message().to_string(),
             status: status.as_u16(),
+            code,
+            existing,
         });
 
+        // Unavailability is temporary, tell clients when to try again
+        if matches!(self, AppError::ServiceUnavailable(_)) {
+            return (status, [(header::RETRY_AFTER, "5")], body).into_response();
+        }
+
         (status, body).into_response()
     }
 }
 
 // Type alias for results
 pub type Result<T> = std::result::Result<T, AppError>;
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        http::{StatusCode, header},
+        response::IntoResponse,
+    };
+
+    use crate::error::{AppError, ErrorCode, ExistingRegistration};
+    use crate::serde::RecordVersion;
+
+    #[test]
+    fn test_error_response_is_json() {
+        let response =
+            AppError::BadRequest("Name parameter is required".to_string()).into_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            response.headers().get(header::CONTENT_TYPE).unwrap(),
+            "application/json"
+        );
+    }
+
+    #[test]
+    fn test_service_unavailable_sets_retry_after() {
+        let response = AppError::ServiceUnavailable("starting".to_string()).into_response();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers().get(header::RETRY_AFTER).unwrap(), "5");
+    }
+
+    #[tokio::test]
+    async fn test_already_registered_includes_existing() {
+        let response = AppError::AlreadyRegistered {
+            message: "User has already been registered.".to_string(),
+            existing: Some(ExistingRegistration {
+                address: "0xabc".to_string(),
+                version: RecordVersion::Web2,
+            }),
+        }
+        .into_response();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "error": "User has already been registered.",
+                "status": 409,
+                "code": "NAME_TAKEN",
+                "existing": { "address": "0xabc", "version": "2" },
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_coded_error_sets_status_and_code() {
+        let response = AppError::Coded {
+            code: ErrorCode::NameTooLong,
+            message: "Name is too long".to_string(),
+        }
+        .into_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "error": "Name is too long",
+                "status": 400,
+                "code": "NAME_TOO_LONG",
+            })
+        );
+    }
+}
@@ -0,0 +1,112 @@
+use log::warn;
+use miden_client::{Client, store::TransactionFilter, transaction::TransactionStatus};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransactionState {
+    Pending,
+    Committed,
+    Failed,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct TransactionEvent {
+    pub transaction_id: String,
+    pub status: TransactionState,
+    /// Block the transaction was included in, `null` until it is committed
+    pub block_num: Option<u32>,
+}
+
+/// Latest known state of submitted transactions, with a broadcast of every change
+#[derive(Clone)]
+pub struct TransactionEvents {
+    statuses: Arc<Mutex<HashMap<String, TransactionEvent>>>,
+    sender: broadcast::Sender<TransactionEvent>,
+}
+
+impl TransactionEvents {
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity);
+        Self {
+            statuses: Arc::new(Mutex::new(HashMap::new())),
+            sender,
+        }
+    }
+
+    /// Subscribe to all future state changes
+    pub fn subscribe(&self) -> broadcast::Receiver<TransactionEvent> {
+        self.sender.subscribe()
+    }
+
+    /// Latest known state of a transaction, `None` if it was never submitted here
+    pub fn latest(&self, transaction_id: &str) -> Option<TransactionEvent> {
+        self.statuses
+            .lock()
+            .ok()
+            .and_then(|statuses| statuses.get(transaction_id).cloned())
+    }
+
+    /// Ids of the transactions still awaiting inclusion
+    pub fn pending(&self) -> Vec<String> {
+        match self.statuses.lock() {
+            Ok(statuses) => statuses
+                .iter()
+                .filter(|(_, event)| event.status == TransactionState::Pending)
+                .map(|(id, _)| id.clone())
+                .collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    /// Record a state change and notify subscribers
+    pub fn update(&self, transaction_id: &str, status: TransactionState, block_num: Option<u32>) {
+        let event = TransactionEvent {
+            transaction_id: transaction_id.to_string(),
+            status,
+            block_num,
+        };
+        if let Ok(mut statuses) = self.statuses.lock() {
+            statuses.insert(event.transaction_id.clone(), event.clone());
+        }
+
+        // no subscribers is not an error
+        let _ = self.sender.send(event);
+    }
+}
+
+/// Refresh pending transactions from the client store, meant to run after a sync
+pub async fn refresh(client: &mut Client, events: &TransactionEvents) {
+    let pending = events.pending();
+    if pending.is_empty() {
+        return;
+    }
+
+    let records = match client.get_transactions(TransactionFilter::All).await {
+        Ok(records) => records,
+        Err(e) => {
+            warn!("Failed to read transactions from the client store: {}", e);
+            return;
+        }
+    };
+
+    for record in records {
+        let transaction_id = record.id.to_string();
+        if !pending.contains(&transaction_id) {
+            continue;
+        }
+
+        match record.status {
+            TransactionStatus::Pending => {}
+            TransactionStatus::Committed(block_num) => events.update(
+                &transaction_id,
+                TransactionState::Committed,
+                Some(block_num.as_u32()),
+            ),
+            _ => events.update(&transaction_id, TransactionState::Failed, None),
+        }
+    }
+}
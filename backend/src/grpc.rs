@@ -0,0 +1,195 @@
+//! gRPC transport mirroring the REST lookup and register endpoints.
+//!
+//! Requests go through the same handler logic as HTTP, so they share the database, the
+//! client worker channel and every validation rule.
+
+use axum::http::StatusCode;
+use tonic::{Request, Response, Status};
+
+use crate::auth::{API_KEY_HEADER, ApiKeys};
+use crate::error::AppError;
+use crate::handler::{self, AppState, LookupParams, LookupSource, RegisterRequest};
+
+pub mod proto {
+    tonic::include_proto!("mns.v1");
+}
+
+use proto::name_service_server::NameService;
+pub use proto::name_service_server::NameServiceServer;
+
+impl From<AppError> for Status {
+    fn from(e: AppError) -> Self {
+        let message = e.to_string();
+        match e {
+            AppError::BadRequest(_) => Status::invalid_argument(message),
+            AppError::NotFound(_) => Status::not_found(message),
+            AppError::Unauthorized(_) => Status::unauthenticated(message),
+            AppError::Forbidden(_) => Status::permission_denied(message),
+            AppError::Conflict(_) | AppError::AlreadyRegistered { .. } => {
+                Status::already_exists(message)
+            }
+            AppError::ServiceUnavailable(_) => Status::unavailable(message),
+            AppError::Internal(_) | AppError::Database(_) => Status::internal(message),
+            // coded errors are all client errors, either invalid input or a taken name
+            AppError::Coded { code, .. } if code.status() == StatusCode::CONFLICT => {
+                Status::already_exists(message)
+            }
+            AppError::Coded { .. } => Status::invalid_argument(message),
+        }
+    }
+}
+
+pub struct GrpcService {
+    state: AppState,
+    // `--api-key` keys, required on `Register` like on the HTTP mutating endpoints
+    api_keys: Option<ApiKeys>,
+}
+
+impl GrpcService {
+    pub fn new(state: AppState, api_keys: Option<ApiKeys>) -> Self {
+        Self { state, api_keys }
+    }
+}
+
+// Check the `x-api-key` metadata of a call that changes registrations, lookups stay public
+fn authorize_write<T>(api_keys: Option<&ApiKeys>, request: &Request<T>) -> Result<(), Status> {
+    let Some(keys) = api_keys else {
+        return Ok(());
+    };
+    let provided = request
+        .metadata()
+        .get(API_KEY_HEADER)
+        .and_then(|value| value.to_str().ok());
+    Ok(keys.check_key(provided)?)
+}
+
+fn parse_source(source: &str) -> Result<LookupSource, Status> {
+    match source {
+        "" | "auto" => Ok(LookupSource::Auto),
+        "db" => Ok(LookupSource::Db),
+        "contract" => Ok(LookupSource::Contract),
+        _ => Err(Status::invalid_argument(format!(
+            "Invalid source '{}', expected one of: auto, db, contract",
+            source
+        ))),
+    }
+}
+
+#[tonic::async_trait]
+impl NameService for GrpcService {
+    async fn lookup(
+        &self,
+        request: Request<proto::LookupRequest>,
+    ) -> Result<Response<proto::LookupReply>, Status> {
+        let request = request.into_inner();
+        let params = LookupParams {
+            name: request.name,
+            source: parse_source(&request.source)?,
+            record_type: request.record_type,
+            soft_not_found: false,
+            include_history: false,
+            coin: None,
+        };
+
+        let response = handler::lookup_name(&self.state, params).await?;
+        Ok(Response::new(proto::LookupReply {
+            address: response.address,
+            version: response.version.to_string(),
+            records: response
+                .records
+                .into_iter()
+                .map(|record| proto::ResolverRecord {
+                    r#type: record.record_type,
+                    value: record.value,
+                })
+                .collect(),
+            source: response.source.as_str().to_string(),
+        }))
+    }
+
+    async fn register(
+        &self,
+        request: Request<proto::RegisterRequest>,
+    ) -> Result<Response<proto::RegisterReply>, Status> {
+        authorize_write(self.api_keys.as_ref(), &request)?;
+        let request = request.into_inner();
+        let request = RegisterRequest {
+            name: request.name,
+            address: request.address,
+            version: request.version,
+            parent_token: request.parent_token,
+            secret: request.secret,
+            owner: request.owner,
+        };
+
+        let response = handler::register_name(&self.state, request).await?;
+        Ok(Response::new(proto::RegisterReply {
+            name: response.name,
+            address: response.address,
+            version: response.version.to_string(),
+            transaction_id: response.transaction_id,
+            owner: response.owner,
+            storage_key: response.storage_key,
+            owner_token: response.owner_token,
+        }))
+    }
+
+    async fn reverse(
+        &self,
+        request: Request<proto::ReverseRequest>,
+    ) -> Result<Response<proto::ReverseReply>, Status> {
+        let address = request.into_inner().address;
+        if address.is_empty() {
+            return Err(Status::invalid_argument("Address parameter is required"));
+        }
+
+        let names = handler::database(&self.state)?.names_for_address(&address)?;
+        Ok(Response::new(proto::ReverseReply { names }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tonic::Code;
+
+    use crate::auth::{API_KEY_HEADER, ApiKeys};
+    use crate::error::AppError;
+    use crate::grpc::{authorize_write, parse_source};
+    use crate::handler::LookupSource;
+
+    #[test]
+    fn test_status_codes_follow_http_errors() {
+        let status: tonic::Status = AppError::NotFound("gone".to_string()).into();
+        assert_eq!(status.code(), Code::NotFound);
+        let status: tonic::Status = AppError::Conflict("taken".to_string()).into();
+        assert_eq!(status.code(), Code::AlreadyExists);
+
+        assert_eq!(parse_source("").unwrap(), LookupSource::Auto);
+        assert_eq!(parse_source("db").unwrap(), LookupSource::Db);
+        assert_eq!(
+            parse_source("web").unwrap_err().code(),
+            Code::InvalidArgument
+        );
+    }
+
+    #[test]
+    fn test_writes_need_api_key() {
+        let request = |key: Option<&str>| {
+            let mut request = tonic::Request::new(());
+            if let Some(key) = key {
+                request
+                    .metadata_mut()
+                    .insert(API_KEY_HEADER, key.parse().unwrap());
+            }
+            request
+        };
+        let keys = ApiKeys::new(["secret".to_string()]);
+
+        assert!(authorize_write(None, &request(None)).is_ok());
+        assert!(authorize_write(Some(&keys), &request(Some("secret"))).is_ok());
+        for key in [None, Some("wrong")] {
+            let status = authorize_write(Some(&keys), &request(key)).unwrap_err();
+            assert_eq!(status.code(), Code::Unauthenticated);
+        }
+    }
+}
@@ -1,218 +1,3289 @@
 use axum::{
-    extract::{Query, State},
-    http::StatusCode,
-    response::{IntoResponse, Json},
+    body::Body,
+    extract::{Path, Query, State},
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    response::{
+        IntoResponse, Json, Response,
+        sse::{Event, KeepAlive, Sse},
+    },
 };
+use futures::stream::{self, StreamExt};
 use log::info;
+use miden_client::{Word, account::AccountId};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::sync::Arc;
-use tokio::sync::oneshot;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock, RwLock};
+use std::time::Duration;
+use tokio::sync::{
+    Semaphore,
+    broadcast::error::RecvError,
+    mpsc::{self, error::TrySendError},
+    oneshot,
+};
 
+use crate::auth::{OWNER_TOKEN_HEADER, hash_owner_token, new_owner_token, secrets_match};
+use crate::bloom::BloomFilter;
+use crate::commit::{CommitPolicy, commitment, now_secs, parse_commitment};
 use crate::db::Database;
-use crate::error::{AppError, Result};
+use crate::error::{AppError, ErrorCode, ExistingRegistration, Result};
+use crate::events::{TransactionEvents, TransactionState};
+use crate::idempotency::{self, Claim, IdempotencyStore};
+use crate::reserved::ReservedNames;
+use crate::serde::{MAX_NAME_BYTES, Name, RecordVersion, hex_to_word, word_to_hex, word_to_str};
+use crate::singleflight::SingleFlight;
+use crate::utils::{masm, mns_code_commitment};
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct User {
     pub name: String,
     pub address: String,
+    pub version: RecordVersion,
+}
+
+// Query parameters of `GET /register`, a missing parameter is rejected with 400 by axum
+#[derive(Deserialize)]
+pub struct RegisterParams {
+    pub name: String,
+    pub address: String,
+    // falls back to `--default-version` when missing
+    #[serde(default)]
     pub version: String,
+    pub parent_token: Option<String>,
+    pub secret: Option<String>,
+    pub owner: Option<String>,
 }
 
-#[derive(Serialize)]
-pub struct LookupResponse {
+impl From<RegisterParams> for RegisterRequest {
+    fn from(params: RegisterParams) -> Self {
+        Self {
+            name: params.name,
+            address: params.address,
+            version: params.version,
+            parent_token: params.parent_token,
+            secret: params.secret,
+            owner: params.owner,
+        }
+    }
+}
+
+// Query parameters of `PUT /update`
+#[derive(Deserialize)]
+pub struct UpdateParams {
+    pub name: String,
+    pub address: String,
+}
+
+// Backend(s) a lookup consults, the database and then the contract by default
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LookupSource {
+    #[default]
+    Auto,
+    Db,
+    Contract,
+}
+
+/// Which backend `source=auto` lookups try first, the other one answers misses.
+///
+/// `db-first` answers database names without a contract query and only sends misses to
+/// the worker, but a database row shadows whatever the contract holds for the same name.
+/// `contract-first` treats the contract as authoritative, so every lookup waits for a
+/// contract query on the single client worker and database names are only found after
+/// a contract miss.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum LookupOrder {
+    #[default]
+    DbFirst,
+    ContractFirst,
+}
+
+// Query parameters of `GET /lookup`
+#[derive(Default, Deserialize)]
+pub struct LookupParams {
+    pub name: String,
+    #[serde(default)]
+    pub source: LookupSource,
+    // resolver record type to select, all records are returned without it
+    pub record_type: Option<String>,
+    // answer 200 with `found: false` instead of 404 for unknown names
+    #[serde(default)]
+    pub soft_not_found: bool,
+    // list the addresses a database name pointed at before its current one
+    #[serde(default)]
+    pub include_history: bool,
+    // chain of the address to return, such as `btc`, the Miden address without it
+    pub coin: Option<String>,
+}
+
+#[derive(Default, Deserialize)]
+pub struct RegisterRequest {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
     pub address: String,
+    #[serde(default)]
     pub version: String,
+    /// Owner token of the parent name, required to register a subname
+    #[serde(default)]
+    pub parent_token: Option<String>,
+    /// Secret of an earlier `/commit`, revealing the commitment this registration claims
+    #[serde(default)]
+    pub secret: Option<String>,
+    /// Account id the registrant declares as owner of a version 2.5 name, recorded on-chain
+    /// as given. Nothing proves the registrant controls it yet.
+    #[serde(default)]
+    pub owner: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ResolverRecord {
+    #[serde(rename = "type")]
+    pub record_type: String,
+    pub value: String,
+}
+
+// Backend a lookup was answered from
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LookupOrigin {
+    Database,
+    Contract,
+}
+
+impl LookupOrigin {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LookupOrigin::Database => "database",
+            LookupOrigin::Contract => "contract",
+        }
+    }
+}
+
+#[derive(Clone, Serialize)]
+pub struct LookupResponse {
+    pub address: String,
+    pub version: RecordVersion,
+    pub records: Vec<ResolverRecord>,
+    /// `contract` only for answers read from the contract storage
+    pub source: LookupOrigin,
+    /// Earlier addresses of a database name, oldest first, only with `include_history`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub history: Option<Vec<PastAddress>>,
+    /// Chain of `address` when another than Miden was asked for with `coin`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub coin: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct PastAddress {
+    pub address: String,
+    /// When the name was pointed away from `address`
+    pub replaced_at: String,
+}
+
+// Miden has no SLIP-44 coin type assigned yet, this provisional value follows the ENSIP-11
+// convention of setting the high bit for chains outside SLIP-44
+pub const MIDEN_COIN_TYPE: u32 = 0x8000_0000;
+
+// Coin identifier of the address every name has, set by registration and `/update`
+pub const MIDEN_COIN: &str = "miden";
+
+#[derive(Serialize)]
+pub struct CoinAddressResponse {
+    pub name: String,
+    pub coin: String,
+    pub address: String,
+}
+
+#[derive(Serialize)]
+pub struct ResolveResponse {
+    pub name: String,
+    pub address: String,
+    #[serde(rename = "coinType")]
+    pub coin_type: u32,
+    pub ttl: u64,
 }
 
+/// Lookup result for `soft_not_found=true`, where unknown names are not an HTTP error
 #[derive(Serialize)]
+pub struct SoftLookupResponse {
+    pub found: bool,
+    #[serde(flatten)]
+    pub lookup: Option<LookupResponse>,
+}
+
+#[derive(Serialize)]
+pub struct RecordsResponse {
+    pub name: String,
+    pub records: Vec<ResolverRecord>,
+}
+
+#[derive(Clone, Debug, Serialize)]
 pub struct RegisterResponse {
     pub name: String,
     pub address: String,
-    pub version: String,
+    pub version: RecordVersion,
     pub transaction_id: Option<String>,
+    /// Account recorded on-chain as the owner, only set for on-chain registrations naming one
+    pub owner: Option<String>,
+    /// Hex key of the name in the contract storage map, only set for on-chain registrations
+    pub storage_key: Option<String>,
+    /// Secret authorizing later changes to a database name, sent back as `x-owner-token`.
+    /// Only this response carries it, not even an `Idempotency-Key` replay of it, and the
+    /// server keeps nothing but its hash.
+    pub owner_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct CommitRequest {
+    /// Hex SHA-256 of `<name>:<secret>`, see [`commitment`]
+    pub commitment: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CommitResponse {
+    pub commitment: String,
+    /// Unix time from which the commitment can be revealed
+    pub reveal_after: u64,
+    /// Unix time at which an unrevealed commitment is dropped
+    pub expires_at: u64,
+}
+
+#[derive(Serialize)]
+pub struct BulkRegisterResult {
+    pub name: String,
+    pub success: bool,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct BulkRegisterResponse {
+    pub registered: usize,
+    pub failed: usize,
+    pub results: Vec<BulkRegisterResult>,
+}
+
+#[derive(Serialize)]
+pub struct StorageEntry {
+    pub name: String,
+    pub address: String,
+}
+
+#[derive(Serialize)]
+pub struct StorageResponse {
+    pub entries: Vec<StorageEntry>,
+    /// Entries in the names maps of every shard
+    pub total: usize,
+    /// Pass as `cursor` to read the next page, `None` once the maps are exhausted
+    pub next_cursor: Option<String>,
+}
+
+/// Raw names map entry, key and value as `0x`-prefixed hex words
+#[derive(Serialize)]
+pub struct ExportEntry {
+    pub key: String,
+    pub value: String,
+}
+
+#[derive(Serialize)]
+pub struct ExportResponse {
+    pub entries: Vec<ExportEntry>,
+    /// Pass as `cursor` to read the next page, `None` once the map is exhausted
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct SearchResponse {
+    pub names: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct StatsResponse {
+    pub total: u64,
+    pub by_version: BTreeMap<String, u64>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct TransactionRecord {
+    pub name: String,
+    pub transaction_id: String,
+    pub created_at: String,
+}
+
+// One change to a name, `old_address` is unset for registrations
+#[derive(Clone, Debug, Serialize)]
+pub struct HistoryEntry {
+    pub old_address: Option<String>,
+    pub new_address: Option<String>,
+    pub action: String,
+    pub at: String,
+}
+
+#[derive(Serialize)]
+pub struct HistoryResponse {
+    pub name: String,
+    pub entries: Vec<HistoryEntry>,
+    /// Offset of the next page, unset on the last one
+    pub next_offset: Option<u32>,
+}
+
+#[derive(Serialize)]
+pub struct TransactionsResponse {
+    pub transactions: Vec<TransactionRecord>,
+}
+
+#[derive(Serialize)]
+pub struct EncodeResponse {
+    pub name: String,
+    pub decoded: String,
+    pub word: String,
+    pub felts: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct DebugLookupResponse {
+    pub name: String,
+    pub key: String,
+    pub stack: Vec<String>,
+    pub address: Option<String>,
+    pub version: Option<RecordVersion>,
+    pub error: Option<String>,
+}
+
+/// How far the client worker has synced, for diagnosing lookups that lag registrations
+#[derive(Serialize)]
+pub struct SyncInfoResponse {
+    pub block_num: u32,
+    /// Unix time in seconds of the last successful periodic sync, `None` before the first
+    pub synced_at: Option<u64>,
+}
+
+/// Result of the worker's RPC round trip for deep health checks
+pub struct WorkerHealth {
+    pub rpc_ok: bool,
+    pub block_height: Option<u32>,
+}
+
+#[derive(Serialize)]
+pub struct HealthResponse {
+    pub status: String,
+    pub ready: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rpc_ok: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub block_height: Option<u32>,
 }
 
 #[derive(Clone)]
 pub struct AppState {
-    pub tx: tokio::sync::mpsc::Sender<ClientRequest>,
-    pub db: Arc<Database>,
+    pub tx: mpsc::Sender<ClientRequest>,
+    pub db: Option<Arc<Database>>,
+    pub max_name_len: usize,
+    pub admin_token: Option<String>,
+    pub events: TransactionEvents,
+    pub read_only: bool,
+    pub bloom: Option<Arc<RwLock<BloomFilter>>>,
+    pub reserved: Option<Arc<ReservedNames>>,
+    pub commits: CommitPolicy,
+    pub idempotency: Arc<IdempotencyStore>,
+    pub ready: Arc<AtomicBool>,
+    pub lookup_cache_ttl_secs: u64,
+    pub lookup_flights: Arc<SingleFlight<Result<LookupResponse>>>,
+    pub lookup_order: LookupOrder,
+    pub register_permits: Arc<Semaphore>,
+    pub expose_contract_conflicts: bool,
+    pub debug_endpoints: bool,
+    /// Version of registrations that do not name one, set with `--default-version`
+    pub default_version: Option<RecordVersion>,
+    /// Contract account ids in shard order, set by the worker once they are available
+    pub contract_ids: Arc<OnceLock<Vec<String>>>,
+}
+
+impl AppState {
+    /// Ready state around a stand-in worker channel and database, with every option at its
+    /// default. Only meant for tests and benches, which override fields as needed.
+    #[doc(hidden)]
+    pub fn for_tests(tx: mpsc::Sender<ClientRequest>, db: Option<Arc<Database>>) -> Self {
+        Self {
+            tx,
+            db,
+            max_name_len: MAX_NAME_BYTES,
+            admin_token: None,
+            events: TransactionEvents::new(16),
+            read_only: false,
+            bloom: None,
+            reserved: None,
+            commits: CommitPolicy::default(),
+            idempotency: Arc::new(IdempotencyStore::new(Duration::from_secs(60), 1024)),
+            ready: Arc::new(AtomicBool::new(true)),
+            lookup_cache_ttl_secs: 30,
+            lookup_flights: Arc::new(SingleFlight::new()),
+            lookup_order: LookupOrder::DbFirst,
+            register_permits: Arc::new(Semaphore::new(32)),
+            expose_contract_conflicts: false,
+            debug_endpoints: false,
+            default_version: None,
+            contract_ids: Arc::new(OnceLock::new()),
+        }
+    }
 }
 
-// Request enum for different Client operations
+// Request enum for different Client operations, each carrying the id of the HTTP
+// request that issued it so worker logs can be correlated
 pub enum ClientRequest {
     Lookup {
-        params: std::collections::HashMap<String, String>,
+        request_id: u64,
+        name: Name,
         respond: tokio::sync::oneshot::Sender<Result<LookupResponse>>,
     },
     Register {
-        params: std::collections::HashMap<String, String>,
+        request_id: u64,
+        name: Name,
+        address: String,
+        owner: Option<AccountId>,
         respond: tokio::sync::oneshot::Sender<Result<RegisterResponse>>,
     },
+    Availability {
+        request_id: u64,
+        name: Name,
+        respond: tokio::sync::oneshot::Sender<Result<bool>>,
+    },
+    Health {
+        request_id: u64,
+        respond: tokio::sync::oneshot::Sender<WorkerHealth>,
+    },
+    Storage {
+        request_id: u64,
+        cursor: Option<Word>,
+        limit: usize,
+        respond: tokio::sync::oneshot::Sender<Result<StorageResponse>>,
+    },
+    DebugLookup {
+        request_id: u64,
+        name: Name,
+        respond: tokio::sync::oneshot::Sender<Result<DebugLookupResponse>>,
+    },
+    ExportStorage {
+        request_id: u64,
+        cursor: Option<Word>,
+        limit: usize,
+        respond: tokio::sync::oneshot::Sender<Result<ExportResponse>>,
+    },
+    SyncInfo {
+        request_id: u64,
+        respond: tokio::sync::oneshot::Sender<Result<SyncInfoResponse>>,
+    },
 }
 
-pub async fn register_handler(
-    State(state): State<AppState>,
-    Query(params): Query<HashMap<String, String>>,
-) -> impl IntoResponse {
-    let name = params.get("name").cloned().unwrap_or_default();
-    let address = params.get("address").cloned().unwrap_or_default();
-    let version = params.get("version").cloned().unwrap_or_default();
-
-    if name.is_empty() {
-        return AppError::BadRequest("Name parameter is required".to_string()).into_response();
+impl ClientRequest {
+    pub fn request_id(&self) -> u64 {
+        match self {
+            ClientRequest::Lookup { request_id, .. }
+            | ClientRequest::Register { request_id, .. }
+            | ClientRequest::Availability { request_id, .. }
+            | ClientRequest::Health { request_id, .. }
+            | ClientRequest::Storage { request_id, .. }
+            | ClientRequest::DebugLookup { request_id, .. }
+            | ClientRequest::ExportStorage { request_id, .. }
+            | ClientRequest::SyncInfo { request_id, .. } => *request_id,
+        }
     }
 
-    if address.is_empty() {
-        return AppError::BadRequest("Address parameter is required".to_string()).into_response();
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ClientRequest::Lookup { .. } => "lookup",
+            ClientRequest::Register { .. } => "register",
+            ClientRequest::Availability { .. } => "availability",
+            ClientRequest::Health { .. } => "health",
+            ClientRequest::Storage { .. } => "storage",
+            ClientRequest::DebugLookup { .. } => "debug_lookup",
+            ClientRequest::ExportStorage { .. } => "export_storage",
+            ClientRequest::SyncInfo { .. } => "sync_info",
+        }
     }
+}
 
-    if version.is_empty() {
-        return AppError::BadRequest("Version parameter is required".to_string()).into_response();
+// The client worker only reads requests once the contract account is imported or deployed,
+// fail fast instead of queueing behind startup
+fn ensure_ready(state: &AppState) -> Result<()> {
+    if state.ready.load(Ordering::Acquire) {
+        return Ok(());
     }
 
-    // Check if user already exists in database
-    if let Ok(Some(_)) = state.db.lookup_user(&name) {
-        info!(
-            "Failed to register user: {} user has already been registered in database.",
-            name
-        );
-        return AppError::Database("User has already been registered.".to_string()).into_response();
+    Err(AppError::ServiceUnavailable(
+        "Service starting, the contract is not ready yet".to_string(),
+    ))
+}
+
+// Queue a request for the client worker without waiting for room, a full queue means the
+// worker is already behind and waiting would only hide it from the client
+fn dispatch(worker: &mpsc::Sender<ClientRequest>, request: ClientRequest) -> Result<()> {
+    worker.try_send(request).map_err(|e| match e {
+        TrySendError::Full(_) => AppError::ServiceUnavailable(
+            "Too many requests queued for the client, try again later".to_string(),
+        ),
+        TrySendError::Closed(_) => AppError::Internal("Failed to process request".to_string()),
+    })
+}
+
+static NEXT_REQUEST_ID: AtomicU64 = AtomicU64::new(1);
+
+// Process-unique id for correlating an HTTP request with the work done on the client thread
+fn next_request_id() -> u64 {
+    NEXT_REQUEST_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+// False only when the bloom filter is enabled and the name is definitely unregistered
+fn might_be_registered(state: &AppState, name: &str) -> bool {
+    match &state.bloom {
+        Some(bloom) => bloom
+            .read()
+            .map(|bloom| bloom.might_contain(name))
+            .unwrap_or(true),
+        None => true,
     }
+}
 
-    let (tx, rx) = oneshot::channel();
-    let request = ClientRequest::Lookup {
-        params: params.clone(),
-        respond: tx,
-    };
+// The users database, unavailable when the server runs with `--no-db`
+pub(crate) fn database(state: &AppState) -> Result<&Database> {
+    state.db.as_deref().ok_or_else(|| {
+        AppError::BadRequest("This server runs without a database (--no-db)".to_string())
+    })
+}
 
-    // Send lookup request to check if user exists in smart contract
-    if let Ok(_) = state.tx.send(request).await {
-        // Wait for the response
-        if let Ok(Ok(_)) = rx.await {
-            // User exists in smart contract
-            info!(
-                "Failed to register user: {} user has already been registered in smart contract.",
-                name
-            );
-            return AppError::Database("User has already been registered.".to_string())
-                .into_response();
-        }
+// Names on the `--reserved-names` list cannot be registered through the public API
+fn ensure_not_reserved(state: &AppState, name: &str) -> Result<()> {
+    match &state.reserved {
+        Some(reserved) if reserved.is_reserved(name) => Err(AppError::Coded {
+            code: ErrorCode::NameReserved,
+            message: format!("Name '{}' is reserved", name),
+        }),
+        _ => Ok(()),
     }
+}
 
-    if version == "2" {
-        // Instantiate User
-        let user = User {
-            name: name.clone(),
-            address: address.clone(),
-            version: version.clone(),
-        };
+// Add a newly registered name to the bloom filter, if enabled
+fn remember_name(state: &AppState, name: &str) {
+    if let Some(Ok(mut bloom)) = state.bloom.as_ref().map(|bloom| bloom.write()) {
+        bloom.insert(name);
+    }
+}
 
-        // Save user to database
-        if let Err(e) = state.db.insert_user(&user) {
-            info!("Failed to save user: {}", e);
-            return AppError::Database("Failed to save user to database".to_string())
-                .into_response();
-        };
+pub async fn register_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(params): Query<RegisterParams>,
+) -> impl IntoResponse {
+    register_idempotent(state, &headers, params.into()).await
+}
 
-        let response = RegisterResponse {
-            name: user.name,
-            address: user.address,
-            version: user.version,
-            transaction_id: None,
-        };
+// JSON body variant of `register_handler`, keeps names and addresses out of URLs
+pub async fn register_json_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(request): Json<RegisterRequest>,
+) -> impl IntoResponse {
+    register_idempotent(state, &headers, request).await
+}
 
-        return (StatusCode::OK, Json(response)).into_response();
-    } else if version == "2.5" {
-        let (tx, rx) = oneshot::channel();
+// First step of a commit-reveal registration, stores a commitment to a name without
+// revealing it. Registering with the secret completes it once `--commit-min-age-secs`
+// have passed.
+pub async fn commit_handler(
+    State(state): State<AppState>,
+    Json(request): Json<CommitRequest>,
+) -> impl IntoResponse {
+    match commit(&state, &request.commitment) {
+        Ok(response) => (StatusCode::CREATED, Json(response)).into_response(),
+        Err(e) => e.into_response(),
+    }
+}
 
-        let request = ClientRequest::Register {
-            params: params.clone(),
-            respond: tx,
-        };
+fn commit(state: &AppState, commitment: &str) -> Result<CommitResponse> {
+    if state.read_only {
+        return Err(AppError::Forbidden(
+            "Server is running in read-only mode".to_string(),
+        ));
+    }
+    let db = database(state)?;
+    let commitment = parse_commitment(commitment)?;
 
-        // Send the request to the client handler
-        if let Err(_) = state.tx.send(request).await {
-            return AppError::Internal("Failed to process request".to_string()).into_response();
-        }
+    let now = now_secs();
+    let expired_before = now.saturating_sub(state.commits.max_age.as_secs());
+    db.insert_commit(&commitment, now, expired_before)?;
+    info!("Stored registration commitment {}", commitment);
 
-        // Wait for the response
-        match rx.await {
-            Ok(result) => match result {
-                Ok(response) => (StatusCode::OK, Json(response)).into_response(),
-                Err(err) => err.into_response(),
-            },
-            Err(_) => AppError::Internal("Failed to receive response".to_string()).into_response(),
+    Ok(CommitResponse {
+        commitment,
+        reveal_after: state.commits.reveal_after(now),
+        expires_at: state.commits.expires_at(now),
+    })
+}
+
+// Consume the commitment `secret` reveals for `name`, or refuse registrations without one
+// when commitments are required. Returns the commitment and its creation time so a
+// failed registration can restore it.
+fn reveal_commitment(
+    state: &AppState,
+    name: &str,
+    secret: Option<&str>,
+) -> Result<Option<(String, u64)>> {
+    let secret = match secret {
+        Some(secret) if !secret.is_empty() => secret,
+        _ if state.commits.required => {
+            return Err(AppError::Forbidden(
+                "Registration needs a commitment, POST /commit then register with its secret"
+                    .to_string(),
+            ));
         }
-    } else {
-        return AppError::BadRequest(
-            "The server can only process Web2 or Web2.5 requests".to_string(),
-        )
-        .into_response();
+        _ => return Ok(None),
+    };
+
+    let now = now_secs();
+    let commitment = commitment(name, secret);
+    let created_at = database(state)?.take_commit(&commitment, |created_at| {
+        state.commits.check_reveal(created_at, now)
+    })?;
+    Ok(Some((commitment, created_at)))
+}
+
+// Put back a commitment taken by a registration that failed afterwards
+fn restore_commitment(state: &AppState, commitment: &str, created_at: u64) {
+    let restored = database(state).and_then(|db| db.insert_commit(commitment, created_at, 0));
+    if let Err(e) = restored {
+        info!("Failed to restore commitment {}: {}", commitment, e);
     }
 }
 
-pub async fn lookup_handler(
+// Bulk import of name -> address mappings for migrating existing registries.
+//
+// Only version "2" (database) entries are accepted; version "2.5" entries are rejected
+// since each would need its own on-chain transaction. Entries are only checked against
+// the database for duplicates, not against the contract. All valid entries are inserted
+// in a single database transaction.
+pub async fn register_bulk_handler(
     State(state): State<AppState>,
-    Query(params): Query<HashMap<String, String>>,
+    Json(requests): Json<Vec<RegisterRequest>>,
 ) -> impl IntoResponse {
-    let name = params.get("name").cloned().unwrap_or_default();
-
-    if name.is_empty() {
-        return AppError::BadRequest("Name parameter is required".to_string()).into_response();
+    if state.read_only {
+        return AppError::Forbidden("Server is running in read-only mode".to_string())
+            .into_response();
+    }
+    // a batch has no secrets to reveal, it would bypass --require-commit
+    if state.commits.required {
+        return AppError::Forbidden(
+            "Bulk registration is disabled while registrations need a commitment".to_string(),
+        )
+        .into_response();
     }
+    let db = match database(&state) {
+        Ok(db) => db,
+        Err(e) => return e.into_response(),
+    };
 
-    // First, check in the database
-    info!("Looking up user '{}' in database", name);
-    match state.db.lookup_user(&name) {
-        Ok(Some(user)) => {
-            info!(
-                "User found in database: {} -> {} (version {})",
-                user.name, user.address, user.version
-            );
+    let mut results = Vec::with_capacity(requests.len());
+    let mut users = Vec::new();
+    let mut seen = std::collections::HashSet::new();
 
-            let response = LookupResponse {
-                address: user.address,
-                version: user.version,
-            };
+    for request in requests {
+        let check = if request.name.is_empty() {
+            Err("Name parameter is required".to_string())
+        } else if request.address.is_empty() {
+            Err("Address parameter is required".to_string())
+        } else if request_version(&state, &request.version).ok() != Some(RecordVersion::Web2) {
+            Err("Bulk registration only supports version 2".to_string())
+        } else if let Err(e) = Name::parse(&request.name, state.max_name_len).and_then(|name| {
+            // the parent check needs its own transaction, see `Database::insert_subname`
+            match name.parent() {
+                Some(parent) => Err(AppError::BadRequest(format!(
+                    "Subnames of '{}' must be registered one at a time",
+                    parent
+                ))),
+                None => Ok(()),
+            }
+        }) {
+            Err(e.to_string())
+        } else if let Err(e) = ensure_not_reserved(&state, &request.name) {
+            Err(e.to_string())
+        } else if !seen.insert(request.name.clone()) {
+            Err("Duplicate name in request".to_string())
+        } else {
+            match db.lookup_user(&request.name) {
+                Ok(Some(_)) => Err("User has already been registered.".to_string()),
+                Ok(None) => Ok(()),
+                Err(e) => Err(e.to_string()),
+            }
+        };
 
-            return (StatusCode::OK, Json(response)).into_response();
+        match check {
+            Ok(()) => {
+                users.push(User {
+                    name: request.name.clone(),
+                    address: request.address,
+                    version: RecordVersion::Web2,
+                });
+                results.push(BulkRegisterResult {
+                    name: request.name,
+                    success: true,
+                    error: None,
+                });
+            }
+            Err(error) => results.push(BulkRegisterResult {
+                name: request.name,
+                success: false,
+                error: Some(error),
+            }),
         }
-        Ok(None) => {
-            info!(
-                "User '{}' not found in database, checking smart contract",
-                name
-            );
-            // User not in database, continue to smart contract check
+    }
+
+    // Insert all valid entries at once, failing all of them if the transaction fails
+    let saved = if users.is_empty() {
+        Ok(())
+    } else {
+        db.insert_users(&users)
+    };
+    match saved {
+        Ok(()) => {
+            for user in &users {
+                remember_name(&state, &user.name);
+            }
         }
         Err(e) => {
-            // Log the database error but continue to smart contract
-            info!("Database error during lookup: {}, trying smart contract", e);
+            info!("Failed to save bulk users: {}", e);
+            for result in results.iter_mut().filter(|result| result.success) {
+                result.success = false;
+                result.error = Some("Failed to save user to database".to_string());
+            }
         }
     }
 
-    // If we reach here, check the smart contract
-    info!("Checking smart contract for user '{}'", name);
-    let (tx, rx) = oneshot::channel();
-    let request = ClientRequest::Lookup {
-        params,
-        respond: tx,
+    let registered = results.iter().filter(|result| result.success).count();
+    let response = BulkRegisterResponse {
+        registered,
+        failed: results.len() - registered,
+        results,
     };
 
-    // Send the request to the client handler
-    if let Err(_) = state.tx.send(request).await {
-        return AppError::Internal("Failed to process request".to_string()).into_response();
-    }
+    (StatusCode::OK, Json(response)).into_response()
+}
 
-    // Wait for the response
-    match rx.await {
-        Ok(result) => match result {
-            Ok(response) => (StatusCode::OK, Json(response)).into_response(),
-            Err(err) => {
-                info!("User not found in smart contract or lookup error");
-                err.into_response()
+// Run a registration, replaying the cached response for a repeated `Idempotency-Key`
+async fn register_idempotent(
+    state: AppState,
+    headers: &HeaderMap,
+    request: RegisterRequest,
+) -> Response {
+    let key = headers
+        .get("idempotency-key")
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+
+    // a key only replays the request it was first used for
+    let fingerprint =
+        serde_json::json!([request.name, request.address, request.version]).to_string();
+
+    let result = match key {
+        None => register_name(&state, request).await,
+        Some(key) if key.len() > idempotency::MAX_KEY_LEN => Err(AppError::BadRequest(format!(
+            "Idempotency-Key is longer than {} bytes",
+            idempotency::MAX_KEY_LEN
+        ))),
+        Some(key) => match state.idempotency.claim(&key, &fingerprint) {
+            Claim::Done(response) => {
+                info!("Replaying registration for idempotency key '{}'", key);
+                Ok(response)
+            }
+            Claim::InFlight => Err(AppError::Conflict(
+                "A request with this idempotency key is already in progress".to_string(),
+            )),
+            Claim::Mismatch => Err(AppError::Coded {
+                code: ErrorCode::IdempotencyKeyReused,
+                message: "This idempotency key was used for a different registration".to_string(),
+            }),
+            Claim::Full => Err(AppError::ServiceUnavailable(
+                "Too many idempotency keys are held, retry later".to_string(),
+            )),
+            Claim::New => {
+                let result = register_name(&state, request).await;
+                // a replay goes to whoever repeats the key, so it never carries the secret
+                let replay = result.as_ref().ok().map(|response| RegisterResponse {
+                    owner_token: None,
+                    ..response.clone()
+                });
+                state.idempotency.complete(&key, replay);
+                result
             }
         },
-        Err(_) => AppError::Internal("Failed to receive response".to_string()).into_response(),
+    };
+
+    match result {
+        Ok(response) => (StatusCode::OK, Json(response)).into_response(),
+        Err(err) => err.into_response(),
+    }
+}
+
+// Version of a registration, the configured default when the request names none
+fn request_version(state: &AppState, version: &str) -> Result<RecordVersion> {
+    match (version, state.default_version) {
+        ("", Some(default)) => Ok(default),
+        ("", None) => Err(AppError::Coded {
+            code: ErrorCode::MissingParameter,
+            message: "Version parameter is required".to_string(),
+        }),
+        (version, _) => version.parse(),
+    }
+}
+
+// Account recorded as the owner of a version 2.5 registration, if the request names one.
+// The owner is declared rather than proven, so nothing may authorize changes against it.
+fn registrant(version: RecordVersion, owner: Option<&str>) -> Result<Option<AccountId>> {
+    match (version, owner) {
+        (RecordVersion::Web2_5, None | Some("")) => Ok(None),
+        (RecordVersion::Web2_5, Some(owner)) => {
+            AccountId::from_hex(owner)
+                .map(Some)
+                .map_err(|e| AppError::Coded {
+                    code: ErrorCode::InvalidAddress,
+                    message: format!("Owner '{}' is not an account id: {}", owner, e),
+                })
+        }
+        _ => Ok(None),
+    }
+}
+
+// Conflict for a name that is taken, describing its current registration when known
+fn already_registered(existing: Option<ExistingRegistration>) -> AppError {
+    AppError::AlreadyRegistered {
+        message: "User has already been registered.".to_string(),
+        existing,
+    }
+}
+
+// Current on-chain registration of a name, if it can be read back
+async fn contract_registration(state: &AppState, name: &Name) -> Option<ExistingRegistration> {
+    let params = LookupParams {
+        name: name.to_string(),
+        source: LookupSource::Contract,
+        record_type: None,
+        soft_not_found: false,
+        include_history: false,
+        coin: None,
+    };
+    lookup_name(state, params)
+        .await
+        .ok()
+        .map(|response| ExistingRegistration {
+            address: response.address,
+            version: response.version,
+        })
+}
+
+impl From<User> for ExistingRegistration {
+    fn from(user: User) -> Self {
+        Self {
+            address: user.address,
+            version: user.version,
+        }
+    }
+}
+
+// Shared registration logic for the query and JSON body routes
+pub(crate) async fn register_name(
+    state: &AppState,
+    request: RegisterRequest,
+) -> Result<RegisterResponse> {
+    if state.read_only {
+        return Err(AppError::Forbidden(
+            "Server is running in read-only mode".to_string(),
+        ));
+    }
+
+    let RegisterRequest {
+        name,
+        address,
+        version,
+        parent_token,
+        secret,
+        owner,
+    } = request;
+
+    if name.is_empty() {
+        return Err(AppError::Coded {
+            code: ErrorCode::MissingParameter,
+            message: "Name parameter is required".to_string(),
+        });
+    }
+
+    if address.is_empty() {
+        return Err(AppError::Coded {
+            code: ErrorCode::MissingParameter,
+            message: "Address parameter is required".to_string(),
+        });
+    }
+
+    // unknown versions fail here, before any lookup or contract round trip
+    let version = request_version(state, &version)?;
+    // so do versions this server cannot store, before a commitment is revealed for them
+    match version {
+        RecordVersion::Web2 if state.db.is_none() => {
+            return Err(AppError::BadRequest(
+                "Version 2 registrations need a database, this server runs with --no-db"
+                    .to_string(),
+            ));
+        }
+        RecordVersion::Web2 | RecordVersion::Web2_5 => {}
+        RecordVersion::Web3 => {
+            return Err(AppError::Coded {
+                code: ErrorCode::InvalidVersion,
+                message: "The server can only process Web2 or Web2.5 requests".to_string(),
+            });
+        }
+    }
+
+    let name = Name::parse(&name, state.max_name_len)?;
+    sentry::configure_scope(|scope| scope.set_tag("name", &name));
+    ensure_not_reserved(state, &name)?;
+
+    // subnames live in the database only for now, on-chain subnames need contract support
+    let parent = match name.parent() {
+        None => None,
+        Some(_) if version != RecordVersion::Web2 => {
+            return Err(AppError::BadRequest(format!(
+                "Subname '{}' can only be registered with version 2",
+                name
+            )));
+        }
+        Some(parent) => match parent_token.as_deref() {
+            None | Some("") => {
+                return Err(AppError::BadRequest(format!(
+                    "Registering subname '{}' needs parent_token, the owner token of '{}'",
+                    name, parent
+                )));
+            }
+            Some(parent_token) => Some((parent.to_string(), hash_owner_token(parent_token))),
+        },
+    };
+
+    let owner = registrant(version, owner.as_deref())?;
+
+    let revealed = reveal_commitment(state, &name, secret.as_deref())?;
+    let result = register_checked(state, name, address, version, owner, parent).await;
+    // a registration that fails after the reveal hands the commitment back for a retry
+    if let (Err(_), Some((commitment, created_at))) = (&result, revealed) {
+        restore_commitment(state, &commitment, created_at);
+    }
+    result
+}
+
+// Registration of a validated request, `owner` is the declared owner of a version 2.5 name and
+// `parent` holds the parent of a subname and the hash of the owner token it was
+// registered with
+async fn register_checked(
+    state: &AppState,
+    name: Name,
+    address: String,
+    version: RecordVersion,
+    owner: Option<AccountId>,
+    parent: Option<(String, String)>,
+) -> Result<RegisterResponse> {
+    // Check if user already exists in database
+    if let Some(Ok(Some(user))) = state.db.as_ref().map(|db| db.lookup_user(&name)) {
+        info!(
+            "Failed to register user: {} user has already been registered in database.",
+            name
+        );
+        return Err(already_registered(Some(user.into())));
+    }
+
+    ensure_ready(state)?;
+
+    // Registrations serialize on the client worker, shed load instead of queueing for minutes
+    let Ok(_permit) = state.register_permits.try_acquire() else {
+        info!("Rejecting registration of '{}', too many in flight", name);
+        return Err(AppError::ServiceUnavailable(
+            "Too many registrations in progress, try again later".to_string(),
+        ));
+    };
+
+    let request_id = next_request_id();
+    info!(
+        "Dispatching contract checks for '{}' as request {}",
+        name, request_id
+    );
+
+    let (tx, rx) = oneshot::channel();
+    let request = ClientRequest::Availability {
+        request_id,
+        name: name.clone(),
+        respond: tx,
+    };
+
+    // Check the smart contract, a failed check must not be mistaken for availability
+    dispatch(&state.tx, request)?;
+
+    match rx.await {
+        Ok(Ok(true)) => {}
+        Ok(Ok(false)) => {
+            info!(
+                "Failed to register user: {} user has already been registered in smart contract.",
+                name
+            );
+            let existing = if state.expose_contract_conflicts {
+                contract_registration(state, &name).await
+            } else {
+                None
+            };
+            return Err(already_registered(existing));
+        }
+        Ok(Err(e)) => return Err(e),
+        Err(_) => return Err(AppError::Internal("Failed to receive response".to_string())),
+    }
+
+    if version == RecordVersion::Web2 {
+        let db = database(state)?;
+
+        // Instantiate User
+        let user = User {
+            name: name.to_string(),
+            address: address.clone(),
+            version,
+        };
+        let owner_token = new_owner_token();
+        let owner_token_hash = hash_owner_token(&owner_token);
+
+        // Save user to database, the unique name constraint settles concurrent registrations
+        let saved = match &parent {
+            Some((parent, parent_token_hash)) => {
+                db.insert_subname(&user, parent, parent_token_hash, &owner_token_hash)
+            }
+            None => db.insert_owned_user(&user, Some(&owner_token_hash)),
+        };
+        match saved {
+            Ok(()) => {}
+            Err(AppError::Conflict(_)) => {
+                let existing = db.lookup_user(&name).ok().flatten();
+                return Err(already_registered(existing.map(Into::into)));
+            }
+            Err(e @ (AppError::NotFound(_) | AppError::Forbidden(_))) => return Err(e),
+            Err(e) => {
+                info!("Failed to save user: {}", e);
+                return Err(AppError::Database(
+                    "Failed to save user to database".to_string(),
+                ));
+            }
+        }
+        remember_name(state, &user.name);
+
+        Ok(RegisterResponse {
+            name: user.name,
+            address: user.address,
+            version: user.version,
+            transaction_id: None,
+            owner: None,
+            storage_key: None,
+            owner_token: Some(owner_token),
+        })
+    } else if version == RecordVersion::Web2_5 {
+        // Two concurrent registrations can both pass the checks above, the contract applies
+        // them in chain order and the later transaction overwrites the earlier entry
+        let (tx, rx) = oneshot::channel();
+
+        let request = ClientRequest::Register {
+            request_id,
+            name,
+            address,
+            owner,
+            respond: tx,
+        };
+
+        // Send the request to the client handler
+        dispatch(&state.tx, request)?;
+
+        // Wait for the response
+        let response = rx
+            .await
+            .map_err(|_| AppError::Internal("Failed to receive response".to_string()))??;
+
+        // Keep a record of the transaction for the admin endpoint
+        let recorded = state
+            .db
+            .as_ref()
+            .zip(response.transaction_id.as_ref())
+            .map(|(db, transaction_id)| db.insert_transaction(&response.name, transaction_id));
+        if let Some(Err(e)) = recorded {
+            info!("Failed to record transaction: {}", e);
+        }
+        remember_name(state, &response.name);
+
+        Ok(response)
+    } else {
+        Err(AppError::Internal(format!(
+            "Unsupported version {} past request validation",
+            version
+        )))
+    }
+}
+
+pub async fn lookup_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(params): Query<LookupParams>,
+) -> impl IntoResponse {
+    // Clients that treat 404 as a hard failure can ask for a 200 with `found: false`
+    let soft_not_found = params.soft_not_found;
+
+    match lookup_name(&state, params).await {
+        // `curl -H 'Accept: text/plain'` gets the bare address for shell scripts
+        Ok(response) if accepts_plain_text(&headers) => (
+            StatusCode::OK,
+            [
+                (header::CACHE_CONTROL, public_cache_control(&state)),
+                (
+                    header::CONTENT_TYPE,
+                    "text/plain; charset=utf-8".to_string(),
+                ),
+            ],
+            response.address,
+        )
+            .into_response(),
+        Ok(response) if soft_not_found => (
+            StatusCode::OK,
+            [(header::CACHE_CONTROL, public_cache_control(&state))],
+            Json(SoftLookupResponse {
+                found: true,
+                lookup: Some(response),
+            }),
+        )
+            .into_response(),
+        Ok(response) => (
+            StatusCode::OK,
+            [(header::CACHE_CONTROL, public_cache_control(&state))],
+            Json(response),
+        )
+            .into_response(),
+        Err(AppError::NotFound(_)) if soft_not_found => (
+            StatusCode::OK,
+            [(header::CACHE_CONTROL, "no-store".to_string())],
+            Json(SoftLookupResponse {
+                found: false,
+                lookup: None,
+            }),
+        )
+            .into_response(),
+        Err(e) => uncached_error(e),
+    }
+}
+
+// Whether the `Accept` header lists `text/plain` before any JSON media type. JSON stays
+// the default for a missing header or `*/*`
+fn accepts_plain_text(headers: &HeaderMap) -> bool {
+    let Some(accept) = headers.get(header::ACCEPT).and_then(|v| v.to_str().ok()) else {
+        return false;
+    };
+    for media_type in accept.split(',') {
+        match media_type.split(';').next().unwrap_or("").trim() {
+            "text/plain" => return true,
+            "application/json" | "application/*" | "*/*" => return false,
+            _ => {}
+        }
+    }
+    false
+}
+
+fn public_cache_control(state: &AppState) -> String {
+    format!("public, max-age={}", state.lookup_cache_ttl_secs)
+}
+
+// Unknown names must not be cached so a later registration is visible immediately
+fn uncached_error(e: AppError) -> Response {
+    let not_found = matches!(e, AppError::NotFound(_));
+    let mut response = e.into_response();
+    if not_found {
+        response
+            .headers_mut()
+            .insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    }
+    response
+}
+
+// Resolve a name from the database or the contract, honouring the `source` and
+// `record_type` lookup parameters
+pub(crate) async fn lookup_name(state: &AppState, params: LookupParams) -> Result<LookupResponse> {
+    let name = Name::parse(&params.name, state.max_name_len)?;
+    sentry::configure_scope(|scope| scope.set_tag("name", &name));
+    let record_type = params.record_type;
+    let include_history = params.include_history;
+
+    // Addresses on other chains only live in the database, next to a registered name
+    let coin = params.coin.as_deref().map(parse_coin).transpose()?;
+    if let Some(coin) = coin.filter(|coin| coin != MIDEN_COIN) {
+        if record_type.is_some() {
+            return Err(AppError::BadRequest(
+                "The coin and record_type parameters cannot be combined".to_string(),
+            ));
+        }
+        return coin_response(database(state)?, &name, coin);
+    }
+
+    // Without a database every lookup goes to the contract
+    let source = match params.source {
+        LookupSource::Db => {
+            database(state)?;
+            LookupSource::Db
+        }
+        _ if state.db.is_none() => LookupSource::Contract,
+        source => source,
+    };
+
+    // Fast path for names that were definitely never registered through this server
+    if source != LookupSource::Contract && !might_be_registered(state, &name) {
+        info!("User '{}' rejected by bloom filter", name);
+        return Err(AppError::NotFound(format!(
+            "Name '{}' not registered",
+            name
+        )));
+    }
+
+    match (source, state.lookup_order) {
+        (LookupSource::Contract, _) => lookup_in_contract(state, &name, record_type).await,
+        (LookupSource::Db, _) => {
+            let db = database(state)?;
+            info!("Looking up user '{}' in database", name);
+            match db.lookup_user(&name)? {
+                Some(user) => {
+                    database_response(db, &name, user, record_type.as_deref(), include_history)
+                }
+                None => Err(AppError::NotFound(format!(
+                    "Name '{}' not found in database",
+                    name
+                ))),
+            }
+        }
+        (LookupSource::Auto, LookupOrder::DbFirst) => {
+            let db = database(state)?;
+            info!("Looking up user '{}' in database", name);
+            match db.lookup_user(&name) {
+                Ok(Some(user)) => {
+                    return database_response(
+                        db,
+                        &name,
+                        user,
+                        record_type.as_deref(),
+                        include_history,
+                    );
+                }
+                Ok(None) => info!(
+                    "User '{}' not found in database, checking smart contract",
+                    name
+                ),
+                // Log the database error but continue to smart contract
+                Err(e) => info!("Database error during lookup: {}, trying smart contract", e),
+            }
+            lookup_in_contract(state, &name, record_type).await
+        }
+        (LookupSource::Auto, LookupOrder::ContractFirst) => {
+            let err = match lookup_in_contract(state, &name, record_type.clone()).await {
+                Ok(response) => return Ok(response),
+                Err(e) => e,
+            };
+            // Database names are not in the contract, and an unreachable contract should
+            // not take them down with it
+            info!(
+                "Contract lookup of '{}' failed ({}), checking database",
+                name, err
+            );
+            let db = database(state)?;
+            match db.lookup_user(&name) {
+                Ok(Some(user)) => {
+                    database_response(db, &name, user, record_type.as_deref(), include_history)
+                }
+                Ok(None) => Err(err),
+                Err(e) => {
+                    info!("Database error during lookup: {}", e);
+                    Err(err)
+                }
+            }
+        }
+    }
+}
+
+// Lookup response for a user found in the database
+fn database_response(
+    db: &Database,
+    name: &Name,
+    user: User,
+    record_type: Option<&str>,
+    include_history: bool,
+) -> Result<LookupResponse> {
+    info!(
+        "User found in database: {} -> {} (version {})",
+        user.name, user.address, user.version
+    );
+
+    let records = select_records(name, user_records(db, &user)?, record_type)?;
+    let history = if include_history {
+        Some(past_addresses(db.name_history(&user.name, 0, u32::MAX)?))
+    } else {
+        None
+    };
+    Ok(LookupResponse {
+        address: match record_type {
+            Some(_) => records[0].value.clone(),
+            None => user.address,
+        },
+        version: user.version,
+        records,
+        source: LookupOrigin::Database,
+        history,
+        coin: None,
+    })
+}
+
+// Resolver records of a database name
+fn user_records(db: &Database, user: &User) -> Result<Vec<ResolverRecord>> {
+    // Rows registered before records existed only have their primary address
+    Ok(match db.lookup_records(&user.name)? {
+        records if !records.is_empty() => records,
+        _ => vec![ResolverRecord {
+            record_type: record_type_for_version(user.version).to_string(),
+            value: user.address.clone(),
+        }],
+    })
+}
+
+// Lowercase a coin identifier, which is a short alphanumeric ticker such as `btc`
+fn parse_coin(coin: &str) -> Result<String> {
+    let valid = (1..=16).contains(&coin.len()) && coin.chars().all(|c| c.is_ascii_alphanumeric());
+    if !valid {
+        return Err(AppError::BadRequest(format!(
+            "Coin '{}' must be 1 to 16 ASCII letters or digits",
+            coin
+        )));
+    }
+    Ok(coin.to_ascii_lowercase())
+}
+
+// Lookup response for the address of a database name on another chain
+fn coin_response(db: &Database, name: &Name, coin: String) -> Result<LookupResponse> {
+    let Some(user) = db.lookup_user(name)? else {
+        return Err(AppError::NotFound(format!(
+            "Name '{}' not found in database",
+            name
+        )));
+    };
+    let Some(address) = db.coin_address(name, &coin)? else {
+        return Err(AppError::NotFound(format!(
+            "Name '{}' has no {} address",
+            name, coin
+        )));
+    };
+
+    Ok(LookupResponse {
+        address,
+        version: user.version,
+        records: Vec::new(),
+        source: LookupOrigin::Database,
+        history: None,
+        coin: Some(coin),
+    })
+}
+
+// Addresses a name pointed at before its current one, every update replaces one
+fn past_addresses(entries: Vec<HistoryEntry>) -> Vec<PastAddress> {
+    entries
+        .into_iter()
+        .filter_map(|entry| {
+            Some(PastAddress {
+                address: entry.old_address?,
+                replaced_at: entry.at,
+            })
+        })
+        .collect()
+}
+
+// Contract half of a lookup, answered by the client worker
+async fn lookup_in_contract(
+    state: &AppState,
+    name: &Name,
+    record_type: Option<String>,
+) -> Result<LookupResponse> {
+    ensure_ready(state)?;
+
+    // Concurrent lookups of the same name share a single contract query
+    let worker = state.tx.clone();
+    let flight_name = name.clone();
+    let result = state
+        .lookup_flights
+        .run(name, move || async move {
+            let request_id = next_request_id();
+            info!(
+                "Checking smart contract for user '{}' as request {}",
+                flight_name, request_id
+            );
+            let (tx, rx) = oneshot::channel();
+            let request = ClientRequest::Lookup {
+                request_id,
+                name: flight_name,
+                respond: tx,
+            };
+
+            // Send the request to the client handler
+            dispatch(&worker, request)?;
+
+            // Wait for the response
+            rx.await.unwrap_or_else(|_| {
+                Err(AppError::Internal("Failed to receive response".to_string()))
+            })
+        })
+        .await;
+
+    match result {
+        Ok(mut response) => {
+            response.records = select_records(name, response.records, record_type.as_deref())?;
+            Ok(response)
+        }
+        Err(err) => {
+            info!("User not found in smart contract or lookup error");
+            Err(err)
+        }
+    }
+}
+
+// Liveness by default, `deep=true` also checks that the worker can reach the Miden RPC
+pub async fn health_handler(
+    State(state): State<AppState>,
+    Query(params): Query<HashMap<String, String>>,
+) -> impl IntoResponse {
+    let ready = state.ready.load(Ordering::Acquire);
+    let deep = params.get("deep").is_some_and(|v| v == "true");
+
+    if !deep {
+        let response = HealthResponse {
+            status: "ok".to_string(),
+            ready,
+            rpc_ok: None,
+            block_height: None,
+        };
+        return (StatusCode::OK, Json(response)).into_response();
+    }
+
+    if let Err(e) = ensure_ready(&state) {
+        return e.into_response();
+    }
+
+    let (tx, rx) = oneshot::channel();
+    let request = ClientRequest::Health {
+        request_id: next_request_id(),
+        respond: tx,
+    };
+
+    if let Err(e) = dispatch(&state.tx, request) {
+        return e.into_response();
+    }
+
+    let health = match rx.await {
+        Ok(health) => health,
+        Err(_) => {
+            return AppError::Internal("Failed to receive response".to_string()).into_response();
+        }
+    };
+
+    let status = if health.rpc_ok {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    let response = HealthResponse {
+        status: if health.rpc_ok { "ok" } else { "degraded" }.to_string(),
+        ready,
+        rpc_ok: Some(health.rpc_ok),
+        block_height: health.block_height,
+    };
+
+    (status, Json(response)).into_response()
+}
+
+// Resolve a name into the ENS-style shape wallet tooling already understands
+pub async fn resolve_handler(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> impl IntoResponse {
+    let params = LookupParams {
+        name: name.clone(),
+        source: LookupSource::Auto,
+        record_type: None,
+        soft_not_found: false,
+        include_history: false,
+        coin: None,
+    };
+
+    match lookup_name(&state, params).await {
+        Ok(response) => {
+            let response = ResolveResponse {
+                name,
+                address: response.address,
+                coin_type: MIDEN_COIN_TYPE,
+                ttl: state.lookup_cache_ttl_secs,
+            };
+            (
+                StatusCode::OK,
+                [(header::CACHE_CONTROL, public_cache_control(&state))],
+                Json(response),
+            )
+                .into_response()
+        }
+        Err(e) => uncached_error(e),
+    }
+}
+
+// Resolver record type holding the primary address of each registration version
+pub fn record_type_for_version(version: RecordVersion) -> &'static str {
+    match version {
+        RecordVersion::Web2 => "web2",
+        RecordVersion::Web2_5 => "web2.5",
+        RecordVersion::Web3 => "web3",
+    }
+}
+
+// Keep only the records of the requested type, failing if there are none
+fn select_records(
+    name: &str,
+    records: Vec<ResolverRecord>,
+    record_type: Option<&str>,
+) -> Result<Vec<ResolverRecord>> {
+    let Some(record_type) = record_type else {
+        return Ok(records);
+    };
+
+    let records: Vec<ResolverRecord> = records
+        .into_iter()
+        .filter(|record| record.record_type == record_type)
+        .collect();
+
+    if records.is_empty() {
+        return Err(AppError::NotFound(format!(
+            "Name '{}' has no {} record",
+            name, record_type
+        )));
+    }
+
+    Ok(records)
+}
+
+// Point a database-backed name at a new address, keeping its registration date. Only the
+// holder of its owner token can move it.
+pub async fn update_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(UpdateParams { name, address }): Query<UpdateParams>,
+) -> impl IntoResponse {
+    let db = match database(&state) {
+        Ok(db) => db,
+        Err(e) => return e.into_response(),
+    };
+
+    if state.read_only {
+        return AppError::Forbidden("Server is running in read-only mode".to_string())
+            .into_response();
+    }
+
+    if name.is_empty() {
+        return AppError::Coded {
+            code: ErrorCode::MissingParameter,
+            message: "Name parameter is required".to_string(),
+        }
+        .into_response();
+    }
+
+    if address.is_empty() {
+        return AppError::Coded {
+            code: ErrorCode::MissingParameter,
+            message: "Address parameter is required".to_string(),
+        }
+        .into_response();
+    }
+
+    let mut user = match db.lookup_user(&name) {
+        Ok(Some(user)) => user,
+        Ok(None) => {
+            return AppError::NotFound(format!("Name '{}' not found in database", name))
+                .into_response();
+        }
+        Err(e) => return e.into_response(),
+    };
+
+    // the contract entry of an on-chain name would keep pointing at the old address
+    if user.version != RecordVersion::Web2 {
+        return AppError::BadRequest(format!(
+            "Name '{}' is registered on-chain and cannot be updated",
+            name
+        ))
+        .into_response();
+    }
+
+    if let Err(e) = authorize_owner(&state, db, &headers, &name) {
+        return e.into_response();
+    }
+
+    match db.update_address(&name, &address) {
+        Ok(true) => {}
+        Ok(false) => {
+            return AppError::NotFound(format!("Name '{}' not found in database", name))
+                .into_response();
+        }
+        Err(e) => return e.into_response(),
+    }
+
+    // every record of the name, with the primary one just moved
+    user.address = address;
+    let records = match user_records(db, &user) {
+        Ok(records) => records,
+        Err(e) => return e.into_response(),
+    };
+    let response = LookupResponse {
+        records,
+        address: user.address,
+        version: user.version,
+        source: LookupOrigin::Database,
+        history: None,
+        coin: None,
+    };
+
+    (StatusCode::OK, Json(response)).into_response()
+}
+
+// Add or replace a resolver record on a name registered in the database, only for the
+// holder of its owner token
+pub async fn records_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
+) -> impl IntoResponse {
+    let db = match database(&state) {
+        Ok(db) => db,
+        Err(e) => return e.into_response(),
+    };
+
+    if state.read_only {
+        return AppError::Forbidden("Server is running in read-only mode".to_string())
+            .into_response();
+    }
+
+    let name = params.get("name").cloned().unwrap_or_default();
+    let record_type = params.get("type").cloned().unwrap_or_default();
+    let value = params.get("value").cloned().unwrap_or_default();
+
+    if name.is_empty() {
+        return AppError::Coded {
+            code: ErrorCode::MissingParameter,
+            message: "Name parameter is required".to_string(),
+        }
+        .into_response();
+    }
+
+    if !matches!(record_type.as_str(), "web2" | "web2.5" | "web3") {
+        return AppError::BadRequest(
+            "Type parameter must be one of: web2, web2.5, web3".to_string(),
+        )
+        .into_response();
+    }
+
+    if value.is_empty() {
+        return AppError::Coded {
+            code: ErrorCode::MissingParameter,
+            message: "Value parameter is required".to_string(),
+        }
+        .into_response();
+    }
+
+    let user = match db.lookup_user(&name) {
+        Ok(Some(user)) => user,
+        Ok(None) => {
+            return AppError::NotFound(format!("Name '{}' not found in database", name))
+                .into_response();
+        }
+        Err(e) => return e.into_response(),
+    };
+
+    // the primary record mirrors the name's address, which only `/update` changes along
+    // with its history
+    if record_type == record_type_for_version(user.version) {
+        return AppError::BadRequest(format!(
+            "The {} record of '{}' is its address, change it through /update",
+            record_type, name
+        ))
+        .into_response();
+    }
+
+    if let Err(e) = authorize_owner(&state, db, &headers, &name) {
+        return e.into_response();
+    }
+
+    if let Err(e) = db.insert_record(&name, &record_type, &value) {
+        return e.into_response();
+    }
+
+    match db.lookup_records(&name) {
+        Ok(records) => (StatusCode::OK, Json(RecordsResponse { name, records })).into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+// Set the address of a database name on another chain, the Miden address is changed
+// through `/update` instead. Like `/update`, it needs the name's owner token.
+pub async fn coins_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
+) -> impl IntoResponse {
+    match set_coin_address(&state, &headers, &params) {
+        Ok(response) => (StatusCode::OK, Json(response)).into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+fn set_coin_address(
+    state: &AppState,
+    headers: &HeaderMap,
+    params: &HashMap<String, String>,
+) -> Result<CoinAddressResponse> {
+    let db = database(state)?;
+    if state.read_only {
+        return Err(AppError::Forbidden(
+            "Server is running in read-only mode".to_string(),
+        ));
+    }
+
+    let param = |key: &str| params.get(key).cloned().unwrap_or_default();
+    let (name, coin, address) = (param("name"), param("coin"), param("address"));
+    for (key, value) in [("Name", &name), ("Coin", &coin), ("Address", &address)] {
+        if value.is_empty() {
+            return Err(AppError::Coded {
+                code: ErrorCode::MissingParameter,
+                message: format!("{} parameter is required", key),
+            });
+        }
+    }
+
+    let coin = parse_coin(&coin)?;
+    if coin == MIDEN_COIN {
+        return Err(AppError::BadRequest(
+            "The Miden address is changed through /update".to_string(),
+        ));
+    }
+
+    if db.lookup_user(&name)?.is_none() {
+        return Err(AppError::NotFound(format!(
+            "Name '{}' not found in database",
+            name
+        )));
+    }
+    authorize_owner(state, db, headers, &name)?;
+    db.set_coin_address(&name, &coin, &address)?;
+
+    Ok(CoinAddressResponse {
+        name,
+        coin,
+        address,
+    })
+}
+
+// Check the `x-owner-token` header against the owner token of `name`. The admin token
+// also passes, it is the only way to change names registered without an owner token.
+fn authorize_owner(state: &AppState, db: &Database, headers: &HeaderMap, name: &str) -> Result<()> {
+    if authorize_admin(state, headers).is_ok() {
+        return Ok(());
+    }
+
+    let Some(token) = headers
+        .get(OWNER_TOKEN_HEADER)
+        .and_then(|value| value.to_str().ok())
+    else {
+        return Err(AppError::Unauthorized("Missing owner token".to_string()));
+    };
+
+    match db.owner_token_hash(name)? {
+        Some(hash) if hash == hash_owner_token(token) => Ok(()),
+        _ => Err(AppError::Forbidden(format!(
+            "Only the owner of '{}' can change it",
+            name
+        ))),
+    }
+}
+
+// Check the `Authorization: Bearer <token>` header against the configured admin token
+fn authorize_admin(state: &AppState, headers: &HeaderMap) -> Result<()> {
+    let Some(expected) = &state.admin_token else {
+        return Err(AppError::Unauthorized(
+            "Admin endpoints are disabled".to_string(),
+        ));
+    };
+
+    let provided = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+
+    match provided {
+        Some(token) if secrets_match(token, expected) => Ok(()),
+        Some(_) => Err(AppError::Unauthorized("Invalid admin token".to_string())),
+        None => Err(AppError::Unauthorized("Missing admin token".to_string())),
+    }
+}
+
+// Rows `/export.ndjson` reads per query, also how many it buffers ahead of a slow client
+const EXPORT_NDJSON_PAGE_SIZE: u32 = 500;
+
+// Every database user as one JSON object per line, streamed so the table is never held in
+// memory. Needs the admin token
+pub async fn export_ndjson_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    if let Err(e) = database(&state).and_then(|_| authorize_admin(&state, &headers)) {
+        return e.into_response();
+    }
+    let db = state
+        .db
+        .clone()
+        .expect("database() checked the database is set");
+
+    let rows = stream::unfold(
+        db.stream_users(EXPORT_NDJSON_PAGE_SIZE),
+        |mut rx| async move {
+            let user = rx.recv().await?;
+            Some((user, rx))
+        },
+    )
+    .map(|user| {
+        // an error ends the body early, the client sees a truncated transfer
+        let user = user.inspect_err(|e| info!("Streaming export failed: {}", e))?;
+        let mut line =
+            serde_json::to_string(&user).map_err(|e| AppError::Internal(e.to_string()))?;
+        line.push('\n');
+        Ok::<_, AppError>(line)
+    });
+
+    (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, "application/x-ndjson")],
+        Body::from_stream(rows),
+    )
+        .into_response()
+}
+
+pub async fn admin_transactions_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
+) -> impl IntoResponse {
+    let db = match database(&state) {
+        Ok(db) => db,
+        Err(e) => return e.into_response(),
+    };
+
+    if let Err(e) = authorize_admin(&state, &headers) {
+        return e.into_response();
+    }
+
+    let limit = match params.get("limit").map(|limit| limit.parse::<u32>()) {
+        None => 20,
+        Some(Ok(limit)) => limit.min(1000),
+        Some(Err(_)) => {
+            return AppError::BadRequest("Limit must be a positive integer".to_string())
+                .into_response();
+        }
+    };
+
+    match db.recent_transactions(limit) {
+        Ok(transactions) => {
+            (StatusCode::OK, Json(TransactionsResponse { transactions })).into_response()
+        }
+        Err(e) => e.into_response(),
+    }
+}
+
+// Prefix search over database-backed names. Names that only exist in the contract
+// are not indexed and will not show up in the results.
+pub async fn search_handler(
+    State(state): State<AppState>,
+    Query(params): Query<HashMap<String, String>>,
+) -> impl IntoResponse {
+    let db = match database(&state) {
+        Ok(db) => db,
+        Err(e) => return e.into_response(),
+    };
+
+    let query = params.get("q").cloned().unwrap_or_default();
+
+    if query.is_empty() {
+        return AppError::Coded {
+            code: ErrorCode::MissingParameter,
+            message: "Query parameter q is required".to_string(),
+        }
+        .into_response();
+    }
+
+    let limit = match params.get("limit").map(|limit| limit.parse::<u32>()) {
+        None => 10,
+        Some(Ok(limit)) => limit.min(100),
+        Some(Err(_)) => {
+            return AppError::BadRequest("Limit must be a positive integer".to_string())
+                .into_response();
+        }
+    };
+
+    match db.search_users(&query, limit) {
+        Ok(names) => (StatusCode::OK, Json(SearchResponse { names })).into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+// Chronological changes to a database name, paginated with `offset` and `limit`
+pub async fn history_handler(
+    State(state): State<AppState>,
+    Query(params): Query<HashMap<String, String>>,
+) -> impl IntoResponse {
+    let db = match database(&state) {
+        Ok(db) => db,
+        Err(e) => return e.into_response(),
+    };
+
+    let name = params.get("name").cloned().unwrap_or_default();
+    if name.is_empty() {
+        return AppError::Coded {
+            code: ErrorCode::MissingParameter,
+            message: "Name parameter is required".to_string(),
+        }
+        .into_response();
+    }
+
+    let parse = |key: &str, default: u32| match params.get(key).map(|v| v.parse::<u32>()) {
+        None => Ok(default),
+        Some(Ok(value)) => Ok(value),
+        Some(Err(_)) => Err(AppError::BadRequest(format!(
+            "{} must be a positive integer",
+            key
+        ))),
+    };
+    let (offset, limit) = match (parse("offset", 0), parse("limit", 50)) {
+        (Ok(offset), Ok(limit)) => (offset, limit.clamp(1, 100)),
+        (Err(e), _) | (_, Err(e)) => return e.into_response(),
+    };
+
+    // one extra row tells whether there is a next page
+    let mut entries = match db.name_history(&name, offset, limit + 1) {
+        Ok(entries) => entries,
+        Err(e) => return e.into_response(),
+    };
+    let next_offset = if entries.len() > limit as usize {
+        entries.truncate(limit as usize);
+        Some(offset + limit)
+    } else {
+        None
+    };
+
+    let response = HistoryResponse {
+        name,
+        entries,
+        next_offset,
+    };
+    (StatusCode::OK, Json(response)).into_response()
+}
+
+// Run the contract lookup for a name and return the raw VM output stack, only served with
+// `--debug-endpoints`
+pub async fn debug_lookup_handler(
+    State(state): State<AppState>,
+    Query(params): Query<HashMap<String, String>>,
+) -> impl IntoResponse {
+    if !state.debug_endpoints {
+        return AppError::NotFound("Debug endpoints are disabled".to_string()).into_response();
+    }
+
+    let name = params.get("name").cloned().unwrap_or_default();
+    let name = match Name::parse(&name, state.max_name_len) {
+        Ok(name) => name,
+        Err(e) => return e.into_response(),
+    };
+
+    if let Err(e) = ensure_ready(&state) {
+        return e.into_response();
+    }
+
+    let request_id = next_request_id();
+    info!(
+        "Dispatching debug lookup of '{}' as request {}",
+        name, request_id
+    );
+
+    let (tx, rx) = oneshot::channel();
+    let request = ClientRequest::DebugLookup {
+        request_id,
+        name,
+        respond: tx,
+    };
+
+    // Send the request to the client handler
+    if let Err(e) = dispatch(&state.tx, request) {
+        return e.into_response();
+    }
+
+    // Wait for the response
+    match rx.await {
+        Ok(result) => match result {
+            Ok(response) => (StatusCode::OK, Json(response)).into_response(),
+            Err(err) => err.into_response(),
+        },
+        Err(_) => AppError::Internal("Failed to receive response".to_string()).into_response(),
+    }
+}
+
+// Report the block the client worker has synced to, only served with `--debug-endpoints`
+pub async fn debug_sync_handler(State(state): State<AppState>) -> impl IntoResponse {
+    if !state.debug_endpoints {
+        return AppError::NotFound("Debug endpoints are disabled".to_string()).into_response();
+    }
+
+    if let Err(e) = ensure_ready(&state) {
+        return e.into_response();
+    }
+
+    let (tx, rx) = oneshot::channel();
+    let request = ClientRequest::SyncInfo {
+        request_id: next_request_id(),
+        respond: tx,
+    };
+
+    if let Err(e) = dispatch(&state.tx, request) {
+        return e.into_response();
+    }
+
+    match rx.await {
+        Ok(Ok(response)) => (StatusCode::OK, Json(response)).into_response(),
+        Ok(Err(err)) => err.into_response(),
+        Err(_) => AppError::Internal("Failed to receive response".to_string()).into_response(),
+    }
+}
+
+// Show how a name is encoded into the contract map key, to diagnose lookup mismatches
+pub async fn debug_encode_handler(
+    State(state): State<AppState>,
+    Query(params): Query<HashMap<String, String>>,
+) -> impl IntoResponse {
+    let name = params.get("name").cloned().unwrap_or_default();
+
+    if name.is_empty() {
+        return AppError::Coded {
+            code: ErrorCode::MissingParameter,
+            message: "Name parameter is required".to_string(),
+        }
+        .into_response();
+    }
+
+    let name = match Name::parse(&name, state.max_name_len) {
+        Ok(name) => name,
+        Err(e) => return e.into_response(),
+    };
+
+    let word = name.to_word();
+    let decoded = match word_to_str(word) {
+        Ok(decoded) => decoded,
+        Err(e) => return e.into_response(),
+    };
+    let response = EncodeResponse {
+        decoded,
+        word: word_to_hex(word),
+        felts: word
+            .iter()
+            .map(|felt| format!("0x{:016x}", felt.as_int()))
+            .collect(),
+        name: name.into(),
+    };
+
+    (StatusCode::OK, Json(response)).into_response()
+}
+
+// Page through the full name -> address map of every shard in key order, for offline
+// indexers. Takes the same `cursor` as `/export`, which lists the raw words instead.
+pub async fn storage_handler(
+    State(state): State<AppState>,
+    Query(params): Query<HashMap<String, String>>,
+) -> impl IntoResponse {
+    let cursor = match params.get("cursor").map(|cursor| hex_to_word(cursor)) {
+        None => None,
+        Some(Ok(cursor)) => Some(cursor),
+        Some(Err(e)) => return e.into_response(),
+    };
+
+    let limit = match params.get("limit").map(|limit| limit.parse::<usize>()) {
+        None => 100,
+        Some(Ok(limit)) => limit.clamp(1, 1000),
+        Some(Err(_)) => {
+            return AppError::BadRequest("Limit must be a positive integer".to_string())
+                .into_response();
+        }
+    };
+
+    if let Err(e) = ensure_ready(&state) {
+        return e.into_response();
+    }
+
+    let request_id = next_request_id();
+    info!("Dispatching storage listing as request {}", request_id);
+
+    let (tx, rx) = oneshot::channel();
+    let request = ClientRequest::Storage {
+        request_id,
+        cursor,
+        limit,
+        respond: tx,
+    };
+
+    // Send the request to the client handler
+    if let Err(e) = dispatch(&state.tx, request) {
+        return e.into_response();
+    }
+
+    // Wait for the response
+    match rx.await {
+        Ok(result) => match result {
+            Ok(response) => (StatusCode::OK, Json(response)).into_response(),
+            Err(err) => err.into_response(),
+        },
+        Err(_) => AppError::Internal("Failed to receive response".to_string()).into_response(),
+    }
+}
+
+#[derive(Serialize)]
+pub struct VersionResponse {
+    pub version: &'static str,
+    /// Commit the server was built from, `unknown` outside a git checkout
+    pub git_sha: &'static str,
+    /// Empty until the contract accounts are imported or deployed
+    pub contract_ids: Vec<String>,
+}
+
+// Which build is running and against which contract
+pub async fn version_handler(State(state): State<AppState>) -> impl IntoResponse {
+    let response = VersionResponse {
+        version: env!("CARGO_PKG_VERSION"),
+        git_sha: env!("MNS_GIT_SHA"),
+        contract_ids: state.contract_ids.get().cloned().unwrap_or_default(),
+    };
+    (StatusCode::OK, Json(response))
+}
+
+#[derive(Serialize)]
+pub struct VerifyResponse {
+    /// SHA-256 of each MASM source file, to compare against the published sources
+    pub sources: BTreeMap<&'static str, String>,
+    pub code_commitment: String,
+}
+
+// Hashes of the MASM sources this server runs, so third parties can check them against a
+// published release without chain access
+pub async fn verify_handler() -> impl IntoResponse {
+    let sources = masm();
+    let code_commitment = match mns_code_commitment() {
+        Ok(commitment) => commitment,
+        Err(e) => return e.into_response(),
+    };
+
+    let response = VerifyResponse {
+        sources: sources.sha256(),
+        code_commitment,
+    };
+    (StatusCode::OK, Json(response)).into_response()
+}
+
+// Walk the raw storage maps of every shard page by page, for indexers mirroring the
+// registry
+pub async fn export_handler(
+    State(state): State<AppState>,
+    Query(params): Query<HashMap<String, String>>,
+) -> impl IntoResponse {
+    let cursor = match params.get("cursor").map(|cursor| hex_to_word(cursor)) {
+        None => None,
+        Some(Ok(cursor)) => Some(cursor),
+        Some(Err(e)) => return e.into_response(),
+    };
+
+    let limit = match params.get("limit").map(|limit| limit.parse::<usize>()) {
+        None => 100,
+        Some(Ok(limit)) => limit.clamp(1, 1000),
+        Some(Err(_)) => {
+            return AppError::BadRequest("Limit must be a positive integer".to_string())
+                .into_response();
+        }
+    };
+
+    if let Err(e) = ensure_ready(&state) {
+        return e.into_response();
+    }
+
+    let request_id = next_request_id();
+    info!("Dispatching storage export as request {}", request_id);
+
+    let (tx, rx) = oneshot::channel();
+    let request = ClientRequest::ExportStorage {
+        request_id,
+        cursor,
+        limit,
+        respond: tx,
+    };
+
+    if let Err(e) = dispatch(&state.tx, request) {
+        return e.into_response();
+    }
+
+    match rx.await {
+        Ok(Ok(response)) => (StatusCode::OK, Json(response)).into_response(),
+        Ok(Err(err)) => err.into_response(),
+        Err(_) => AppError::Internal("Failed to receive response".to_string()).into_response(),
+    }
+}
+
+pub async fn stats_handler(State(state): State<AppState>) -> impl IntoResponse {
+    let db = match database(&state) {
+        Ok(db) => db,
+        Err(e) => return e.into_response(),
+    };
+
+    let total = match db.count_users() {
+        Ok(total) => total,
+        Err(e) => return e.into_response(),
+    };
+
+    let by_version = match db.count_by_version() {
+        Ok(by_version) => by_version,
+        Err(e) => return e.into_response(),
+    };
+
+    (StatusCode::OK, Json(StatsResponse { total, by_version })).into_response()
+}
+
+// Latest state of a transaction submitted by this server, including its block once
+// committed so clients can count confirmations
+pub async fn transaction_handler(
+    State(state): State<AppState>,
+    Path(transaction_id): Path<String>,
+) -> impl IntoResponse {
+    match state.events.latest(&transaction_id) {
+        Some(event) => (StatusCode::OK, Json(event)).into_response(),
+        None => AppError::NotFound(format!("Transaction '{}' not found", transaction_id))
+            .into_response(),
+    }
+}
+
+// Server-Sent Events stream of status changes for a submitted registration transaction.
+// Emits the current status first and closes once the transaction is committed or failed.
+pub async fn events_handler(
+    State(state): State<AppState>,
+    Path(transaction_id): Path<String>,
+) -> impl IntoResponse {
+    // Subscribe before reading the current status so no transition is missed
+    let receiver = state.events.subscribe();
+    let Some(initial) = state.events.latest(&transaction_id) else {
+        return AppError::NotFound(format!("Transaction '{}' not found", transaction_id))
+            .into_response();
+    };
+    let status = initial.status;
+
+    let events = state.events.clone();
+    let updates = stream::unfold(
+        (receiver, status == TransactionState::Pending),
+        move |(mut receiver, open)| {
+            let transaction_id = transaction_id.clone();
+            let events = events.clone();
+            async move {
+                if !open {
+                    return None;
+                }
+                loop {
+                    let event = match receiver.recv().await {
+                        Ok(event) if event.transaction_id == transaction_id => event,
+                        Ok(_) => continue,
+                        // Missed some updates, fall back to the latest recorded status
+                        Err(RecvError::Lagged(_)) => match events.latest(&transaction_id) {
+                            Some(event) if event.status != TransactionState::Pending => event,
+                            _ => continue,
+                        },
+                        Err(RecvError::Closed) => return None,
+                    };
+                    let open = event.status == TransactionState::Pending;
+                    return Some((event, (receiver, open)));
+                }
+            }
+        },
+    );
+
+    let stream = stream::once(async move { initial })
+        .chain(updates)
+        .map(|event| Event::default().event("status").json_data(event));
+
+    Sse::new(stream)
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use axum::{
+        extract::{Path, Query, State},
+        http::{HeaderMap, HeaderValue, StatusCode, Uri, header},
+        response::{IntoResponse, Json},
+    };
+    use tokio::sync::{Semaphore, mpsc, oneshot};
+
+    use crate::auth::OWNER_TOKEN_HEADER;
+    use crate::commit::{CommitPolicy, commitment};
+    use crate::db::Database;
+    use crate::error::{AppError, ErrorCode, ExistingRegistration};
+    use crate::events::TransactionState;
+    use crate::handler::{
+        AppState, ClientRequest, LookupOrder, LookupOrigin, LookupParams, LookupResponse,
+        LookupSource, RegisterParams, RegisterRequest, ResolverRecord, SyncInfoResponse,
+        UpdateParams, User, coins_handler, commit, debug_sync_handler, export_ndjson_handler,
+        lookup_handler, lookup_name, records_handler, register_bulk_handler, register_json_handler,
+        register_name, registrant, transaction_handler, update_handler, version_handler,
+    };
+    use crate::reserved::ReservedNames;
+    use crate::serde::RecordVersion;
+
+    // State backed by an in-memory database and a stand-in worker that reports every
+    // name as free in the contract
+    fn test_state() -> AppState {
+        let (tx, mut rx) = mpsc::channel(16);
+        tokio::spawn(async move {
+            while let Some(request) = rx.recv().await {
+                if let ClientRequest::Availability { respond, .. } = request {
+                    let _ = respond.send(Ok(true));
+                }
+            }
+        });
+
+        AppState::for_tests(tx, Some(Arc::new(Database::new(":memory:").unwrap())))
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_register_of_same_name() {
+        let state = test_state();
+        let request = || RegisterRequest {
+            name: "alice.miden".to_string(),
+            address: "0xabc".to_string(),
+            version: "2".to_string(),
+            ..Default::default()
+        };
+
+        // both pass the database check before either inserts
+        let (first, second) = tokio::join!(
+            register_name(&state, request()),
+            register_name(&state, request())
+        );
+
+        assert!(first.is_ok() != second.is_ok());
+        // the loser learns who holds the name
+        match first.and(second) {
+            Err(AppError::AlreadyRegistered { existing, .. }) => assert_eq!(
+                existing,
+                Some(ExistingRegistration {
+                    address: "0xabc".to_string(),
+                    version: RecordVersion::Web2,
+                })
+            ),
+            other => panic!(
+                "expected AlreadyRegistered, got {:?}",
+                other.map(|r| r.name)
+            ),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_lookup_cache_control() {
+        let state = test_state();
+        state
+            .db
+            .as_ref()
+            .unwrap()
+            .insert_user(&User {
+                name: "alice.miden".to_string(),
+                address: "0xabc".to_string(),
+                version: RecordVersion::Web2,
+            })
+            .unwrap();
+
+        let lookup = |name: &str| {
+            let params = LookupParams {
+                name: name.to_string(),
+                source: LookupSource::Db,
+                ..Default::default()
+            };
+            lookup_handler(State(state.clone()), HeaderMap::new(), Query(params))
+        };
+
+        let found = lookup("alice.miden").await.into_response();
+        assert_eq!(found.status(), StatusCode::OK);
+        assert_eq!(
+            found.headers().get(header::CACHE_CONTROL).unwrap(),
+            "public, max-age=30"
+        );
+
+        let missing = lookup("bob.miden").await.into_response();
+        assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            missing.headers().get(header::CACHE_CONTROL).unwrap(),
+            "no-store"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_lookup_plain_text() {
+        let state = test_state();
+        state
+            .db
+            .as_ref()
+            .unwrap()
+            .insert_user(&User {
+                name: "alice.miden".to_string(),
+                address: "0xabc".to_string(),
+                version: RecordVersion::Web2,
+            })
+            .unwrap();
+
+        let lookup = |accept: &'static str| {
+            let params = LookupParams {
+                name: "alice.miden".to_string(),
+                source: LookupSource::Db,
+                ..Default::default()
+            };
+            let mut headers = HeaderMap::new();
+            headers.insert(header::ACCEPT, HeaderValue::from_static(accept));
+            lookup_handler(State(state.clone()), headers, Query(params))
+        };
+
+        let plain = lookup("text/plain").await.into_response();
+        assert_eq!(plain.status(), StatusCode::OK);
+        assert_eq!(
+            plain.headers().get(header::CONTENT_TYPE).unwrap(),
+            "text/plain; charset=utf-8"
+        );
+        let body = axum::body::to_bytes(plain.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], b"0xabc");
+
+        // JSON wins when it is listed first or the client accepts anything
+        for accept in ["application/json, text/plain", "*/*"] {
+            let json = lookup(accept).await.into_response();
+            assert_eq!(
+                json.headers().get(header::CONTENT_TYPE).unwrap(),
+                "application/json"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_lookup_soft_not_found() {
+        let state = test_state();
+        let params = LookupParams {
+            name: "bob.miden".to_string(),
+            source: LookupSource::Db,
+            soft_not_found: true,
+            ..Default::default()
+        };
+
+        let response = lookup_handler(State(state), HeaderMap::new(), Query(params))
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body, serde_json::json!({ "found": false }));
+    }
+
+    #[test]
+    fn test_query_params_are_typed() {
+        let uri = |uri: &str| uri.parse::<Uri>().unwrap();
+
+        let params =
+            Query::<LookupParams>::try_from_uri(&uri("/lookup?name=alice.miden&source=contract"))
+                .unwrap();
+        assert_eq!(params.source, LookupSource::Contract);
+        assert!(!params.soft_not_found);
+
+        // missing or malformed parameters are rejected before reaching the handler
+        assert!(Query::<LookupParams>::try_from_uri(&uri("/lookup?source=db")).is_err());
+        assert!(Query::<LookupParams>::try_from_uri(&uri("/lookup?name=a&source=web")).is_err());
+        assert!(Query::<RegisterParams>::try_from_uri(&uri("/register?name=a&version=2")).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_no_db_rejects_version_2() {
+        let state = AppState {
+            db: None,
+            ..test_state()
+        };
+        let request = RegisterRequest {
+            name: "alice.miden".to_string(),
+            address: "0xabc".to_string(),
+            version: "2".to_string(),
+            ..Default::default()
+        };
+
+        assert!(matches!(
+            register_name(&state, request).await,
+            Err(AppError::BadRequest(_))
+        ));
+        let params = LookupParams {
+            name: "alice.miden".to_string(),
+            source: LookupSource::Db,
+            ..Default::default()
+        };
+        let response = lookup_handler(State(state), HeaderMap::new(), Query(params))
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_register_rejects_unknown_version() {
+        let state = test_state();
+        for version in ["2.0", "web2", "3"] {
+            let request = RegisterRequest {
+                name: "alice.miden".to_string(),
+                address: "0xabc".to_string(),
+                version: version.to_string(),
+                ..Default::default()
+            };
+            assert!(matches!(
+                register_name(&state, request).await,
+                Err(AppError::Coded {
+                    code: ErrorCode::InvalidVersion,
+                    ..
+                })
+            ));
+        }
+        assert!(
+            state
+                .db
+                .as_ref()
+                .unwrap()
+                .lookup_user("alice.miden")
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_lookup_order() {
+        // a worker whose contract only knows alice, at a different address than the database
+        let (tx, mut rx) = mpsc::channel(16);
+        tokio::spawn(async move {
+            while let Some(request) = rx.recv().await {
+                if let ClientRequest::Lookup { name, respond, .. } = request {
+                    let _ = respond.send(match &*name {
+                        "alice.miden" => Ok(LookupResponse {
+                            address: "0xchain".to_string(),
+                            version: RecordVersion::Web2_5,
+                            records: vec![ResolverRecord {
+                                record_type: "web2.5".to_string(),
+                                value: "0xchain".to_string(),
+                            }],
+                            source: LookupOrigin::Contract,
+                            history: None,
+                            coin: None,
+                        }),
+                        _ => Err(AppError::NotFound("not registered".to_string())),
+                    });
+                }
+            }
+        });
+        let state = AppState { tx, ..test_state() };
+        let db = state.db.as_ref().unwrap();
+        for name in ["alice.miden", "bob.miden"] {
+            db.insert_user(&User {
+                name: name.to_string(),
+                address: "0xabc".to_string(),
+                version: RecordVersion::Web2,
+            })
+            .unwrap();
+        }
+
+        let lookup = |state: AppState, name: &str| {
+            let params = LookupParams {
+                name: name.to_string(),
+                source: LookupSource::Auto,
+                ..Default::default()
+            };
+            async move {
+                let response = lookup_name(&state, params).await.unwrap();
+                (response.address, response.source)
+            }
+        };
+
+        let database = ("0xabc".to_string(), LookupOrigin::Database);
+        assert_eq!(lookup(state.clone(), "alice.miden").await, database);
+        let state = AppState {
+            lookup_order: LookupOrder::ContractFirst,
+            ..state
+        };
+        assert_eq!(
+            lookup(state.clone(), "alice.miden").await,
+            ("0xchain".to_string(), LookupOrigin::Contract)
+        );
+        // contract misses still find database names
+        assert_eq!(lookup(state, "bob.miden").await, database);
+    }
+
+    #[tokio::test]
+    async fn test_transaction_reports_block_once_committed() {
+        let state = test_state();
+        let status = |state: AppState| async move {
+            let response = transaction_handler(State(state), Path("0x123".to_string()))
+                .await
+                .into_response();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+        };
+
+        state
+            .events
+            .update("0x123", TransactionState::Pending, None);
+        let pending = status(state.clone()).await;
+        assert_eq!(pending["status"], "pending");
+        assert!(pending["block_num"].is_null());
+
+        state
+            .events
+            .update("0x123", TransactionState::Committed, Some(42));
+        assert_eq!(status(state.clone()).await["block_num"], 42);
+
+        let response = transaction_handler(State(state), Path("0x456".to_string()))
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_register_sheds_load_without_permits() {
+        let state = AppState {
+            register_permits: Arc::new(Semaphore::new(0)),
+            ..test_state()
+        };
+        let request = RegisterRequest {
+            name: "alice.miden".to_string(),
+            address: "0xabc".to_string(),
+            version: "2".to_string(),
+            ..Default::default()
+        };
+
+        match register_name(&state, request).await {
+            Err(e @ AppError::ServiceUnavailable(_)) => {
+                let response = e.into_response();
+                assert!(response.headers().contains_key(header::RETRY_AFTER));
+            }
+            other => panic!("expected 503, got {:?}", other.map(|r| r.name)),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_full_worker_queue_is_rejected() {
+        // a worker that never drains its single queue slot
+        let (tx, _rx) = mpsc::channel(1);
+        let (respond, _) = oneshot::channel();
+        tx.try_send(ClientRequest::SyncInfo {
+            request_id: 0,
+            respond,
+        })
+        .unwrap();
+        let state = AppState {
+            tx,
+            debug_endpoints: true,
+            ..test_state()
+        };
+
+        let response = debug_sync_handler(State(state)).await.into_response();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(response.headers().contains_key(header::RETRY_AFTER));
+    }
+
+    #[tokio::test]
+    async fn test_debug_sync() {
+        let (tx, mut rx) = mpsc::channel(16);
+        tokio::spawn(async move {
+            while let Some(request) = rx.recv().await {
+                if let ClientRequest::SyncInfo { respond, .. } = request {
+                    let _ = respond.send(Ok(SyncInfoResponse {
+                        block_num: 42,
+                        synced_at: Some(1_700_000_000),
+                    }));
+                }
+            }
+        });
+
+        let disabled = debug_sync_handler(State(test_state()))
+            .await
+            .into_response();
+        assert_eq!(disabled.status(), StatusCode::NOT_FOUND);
+
+        let state = AppState {
+            tx,
+            debug_endpoints: true,
+            ..test_state()
+        };
+        let response = debug_sync_handler(State(state)).await.into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({ "block_num": 42, "synced_at": 1_700_000_000 })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_version_reports_contracts_once_ready() {
+        let state = test_state();
+        let body = |state: AppState| async move {
+            let response = version_handler(State(state)).await.into_response();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+        };
+
+        let starting = body(state.clone()).await;
+        assert_eq!(starting["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(starting["contract_ids"], serde_json::json!([]));
+
+        state
+            .contract_ids
+            .set(vec!["0xdde9bd696d7c6400000432b139e732".to_string()])
+            .unwrap();
+        let ready = body(state).await;
+        assert_eq!(
+            ready["contract_ids"],
+            serde_json::json!(["0xdde9bd696d7c6400000432b139e732"])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_register_subname_needs_parent_owner() {
+        let state = test_state();
+        let request = |name: &str, version: &str, parent_token: Option<&str>| RegisterRequest {
+            name: name.to_string(),
+            address: "0xwallet".to_string(),
+            version: version.to_string(),
+            parent_token: parent_token.map(str::to_string),
+            ..Default::default()
+        };
+        let parent_token = register_name(
+            &state,
+            RegisterRequest {
+                address: "0xabc".to_string(),
+                ..request("alice.miden", "2", None)
+            },
+        )
+        .await
+        .unwrap()
+        .owner_token
+        .unwrap();
+        let parent_token = Some(parent_token.as_str());
+
+        for (request, expected) in [
+            (
+                request("wallet.alice.miden", "2", None),
+                "needs parent_token",
+            ),
+            (
+                request("wallet.alice.miden", "2.5", parent_token),
+                "version 2",
+            ),
+        ] {
+            match register_name(&state, request).await {
+                Err(AppError::BadRequest(msg)) => assert!(msg.contains(expected), "{}", msg),
+                other => panic!("expected 400, got {:?}", other.map(|r| r.name)),
+            }
+        }
+        assert!(matches!(
+            register_name(&state, request("wallet.bob.miden", "2", parent_token)).await,
+            Err(AppError::NotFound(_))
+        ));
+        // the parent's public address proves nothing
+        assert!(matches!(
+            register_name(&state, request("wallet.alice.miden", "2", Some("0xabc"))).await,
+            Err(AppError::Forbidden(_))
+        ));
+
+        let response = register_name(&state, request("wallet.alice.miden", "2", parent_token))
+            .await
+            .unwrap();
+        assert_eq!(response.address, "0xwallet");
+        assert!(response.owner_token.is_some());
+        let stored = state.db.as_ref().unwrap().lookup_user("wallet.alice.miden");
+        assert_eq!(stored.unwrap().unwrap().address, "0xwallet");
+    }
+
+    #[tokio::test]
+    async fn test_register_rejects_reserved_names() {
+        let state = AppState {
+            reserved: Some(Arc::new(ReservedNames::parse("admin.miden\nmiden*"))),
+            ..test_state()
+        };
+        let request = |name: &str| RegisterRequest {
+            name: name.to_string(),
+            address: "0xabc".to_string(),
+            version: "2".to_string(),
+            ..Default::default()
+        };
+
+        for name in ["admin.miden", "midenlabs.miden"] {
+            assert!(matches!(
+                register_name(&state, request(name)).await,
+                Err(AppError::Coded {
+                    code: ErrorCode::NameReserved,
+                    ..
+                })
+            ));
+        }
+        register_name(&state, request("alice.miden")).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_register_reveals_commitment() {
+        let state = AppState {
+            commits: CommitPolicy {
+                min_age: Duration::ZERO,
+                required: true,
+                ..CommitPolicy::default()
+            },
+            ..test_state()
+        };
+        let request = |secret: Option<&str>| RegisterRequest {
+            name: "alice.miden".to_string(),
+            address: "0xabc".to_string(),
+            version: "2".to_string(),
+            secret: secret.map(str::to_string),
+            ..Default::default()
+        };
+
+        assert!(matches!(
+            register_name(&state, request(None)).await,
+            Err(AppError::Forbidden(_))
+        ));
+        commit(&state, &commitment("alice.miden", "hunter2")).unwrap();
+        assert!(matches!(
+            register_name(&state, request(Some("hunter3"))).await,
+            Err(AppError::NotFound(_))
+        ));
+        register_name(&state, request(Some("hunter2")))
+            .await
+            .unwrap();
+
+        // a registration failing after the reveal leaves the commitment in place
+        let retry = commitment("alice.miden", "hunter3");
+        commit(&state, &retry).unwrap();
+        assert!(matches!(
+            register_name(&state, request(Some("hunter3"))).await,
+            Err(AppError::AlreadyRegistered { .. })
+        ));
+        let db = state.db.as_ref().unwrap();
+        assert!(db.take_commit(&retry, |_| Ok(())).is_ok());
+
+        // an unsupported version is refused before the commitment is even looked at
+        let web3 = commitment("carol.miden", "hunter2");
+        commit(&state, &web3).unwrap();
+        let request_web3 = RegisterRequest {
+            name: "carol.miden".to_string(),
+            version: "3".to_string(),
+            ..request(Some("hunter2"))
+        };
+        assert!(matches!(
+            register_name(&state, request_web3).await,
+            Err(AppError::Coded {
+                code: ErrorCode::InvalidVersion,
+                ..
+            })
+        ));
+        assert!(db.take_commit(&web3, |_| Ok(())).is_ok());
+
+        // a batch would skip the commitments altogether
+        let response = register_bulk_handler(State(state.clone()), Json(vec![request(None)]))
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        // a commitment cannot be revealed before its minimum age
+        let state = AppState {
+            commits: CommitPolicy::default(),
+            ..test_state()
+        };
+        commit(&state, &commitment("bob.miden", "hunter2")).unwrap();
+        let early = RegisterRequest {
+            name: "bob.miden".to_string(),
+            ..request(Some("hunter2"))
+        };
+        assert!(matches!(
+            register_name(&state, early).await,
+            Err(AppError::BadRequest(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_lookup_include_history() {
+        let state = test_state();
+        let db = state.db.as_ref().unwrap();
+        db.insert_user(&User {
+            name: "alice.miden".to_string(),
+            address: "0xold".to_string(),
+            version: RecordVersion::Web2,
+        })
+        .unwrap();
+        db.update_address("alice.miden", "0xnew").unwrap();
+
+        let lookup = |include_history: bool| {
+            let params = LookupParams {
+                name: "alice.miden".to_string(),
+                source: LookupSource::Db,
+                include_history,
+                ..Default::default()
+            };
+            lookup_name(&state, params)
+        };
+
+        let response = lookup(true).await.unwrap();
+        assert_eq!(response.address, "0xnew");
+        let history = response.history.unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].address, "0xold");
+
+        assert!(lookup(false).await.unwrap().history.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_export_ndjson_streams_users() {
+        let state = AppState {
+            admin_token: Some("secret".to_string()),
+            ..test_state()
+        };
+        for name in ["alice.miden", "bob.miden"] {
+            state
+                .db
+                .as_ref()
+                .unwrap()
+                .insert_user(&User {
+                    name: name.to_string(),
+                    address: "0xabc".to_string(),
+                    version: RecordVersion::Web2,
+                })
+                .unwrap();
+        }
+
+        let response = export_ndjson_handler(State(state.clone()), HeaderMap::new())
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_static("Bearer secret"),
+        );
+        let response = export_ndjson_handler(State(state), headers)
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let names: Vec<String> = std::str::from_utf8(&body)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<User>(line).unwrap().name)
+            .collect();
+        assert_eq!(names, ["alice.miden", "bob.miden"]);
+    }
+
+    #[tokio::test]
+    async fn test_lookup_coin_address() {
+        let state = test_state();
+        let owner_token = register_name(
+            &state,
+            RegisterRequest {
+                name: "alice.miden".to_string(),
+                address: "0xabc".to_string(),
+                version: "2".to_string(),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap()
+        .owner_token
+        .unwrap();
+
+        let set_btc = |token: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(OWNER_TOKEN_HEADER, HeaderValue::from_str(token).unwrap());
+            let params = [
+                ("name", "alice.miden"),
+                ("coin", "BTC"),
+                ("address", "bc1alice"),
+            ]
+            .map(|(key, value)| (key.to_string(), value.to_string()));
+            coins_handler(State(state.clone()), headers, Query(params.into()))
+        };
+        let response = set_btc("wrong").await.into_response();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = set_btc(&owner_token).await.into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let lookup = |coin: Option<&str>| {
+            let params = LookupParams {
+                name: "alice.miden".to_string(),
+                coin: coin.map(str::to_string),
+                ..Default::default()
+            };
+            lookup_name(&state, params)
+        };
+
+        let response = lookup(Some("btc")).await.unwrap();
+        assert_eq!(response.address, "bc1alice");
+        assert_eq!(response.coin.as_deref(), Some("btc"));
+        // the Miden address stays the default
+        assert_eq!(lookup(None).await.unwrap().address, "0xabc");
+        assert_eq!(lookup(Some("miden")).await.unwrap().address, "0xabc");
+        assert!(matches!(
+            lookup(Some("eth")).await,
+            Err(AppError::NotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_register_falls_back_to_default_version() {
+        let request = |name: &str, version: &str| RegisterRequest {
+            name: name.to_string(),
+            address: "0xabc".to_string(),
+            version: version.to_string(),
+            ..Default::default()
+        };
+
+        let state = test_state();
+        assert!(matches!(
+            register_name(&state, request("alice.miden", "")).await,
+            Err(AppError::Coded {
+                code: ErrorCode::MissingParameter,
+                ..
+            })
+        ));
+
+        let state = AppState {
+            default_version: Some(RecordVersion::Web2),
+            ..test_state()
+        };
+        let response = register_name(&state, request("alice.miden", ""))
+            .await
+            .unwrap();
+        assert_eq!(response.version, RecordVersion::Web2);
+        // an explicit version still wins
+        assert!(matches!(
+            register_name(&state, request("bob.miden", "2.0")).await,
+            Err(AppError::Coded {
+                code: ErrorCode::InvalidVersion,
+                ..
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_records_need_owner_token() {
+        let state = test_state();
+        let registered = register_name(
+            &state,
+            RegisterRequest {
+                name: "alice.miden".to_string(),
+                address: "0xabc".to_string(),
+                version: "2".to_string(),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        let owner_token = registered.owner_token.unwrap();
+
+        let put_record = |record_type: &str, token: Option<&str>| {
+            let mut headers = HeaderMap::new();
+            if let Some(token) = token {
+                headers.insert(OWNER_TOKEN_HEADER, HeaderValue::from_str(token).unwrap());
+            }
+            let params = [
+                ("name", "alice.miden"),
+                ("type", record_type),
+                ("value", "0xdef"),
+            ]
+            .map(|(key, value)| (key.to_string(), value.to_string()));
+            records_handler(State(state.clone()), headers, Query(params.into()))
+        };
+
+        assert_eq!(
+            put_record("web3", None).await.into_response().status(),
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            put_record("web3", Some("wrong"))
+                .await
+                .into_response()
+                .status(),
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            put_record("web3", Some(&owner_token))
+                .await
+                .into_response()
+                .status(),
+            StatusCode::OK
+        );
+
+        // the address record only moves through `/update`
+        assert_eq!(
+            put_record("web2", Some(&owner_token))
+                .await
+                .into_response()
+                .status(),
+            StatusCode::BAD_REQUEST
+        );
+        let user = state.db.as_ref().unwrap().lookup_user("alice.miden");
+        assert_eq!(user.unwrap().unwrap().address, "0xabc");
+    }
+
+    #[tokio::test]
+    async fn test_update_needs_owner_token() {
+        let state = AppState {
+            admin_token: Some("admin".to_string()),
+            ..test_state()
+        };
+        let db = state.db.clone().unwrap();
+        let owner_token = register_name(
+            &state,
+            RegisterRequest {
+                name: "alice.miden".to_string(),
+                address: "0xabc".to_string(),
+                version: "2".to_string(),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap()
+        .owner_token
+        .unwrap();
+
+        let update = |header_name: header::HeaderName, value: String, address: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header_name, HeaderValue::from_str(&value).unwrap());
+            let params = UpdateParams {
+                name: "alice.miden".to_string(),
+                address: address.to_string(),
+            };
+            update_handler(State(state.clone()), headers, Query(params))
+        };
+
+        // the public address proves nothing
+        let response = update(
+            OWNER_TOKEN_HEADER.parse().unwrap(),
+            "0xabc".to_string(),
+            "0xevil",
+        )
+        .await
+        .into_response();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let address = || db.lookup_user("alice.miden").unwrap().unwrap().address;
+        assert_eq!(address(), "0xabc");
+
+        // the response lists every record of the name, not only the one that moved
+        db.insert_record("alice.miden", "web3", "0x777").unwrap();
+        let response = update(OWNER_TOKEN_HEADER.parse().unwrap(), owner_token, "0xdef")
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(address(), "0xdef");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let mut records: Vec<String> = body["records"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|record| format!("{}={}", record["type"], record["value"]))
+            .collect();
+        records.sort();
+        assert_eq!(records, [r#""web2"="0xdef""#, r#""web3"="0x777""#]);
+
+        // the admin token also passes, for names registered without an owner token
+        let response = update(header::AUTHORIZATION, "Bearer admin".to_string(), "0x123")
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(address(), "0x123");
+    }
+
+    #[tokio::test]
+    async fn test_idempotent_replay_leaves_out_owner_token() {
+        let state = test_state();
+        let register = |address: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert("idempotency-key", HeaderValue::from_static("key"));
+            let request = RegisterRequest {
+                name: "alice.miden".to_string(),
+                address: address.to_string(),
+                version: "2".to_string(),
+                ..Default::default()
+            };
+            register_json_handler(State(state.clone()), headers, Json(request))
+        };
+        let owner_token = |response: axum::response::Response| async move {
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            body["owner_token"].as_str().map(str::to_string)
+        };
+
+        let first = register("0xabc").await.into_response();
+        assert!(owner_token(first).await.is_some());
+        let replay = register("0xabc").await.into_response();
+        assert_eq!(owner_token(replay).await, None);
+
+        // the key cannot pass a different registration off as the first one
+        let reused = register("0xdef").await.into_response();
+        assert_eq!(reused.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[test]
+    fn test_registrant_is_optional_on_chain() {
+        let owner = "0xdde9bd696d7c6400000432b139e732";
+        let recorded = registrant(RecordVersion::Web2_5, Some(owner)).unwrap();
+        assert_eq!(recorded.unwrap().to_hex(), owner);
+        assert_eq!(registrant(RecordVersion::Web2_5, None).unwrap(), None);
+        assert_eq!(registrant(RecordVersion::Web2_5, Some("")).unwrap(), None);
+        assert_eq!(registrant(RecordVersion::Web2, None).unwrap(), None);
+
+        match registrant(RecordVersion::Web2_5, Some("0xwallet")) {
+            Err(AppError::Coded { code, .. }) => assert_eq!(code, ErrorCode::InvalidAddress),
+            other => panic!("expected an invalid owner, got {:?}", other),
+        }
     }
 }
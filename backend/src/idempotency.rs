@@ -0,0 +1,177 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::handler::RegisterResponse;
+
+/// Longest idempotency key accepted, in bytes
+pub const MAX_KEY_LEN: usize = 255;
+
+enum State {
+    InFlight,
+    Done(RegisterResponse),
+}
+
+struct Entry {
+    created_at: Instant,
+    // the request the key was first used for
+    fingerprint: String,
+    state: State,
+}
+
+/// Outcome of claiming an idempotency key
+pub enum Claim {
+    /// First use of the key, the caller must run the request and `complete` it
+    New,
+    /// A request with the same key is still running
+    InFlight,
+    /// A request with the same key already succeeded
+    Done(RegisterResponse),
+    /// The key was used for a different request
+    Mismatch,
+    /// The store holds `max_entries` live keys and takes no new ones
+    Full,
+}
+
+/// In-memory store mapping idempotency keys to successful register responses
+pub struct IdempotencyStore {
+    ttl: Duration,
+    max_entries: usize,
+    entries: Mutex<HashMap<String, Entry>>,
+}
+
+impl IdempotencyStore {
+    pub fn new(ttl: Duration, max_entries: usize) -> Self {
+        Self {
+            ttl,
+            max_entries,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Claim a key for the request described by `fingerprint`, dropping expired entries
+    /// first
+    pub fn claim(&self, key: &str, fingerprint: &str) -> Claim {
+        let Ok(mut entries) = self.entries.lock() else {
+            return Claim::New;
+        };
+
+        let now = Instant::now();
+        entries.retain(|_, entry| now.duration_since(entry.created_at) < self.ttl);
+
+        match entries.get(key) {
+            Some(entry) if entry.fingerprint != fingerprint => Claim::Mismatch,
+            Some(Entry {
+                state: State::InFlight,
+                ..
+            }) => Claim::InFlight,
+            Some(Entry {
+                state: State::Done(response),
+                ..
+            }) => Claim::Done(response.clone()),
+            None if entries.len() >= self.max_entries => Claim::Full,
+            None => {
+                entries.insert(
+                    key.to_string(),
+                    Entry {
+                        created_at: now,
+                        fingerprint: fingerprint.to_string(),
+                        state: State::InFlight,
+                    },
+                );
+                Claim::New
+            }
+        }
+    }
+
+    /// Store the response for a claimed key, or release it if the request failed
+    pub fn complete(&self, key: &str, response: Option<RegisterResponse>) {
+        let Ok(mut entries) = self.entries.lock() else {
+            return;
+        };
+
+        match response {
+            Some(response) => {
+                if let Some(entry) = entries.get_mut(key) {
+                    entry.created_at = Instant::now();
+                    entry.state = State::Done(response);
+                }
+            }
+            None => {
+                entries.remove(key);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::handler::RegisterResponse;
+    use crate::idempotency::{Claim, IdempotencyStore};
+    use crate::serde::RecordVersion;
+
+    fn response() -> RegisterResponse {
+        RegisterResponse {
+            name: "alice.miden".to_string(),
+            address: "0xabc".to_string(),
+            version: RecordVersion::Web2_5,
+            transaction_id: Some("0x123".to_string()),
+            owner: None,
+            storage_key: None,
+            owner_token: None,
+        }
+    }
+
+    #[test]
+    fn test_replays_completed_response() {
+        let store = IdempotencyStore::new(Duration::from_secs(60), 16);
+        assert!(matches!(store.claim("key", "request"), Claim::New));
+        assert!(matches!(store.claim("key", "request"), Claim::InFlight));
+
+        store.complete("key", Some(response()));
+        match store.claim("key", "request") {
+            Claim::Done(replayed) => assert_eq!(replayed.transaction_id, Some("0x123".to_string())),
+            _ => panic!("expected a replayed response"),
+        }
+    }
+
+    #[test]
+    fn test_failed_request_releases_key() {
+        let store = IdempotencyStore::new(Duration::from_secs(60), 16);
+        assert!(matches!(store.claim("key", "request"), Claim::New));
+        store.complete("key", None);
+        assert!(matches!(store.claim("key", "request"), Claim::New));
+    }
+
+    #[test]
+    fn test_expired_entries_are_dropped() {
+        let store = IdempotencyStore::new(Duration::ZERO, 16);
+        assert!(matches!(store.claim("key", "request"), Claim::New));
+        store.complete("key", Some(response()));
+        assert!(matches!(store.claim("key", "request"), Claim::New));
+    }
+
+    #[test]
+    fn test_key_is_bound_to_its_request() {
+        let store = IdempotencyStore::new(Duration::from_secs(60), 16);
+        assert!(matches!(store.claim("key", "request"), Claim::New));
+        assert!(matches!(store.claim("key", "other"), Claim::Mismatch));
+
+        store.complete("key", Some(response()));
+        assert!(matches!(store.claim("key", "other"), Claim::Mismatch));
+    }
+
+    #[test]
+    fn test_store_takes_no_keys_past_its_limit() {
+        let store = IdempotencyStore::new(Duration::from_secs(60), 1);
+        assert!(matches!(store.claim("first", "request"), Claim::New));
+        assert!(matches!(store.claim("second", "request"), Claim::Full));
+
+        // known keys are still answered
+        assert!(matches!(store.claim("first", "request"), Claim::InFlight));
+        store.complete("first", None);
+        assert!(matches!(store.claim("second", "request"), Claim::New));
+    }
+}
@@ -0,0 +1,148 @@
+//! End-to-end tests against a local Miden node listening on `localhost:3002`.
+//!
+//! These need a running node and are ignored by default, run them with
+//! `cargo test -- --ignored`. The client store and keystore live at fixed paths in the
+//! working directory, so the tests hold [`NODE`] while they run.
+
+use miden_client::{Client, account::Account};
+use tokio::sync::{Mutex, MutexGuard};
+
+use crate::retry::{self, RetryPolicy};
+use crate::serde::{MAX_NAME_BYTES, Name, RecordVersion};
+use crate::service;
+use crate::utils::{
+    DEFAULT_KEYSTORE_DIR, create_account, create_client, deploy_account, load_masm, remove_store,
+};
+
+// Serializes the tests over the shared client store
+static NODE: Mutex<()> = Mutex::const_new(());
+
+// A fresh client store and contract per test, without touching deployment.json
+async fn setup() -> (MutexGuard<'static, ()>, Client, Account, RetryPolicy) {
+    let guard = NODE.lock().await;
+    remove_store();
+    load_masm("masm").unwrap();
+    let policy = RetryPolicy::new(3, 500);
+    let mut client = create_client(10_000, DEFAULT_KEYSTORE_DIR).await.unwrap();
+
+    let (account, _) = create_account(&mut client, None, 0).await.unwrap();
+    deploy_account(&mut client, &account, &policy)
+        .await
+        .unwrap();
+
+    (guard, client, account, policy)
+}
+
+#[tokio::test]
+#[ignore = "requires a local Miden node on localhost:3002"]
+async fn test_register_then_lookup() {
+    let (_guard, mut client, account, policy) = setup().await;
+
+    let name = Name::parse(
+        &format!("it{}.miden", rand::random::<u32>()),
+        MAX_NAME_BYTES,
+    )
+    .unwrap();
+    let address = "https://mirko.dev".to_string();
+    let registered = service::register(
+        &mut client,
+        account.id(),
+        name.clone(),
+        address.clone(),
+        Some(account.id()),
+        &policy,
+    )
+    .await
+    .unwrap();
+    assert!(registered.transaction_id.is_some());
+
+    retry::sync_state(&mut client, &policy).await.unwrap();
+
+    // the lookup script must read back the exact key the register script wrote
+    let found = service::lookup(&mut client, account.id(), name.clone())
+        .await
+        .unwrap();
+    assert_eq!(found.address, address);
+    assert_eq!(found.version, RecordVersion::Web2_5);
+
+    let nobody = Name::parse("nobody.miden", MAX_NAME_BYTES).unwrap();
+    let missing = service::lookup(&mut client, account.id(), nobody).await;
+    assert!(missing.is_err());
+
+    // account ids are stored natively rather than as strings
+    let name = Name::parse(
+        &format!("id{}.miden", rand::random::<u32>()),
+        MAX_NAME_BYTES,
+    )
+    .unwrap();
+    let address = account.id().to_hex();
+    service::register(
+        &mut client,
+        account.id(),
+        name.clone(),
+        address.clone(),
+        Some(account.id()),
+        &policy,
+    )
+    .await
+    .unwrap();
+
+    let found = service::lookup(&mut client, account.id(), name)
+        .await
+        .unwrap();
+    assert_eq!(found.address, address);
+}
+
+#[tokio::test]
+#[ignore = "requires a local Miden node on localhost:3002"]
+async fn test_register_then_lookup_batch() {
+    let (_guard, mut client, account, policy) = setup().await;
+
+    let mut names = Vec::new();
+    for i in 0..2 {
+        let name = Name::parse(
+            &format!("batch{}{}.miden", i, rand::random::<u32>()),
+            MAX_NAME_BYTES,
+        )
+        .unwrap();
+        service::register(
+            &mut client,
+            account.id(),
+            name.clone(),
+            format!("https://batch{}.dev", i),
+            None,
+            &policy,
+        )
+        .await
+        .unwrap();
+        names.push(name);
+    }
+    retry::sync_state(&mut client, &policy).await.unwrap();
+
+    // five names span a full batch and a partial one, with a free name in each
+    let nobody = Name::parse("nobody.miden", MAX_NAME_BYTES).unwrap();
+    let batch = [
+        names[0].clone(),
+        nobody.clone(),
+        names[1].clone(),
+        names[0].clone(),
+        nobody,
+    ];
+    let results = service::lookup_batch(&mut client, account.id(), &batch).await;
+    assert_eq!(results.len(), batch.len());
+
+    let addresses: Vec<Option<String>> = results
+        .into_iter()
+        .map(|result| result.ok().map(|found| found.address))
+        .collect();
+    assert_eq!(
+        addresses,
+        [
+            Some("https://batch0.dev".to_string()),
+            None,
+            Some("https://batch1.dev".to_string()),
+            Some("https://batch0.dev".to_string()),
+            None,
+        ]
+    );
+}
@@ -0,0 +1,19 @@
+pub mod auth;
+pub mod backup;
+pub mod bloom;
+pub mod commit;
+pub mod db;
+pub mod error;
+pub mod events;
+pub mod grpc;
+pub mod handler;
+pub mod idempotency;
+#[cfg(test)]
+mod integration;
+pub mod reserved;
+pub mod retry;
+pub mod serde;
+pub mod service;
+pub mod shard;
+pub mod singleflight;
+pub mod utils;
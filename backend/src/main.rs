@@ -1,25 +1,62 @@
+use anyhow::{Context, anyhow, bail};
 use axum::Router;
-use axum::routing::{get, put};
+use axum::body::Body;
+use axum::extract::DefaultBodyLimit;
+use axum::http::{Method, Request};
+use axum::middleware::from_fn_with_state;
+use axum::routing::{get, post, put};
+use axum_server::tls_rustls::RustlsConfig;
 use clap::Parser;
-use log::info;
+use clap::builder::RangedU64ValueParser;
+use log::{error, info, warn};
 use miden_client::account::AccountId;
-use std::sync::Arc;
+use sentry::integrations::tower::{NewSentryLayer, SentryHttpLayer};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tokio::net::TcpListener;
-use tokio::sync::mpsc;
+use tokio::signal::unix::{SignalKind, signal};
+use tokio::sync::{Semaphore, mpsc, oneshot};
+use tokio::time::MissedTickBehavior;
+use tower_http::LatencyUnit;
+use tower_http::compression::CompressionLayer;
 use tower_http::cors::{Any, CorsLayer};
+use tower_http::limit::RequestBodyLimitLayer;
+use tower_http::trace::{DefaultOnResponse, TraceLayer};
+use tracing::{Instrument, Level, info_span};
+use tracing_subscriber::EnvFilter;
 
-mod db;
-mod error;
-mod handler;
-mod serde;
-mod service;
-mod utils;
-
-use db::Database;
-use handler::{AppState, ClientRequest, lookup_handler, register_handler};
-use utils::{create_account, create_client, deploy_account, remove_store};
-
-const CONTRACT_ID: &str = "0xdde9bd696d7c6400000432b139e732";
+use miden_name_service::auth::{ApiKeys, require_api_key};
+use miden_name_service::bloom::BloomFilter;
+use miden_name_service::commit::CommitPolicy;
+use miden_name_service::db::Database;
+use miden_name_service::error::AppError;
+use miden_name_service::events::{TransactionEvents, TransactionState};
+use miden_name_service::grpc::{GrpcService, NameServiceServer};
+use miden_name_service::handler::{
+    AppState, ClientRequest, LookupOrder, SyncInfoResponse, WorkerHealth,
+    admin_transactions_handler, coins_handler, commit_handler, debug_encode_handler,
+    debug_lookup_handler, debug_sync_handler, events_handler, export_handler,
+    export_ndjson_handler, health_handler, history_handler, lookup_handler, records_handler,
+    register_bulk_handler, register_handler, register_json_handler, resolve_handler,
+    search_handler, stats_handler, storage_handler, transaction_handler, update_handler,
+    verify_handler, version_handler,
+};
+use miden_name_service::idempotency::IdempotencyStore;
+use miden_name_service::reserved::ReservedNames;
+use miden_name_service::retry::{self, RetryPolicy};
+use miden_name_service::serde::{MAX_NAME_BYTES, Name, RecordVersion};
+use miden_name_service::shard::shard_for;
+use miden_name_service::singleflight::SingleFlight;
+use miden_name_service::utils::{
+    DEFAULT_KEYSTORE_DIR, DEPLOYMENT_FILE, MasmSources, create_client_with_rpc, deploy_new_account,
+    keep_alive, load_deployment, load_masm, load_or_deploy_shard, parse_deploy_seed, remove_store,
+    rpc_client, verify_mns_account, verify_storage_layout,
+};
+use miden_name_service::{backup, events, service};
 
 /// Command line arguments for the MNS server
 #[derive(Parser, Debug)]
@@ -28,127 +65,906 @@ struct Args {
     /// Force deploy a new contract even if one already exists
     #[arg(short, long)]
     force_deploy: bool,
+
+    /// Register and look up a throwaway name on every shard before serving, exiting
+    /// non-zero if the round trip fails. Each run leaves one name in the contract
+    #[arg(long, conflicts_with = "read_only")]
+    self_test: bool,
+
+    /// Serve lookups only, rejecting registrations and never deploying a contract
+    #[arg(long, conflicts_with = "force_deploy")]
+    read_only: bool,
+
+    /// PEM certificate chain to serve HTTPS with, requires --tls-key
+    #[arg(long, requires = "tls_key")]
+    tls_cert: Option<String>,
+
+    /// PEM private key to serve HTTPS with, requires --tls-cert
+    #[arg(long, requires = "tls_cert")]
+    tls_key: Option<String>,
+
+    /// Directory of the MASM contract and scripts, reloaded on SIGHUP when set
+    #[arg(long)]
+    masm_dir: Option<String>,
+
+    /// Directory holding the client signing keys
+    #[arg(long, env = "MNS_KEYSTORE", default_value = DEFAULT_KEYSTORE_DIR)]
+    keystore_dir: String,
+
+    /// Fixed 32-byte hex seed for new contract accounts, for reproducible account ids
+    #[arg(long)]
+    deploy_seed: Option<String>,
+
+    /// Block new contract account ids are anchored at, rounded down to its epoch block
+    /// (a multiple of 65536). Genesis by default, so a `--deploy-seed` always yields the
+    /// same id on a network
+    #[arg(long, default_value_t = 0)]
+    deploy_anchor_block: u32,
+
+    /// Maximum number of retries for transient RPC failures
+    #[arg(long, default_value_t = 3)]
+    rpc_max_retries: u32,
+
+    /// Base delay in milliseconds between RPC retries, doubled on each attempt
+    #[arg(long, default_value_t = 500)]
+    rpc_retry_base_ms: u64,
+
+    /// Longest delay in milliseconds waited when the node asks to retry later, since the
+    /// wait holds up every request on the client worker
+    #[arg(long, default_value_t = 10_000)]
+    rpc_max_retry_after_ms: u64,
+
+    /// Interval in seconds between background client state syncs
+    #[arg(long, default_value_t = 5, value_parser = clap::value_parser!(u64).range(1..))]
+    sync_interval: u64,
+
+    /// Timeout in milliseconds for RPC requests to the Miden node
+    #[arg(long, default_value_t = 10_000)]
+    rpc_timeout_ms: u64,
+
+    /// Seconds the node connection may sit idle before a keep-alive request is sent, so
+    /// gateways don't silently drop it (0 disables). State syncs count as traffic
+    #[arg(long, default_value_t = 30)]
+    rpc_keep_alive_secs: u64,
+
+    /// Maximum name length in user-visible characters (names are also capped at 24 bytes)
+    #[arg(long, default_value_t = MAX_NAME_BYTES)]
+    max_name_len: usize,
+
+    /// Bearer token required by the admin endpoints (disabled when unset)
+    #[arg(long)]
+    admin_token: Option<String>,
+
+    /// Key accepted in the `X-API-Key` header of the HTTP register, update and records
+    /// endpoints and in the `x-api-key` metadata of gRPC `Register`, repeat for several
+    /// keys. Lookups stay public; unset leaves writes open
+    #[arg(long)]
+    api_key: Vec<String>,
+
+    /// Path to the SQLite users database (`:memory:` for an in-memory database)
+    #[arg(long, default_value = "users.sqlite3")]
+    db_path: String,
+
+    /// Skip the SQLite database and resolve everything against the contract.
+    /// Version 2 (database) registrations are rejected.
+    #[arg(long, conflicts_with_all = ["export", "import"])]
+    no_db: bool,
+
+    /// How long to wait on a locked database before failing, in milliseconds
+    #[arg(long, default_value_t = 5_000)]
+    db_busy_timeout_ms: u64,
+
+    /// Enable a bloom filter of registered names with this false-positive rate (e.g. 0.01).
+    /// Only names registered through this server are known to it.
+    #[arg(long)]
+    bloom_fp_rate: Option<f64>,
+
+    /// Number of names the bloom filter is sized for
+    #[arg(long, default_value_t = 100_000)]
+    bloom_capacity: usize,
+
+    /// Seconds a successful registration is replayed for a repeated Idempotency-Key
+    #[arg(long, default_value_t = 86_400)]
+    idempotency_ttl_secs: u64,
+
+    /// Most idempotency keys held at once, registrations with a new key are refused past it
+    #[arg(long, default_value_t = 10_000)]
+    idempotency_max_keys: usize,
+
+    /// Seconds a commitment from /commit must wait before a registration can reveal it
+    #[arg(long, default_value_t = 60)]
+    commit_min_age_secs: u64,
+
+    /// Seconds after which an unrevealed commitment expires
+    #[arg(long, default_value_t = 86_400)]
+    commit_max_age_secs: u64,
+
+    /// Only register names that reveal an earlier commitment, so pending names cannot be
+    /// front-run. Bulk imports are unaffected
+    #[arg(long, conflicts_with = "no_db")]
+    require_commit: bool,
+
+    /// Registrations processed at once, further ones get 503 until one finishes.
+    /// Lookups are not limited
+    #[arg(
+        long,
+        default_value_t = 32,
+        value_parser = RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    max_inflight_registers: usize,
+
+    /// Only warn when an imported contract account's code differs from the compiled MASM
+    /// contract, e.g. while testing changed sources against an older deployment
+    #[arg(long)]
+    allow_code_mismatch: bool,
+
+    /// Serve every response uncompressed, regardless of Accept-Encoding
+    #[arg(long)]
+    no_compression: bool,
+
+    /// Largest accepted request body in bytes, larger requests get 413
+    #[arg(long, default_value_t = 64 * 1024)]
+    max_body_size: usize,
+
+    /// Log filter used when RUST_LOG is not set, e.g. `warn,miden_name_service=trace`
+    #[arg(long, default_value = "info,miden_name_service=debug")]
+    log_level: String,
+
+    /// How long clients and CDNs may cache successful lookups, in seconds
+    #[arg(long, default_value_t = 30)]
+    lookup_cache_ttl_secs: u64,
+
+    /// Dump the database users to this file (`.csv` for CSV, JSON otherwise) and exit.
+    /// Names registered on-chain are not included.
+    #[arg(long, conflicts_with = "import")]
+    export: Option<PathBuf>,
+
+    /// Backend tried first by lookups without an explicit `source`. `db-first` answers
+    /// database names without a contract query, `contract-first` makes the contract
+    /// authoritative at the cost of a contract query per lookup
+    #[arg(long, value_enum, default_value_t = LookupOrder::DbFirst)]
+    lookup_order: LookupOrder,
+
+    /// Include the current address and version in 409s for names held only by the contract.
+    /// Database registrations are always shared.
+    #[arg(long)]
+    expose_contract_conflicts: bool,
+
+    /// Version used for registrations that do not name one (2, 2.5 or 3). Without it the
+    /// version parameter is required
+    #[arg(long)]
+    default_version: Option<RecordVersion>,
+
+    /// Serve `/debug/lookup`, which runs the contract lookup and returns the raw VM stack
+    #[arg(long)]
+    debug_endpoints: bool,
+
+    /// Report internal errors to Sentry at this DSN (disabled when unset)
+    #[arg(long)]
+    sentry_dsn: Option<String>,
+
+    /// Number of contract accounts names are spread over by hash. Changing it for an
+    /// existing deployment hides names registered on other shards
+    #[arg(
+        long,
+        default_value_t = 1,
+        value_parser = RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    shards: usize,
+
+    /// Requests queued for the client worker before new ones are rejected with a 503
+    #[arg(
+        long,
+        default_value_t = 32,
+        value_parser = RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    worker_queue_size: usize,
+
+    /// Also serve the gRPC interface on this port (disabled when unset)
+    #[arg(long)]
+    grpc_port: Option<u16>,
+
+    /// File of names refused by /register, one per line with `#` comments. An entry
+    /// ending in `*` reserves every name with that prefix
+    #[arg(long)]
+    reserved_names: Option<PathBuf>,
+
+    /// Include query strings, which hold names and addresses, in the access log
+    #[arg(long)]
+    log_query_strings: bool,
+
+    /// Print the assembled contract and scripts from the MASM directory and exit
+    #[arg(long, conflicts_with_all = ["export", "import"])]
+    dump_masm_ir: bool,
+
+    /// Load users from a file written by --export into the database and exit
+    #[arg(long)]
+    import: Option<PathBuf>,
 }
 
 #[tokio::main]
-async fn main() {
+async fn main() -> ExitCode {
+    // startup failures are reported as one line rather than a panic and its backtrace
+    match run().await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {:#}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run() -> anyhow::Result<()> {
     // Parse command-line arguments
     let args = Args::parse();
 
     // Initialize logging
-    env_logger::init();
+    // `log` records are forwarded to tracing so they pick up the active span
+    // an explicit RUST_LOG takes precedence over --log-level
+    let filter = match EnvFilter::try_from_default_env() {
+        Ok(filter) => filter,
+        Err(_) => match EnvFilter::try_new(&args.log_level) {
+            Ok(filter) => filter,
+            Err(e) => bail!("Invalid --log-level '{}': {}", args.log_level, e),
+        },
+    };
+    tracing_subscriber::fmt().with_env_filter(filter).init();
+
+    // Keep the guard alive for the whole run so queued events are flushed on exit
+    let _sentry = args.sentry_dsn.as_deref().map(|dsn| {
+        sentry::init((
+            dsn,
+            sentry::ClientOptions {
+                release: sentry::release_name!(),
+                ..Default::default()
+            },
+        ))
+    });
+
+    // Offline backup commands only touch the database
+    if args.export.is_some() || args.import.is_some() {
+        let database = Database::with_busy_timeout(
+            &args.db_path,
+            Duration::from_millis(args.db_busy_timeout_ms),
+        )
+        .context("Failed to initialize database")?;
+        if let Some(path) = &args.export {
+            backup::export_users(&database, path).context("Export failed")?;
+        }
+        if let Some(path) = &args.import {
+            backup::import_users(&database, path, args.max_name_len).context("Import failed")?;
+        }
+        return Ok(());
+    }
+
+    // Developer aid for MASM assembly issues, nothing else is started
+    if args.dump_masm_ir {
+        let masm_dir = args.masm_dir.as_deref().unwrap_or("masm");
+        let ir = MasmSources::load(masm_dir)
+            .and_then(|sources| sources.dump_ir())
+            .context("Failed to assemble MASM sources")?;
+        print!("{}", ir);
+        return Ok(());
+    }
 
     info!("Initializing MNS server");
 
     // sanitize
     remove_store();
 
-    // Initialize the database
-    let db_path = "users.sqlite3";
-    let database = match Database::new(db_path) {
-        Ok(db) => {
-            info!("Database initialized successfully at {}", db_path);
-            Arc::new(db)
+    // Load the contract and scripts, failing fast on compile errors
+    let masm_dir = args.masm_dir.clone().unwrap_or_else(|| "masm".to_string());
+    load_masm(&masm_dir).context("Failed to load MASM sources")?;
+
+    // Recompile the MASM sources on SIGHUP when iterating on an explicit directory
+    if args.masm_dir.is_some() {
+        let mut hangup = signal(SignalKind::hangup()).context("Failed to listen for SIGHUP")?;
+        let masm_dir = masm_dir.clone();
+        tokio::spawn(async move {
+            while hangup.recv().await.is_some() {
+                info!("SIGHUP received, reloading MASM sources from {}", masm_dir);
+                if let Err(e) = load_masm(&masm_dir) {
+                    error!("Keeping previous MASM sources: {}", e);
+                }
+            }
+        });
+    }
+
+    // Initialize the database, unless the contract is the only source of truth
+    let db_path = &args.db_path;
+    let database = if args.no_db {
+        info!("Running without a database, only the contract is consulted");
+        None
+    } else {
+        let db =
+            Database::with_busy_timeout(db_path, Duration::from_millis(args.db_busy_timeout_ms))
+                .context("Failed to initialize database")?;
+        info!("Database initialized successfully at {}", db_path);
+        Some(Arc::new(db))
+    };
+
+    // Build the bloom filter of known names, if enabled
+    let bloom = match args.bloom_fp_rate {
+        None => None,
+        Some(rate) => {
+            if rate <= 0.0 || rate >= 1.0 {
+                bail!("Bloom filter false-positive rate must be between 0 and 1");
+            }
+            let names = match database.as_ref() {
+                Some(db) => db
+                    .registered_names()
+                    .context("Failed to load names for bloom filter")?,
+                None => Vec::new(),
+            };
+            let mut filter = BloomFilter::new(args.bloom_capacity.max(names.len()), rate);
+            for name in &names {
+                filter.insert(name);
+            }
+            info!("Bloom filter initialized with {} names", names.len());
+            Some(Arc::new(RwLock::new(filter)))
         }
-        Err(e) => {
-            panic!("Failed to initialize database: {}", e);
+    };
+
+    if args.commit_min_age_secs >= args.commit_max_age_secs {
+        bail!("--commit-min-age-secs must be less than --commit-max-age-secs");
+    }
+
+    // Names held back from public registration
+    let reserved = match &args.reserved_names {
+        None => None,
+        Some(path) => {
+            let reserved = ReservedNames::load(path).context("Failed to load reserved names")?;
+            info!("Reserved {} names from {}", reserved.len(), path.display());
+            Some(Arc::new(reserved))
         }
     };
 
+    let retry_policy = RetryPolicy::new(args.rpc_max_retries, args.rpc_retry_base_ms)
+        .with_max_retry_after(Duration::from_millis(args.rpc_max_retry_after_ms));
+
     // Create a new local task set to run a client that must run on the same thread
     let local = tokio::task::LocalSet::new();
 
     // Create channel for communication with the client
-    let (tx, mut rx) = mpsc::channel(32);
+    let (tx, mut rx) = mpsc::channel(args.worker_queue_size);
+
+    // Shared registry of submitted transactions, updated by the client worker
+    let tx_events = TransactionEvents::new(64);
+
+    // Validate the deploy seed up front rather than when a deployment is first needed
+    let deploy_seed = args
+        .deploy_seed
+        .as_deref()
+        .map(parse_deploy_seed)
+        .transpose()
+        .context("Invalid --deploy-seed")?;
+
+    // Set by the client worker once the contract account is available
+    let ready = Arc::new(AtomicBool::new(false));
+    let contract_ids = Arc::new(OnceLock::new());
 
     // Create application state with database
-    let state = AppState { tx, db: database };
+    let state = AppState {
+        tx,
+        db: database,
+        max_name_len: args.max_name_len,
+        admin_token: args.admin_token.clone(),
+        events: tx_events.clone(),
+        read_only: args.read_only,
+        bloom,
+        reserved,
+        commits: CommitPolicy {
+            min_age: Duration::from_secs(args.commit_min_age_secs),
+            max_age: Duration::from_secs(args.commit_max_age_secs),
+            required: args.require_commit,
+        },
+        idempotency: Arc::new(IdempotencyStore::new(
+            Duration::from_secs(args.idempotency_ttl_secs),
+            args.idempotency_max_keys,
+        )),
+        ready: ready.clone(),
+        lookup_cache_ttl_secs: args.lookup_cache_ttl_secs,
+        lookup_flights: Arc::new(SingleFlight::new()),
+        lookup_order: args.lookup_order,
+        register_permits: Arc::new(Semaphore::new(args.max_inflight_registers)),
+        expose_contract_conflicts: args.expose_contract_conflicts,
+        debug_endpoints: args.debug_endpoints,
+        default_version: args.default_version,
+        contract_ids: contract_ids.clone(),
+    };
+
+    // Serve the gRPC mirror of the API alongside HTTP, if enabled
+    if let Some(port) = args.grpc_port {
+        // the same --api-key keys guard gRPC registrations, or they would bypass them
+        let api_keys = (!args.api_key.is_empty()).then(|| ApiKeys::new(args.api_key.clone()));
+        let service = NameServiceServer::new(GrpcService::new(state.clone(), api_keys));
+        let addr = SocketAddr::from(([0, 0, 0, 0], port));
+        tokio::spawn(async move {
+            info!("gRPC server listening on {}", addr);
+            if let Err(e) = tonic::transport::Server::builder()
+                .add_service(service)
+                .serve(addr)
+                .await
+            {
+                error!("gRPC server failed: {}", e);
+            }
+        });
+    }
 
     // Create the router with all routes and middleware
+    // Endpoints that change registrations, gated by --api-key when any key is given
+    let mutating = Router::new()
+        .route(
+            "/register",
+            put(register_handler).post(register_json_handler),
+        )
+        .route("/register/bulk", post(register_bulk_handler))
+        .route("/commit", post(commit_handler))
+        .route("/update", put(update_handler))
+        .route("/records", put(records_handler))
+        .route("/coins", put(coins_handler));
+    let mutating = if args.api_key.is_empty() {
+        mutating
+    } else {
+        info!(
+            "Requiring one of {} API key(s) on mutating endpoints",
+            args.api_key.len()
+        );
+        mutating.route_layer(from_fn_with_state(
+            ApiKeys::new(args.api_key.clone()),
+            require_api_key,
+        ))
+    };
+
     let app = Router::new()
-        .route("/register", put(register_handler))
+        .merge(mutating)
+        .route("/health", get(health_handler))
         .route("/lookup", get(lookup_handler))
+        .route("/resolve/{name}", get(resolve_handler))
+        .route("/tx/{tx_id}", get(transaction_handler))
+        .route("/events/{tx_id}", get(events_handler))
+        .route("/search", get(search_handler))
+        .route("/history", get(history_handler))
+        .route("/stats", get(stats_handler))
+        .route("/verify", get(verify_handler))
+        .route("/version", get(version_handler))
+        .route("/storage", get(storage_handler))
+        .route("/export", get(export_handler))
+        .route("/export.ndjson", get(export_ndjson_handler))
+        .route("/admin/transactions", get(admin_transactions_handler))
+        .route("/debug/encode", get(debug_encode_handler))
+        .route("/debug/lookup", get(debug_lookup_handler))
+        .route("/debug/sync", get(debug_sync_handler))
         .with_state(state)
+        // a Sentry hub per request, tagged with the method and route it hit
+        .layer(SentryHttpLayer::with_transaction())
+        .layer(NewSentryLayer::<Request<Body>>::new_from_top())
+        // replace axum's per-extractor default with one limit for every request body
+        .layer(DefaultBodyLimit::disable())
+        .layer(RequestBodyLimitLayer::new(args.max_body_size))
         .layer(
             CorsLayer::new()
                 .allow_origin(Any)
-                .allow_methods(Any)
+                // list methods explicitly so preflights get a concrete Allow-Methods header
+                .allow_methods([Method::GET, Method::PUT, Method::POST, Method::OPTIONS])
                 .allow_headers(Any),
         );
 
+    // One access log line per request with its status and latency. Query strings carry
+    // names and addresses, so only the path is logged unless asked for
+    let log_query_strings = args.log_query_strings;
+    let app = app.layer(
+        TraceLayer::new_for_http()
+            .make_span_with(move |request: &Request<Body>| {
+                let uri = if log_query_strings {
+                    request.uri().to_string()
+                } else {
+                    request.uri().path().to_string()
+                };
+                info_span!("http_request", method = %request.method(), uri = %uri)
+            })
+            .on_response(
+                DefaultOnResponse::new()
+                    .level(Level::INFO)
+                    .latency_unit(LatencyUnit::Millis),
+            ),
+    );
+
+    // gzip or brotli for clients that send Accept-Encoding, event streams stay uncompressed
+    let app = if args.no_compression {
+        app
+    } else {
+        app.layer(CompressionLayer::new())
+    };
+
+    // clap guarantees the certificate and key are given together
+    let tls = args.tls_cert.clone().zip(args.tls_key.clone());
+
+    // Reported by the client worker when it cannot import or deploy the contract
+    let (startup_failed, startup_failure) = oneshot::channel::<anyhow::Error>();
+
     // Spawn a local task to handle client operations
     local.spawn_local(async move {
-        info!("Creating client and deploying mns account");
-        let mut client = create_client().await;
-        let _ = client.sync_state().await.unwrap();
-        let deployed_account_id = AccountId::from_hex(CONTRACT_ID).unwrap();
-
-        // Check if we should force deploy a new contract
-        let account = if args.force_deploy {
-            info!("Forced deployment flag is set, deploying a new contract");
-            let new_account = create_account(&mut client).await;
-            let _ = deploy_account(&mut client, &new_account).await;
-            info!("Client initialized and new MNS account deployed successfully");
-            new_account
-        } else {
-            // Try to import existing account or create a new one
-            match client.import_account_by_id(deployed_account_id).await {
-                Ok(()) => {
-                    // Successfully imported, now retrieve it
-                    match client.get_account(deployed_account_id).await {
-                        Ok(Some(account_record)) => {
-                            info!("Successfully imported existing MNS contract account");
-                            account_record.account().clone()
-                        }
-                        Ok(None) => {
-                            panic!(
-                                "Imported account from blockchain but it's not present in client"
-                            )
+        // Failures up to here stop the server instead of leaving it unready forever
+        let rpc = rpc_client(args.rpc_timeout_ms);
+        let startup = async {
+            info!("Creating client and deploying mns account");
+            let mut client = create_client_with_rpc(rpc.clone(), &args.keystore_dir).await?;
+            client
+                .sync_state()
+                .await
+                .map_err(|e| anyhow!("Failed to sync client state: {}", e))?;
+
+            // The account recorded by a previous deployment. There is no built-in default,
+            // contracts built from older sources fail the code and storage checks below
+            let deployed_account_id = match load_deployment(DEPLOYMENT_FILE) {
+                Some(deployment) => {
+                    info!(
+                        "Using deployed account {} from {}",
+                        deployment.account_id, DEPLOYMENT_FILE
+                    );
+                    let account_id = AccountId::from_hex(&deployment.account_id).map_err(|e| {
+                        anyhow!(
+                            "Invalid contract account id '{}': {}",
+                            deployment.account_id,
+                            e
+                        )
+                    })?;
+                    Some(account_id)
+                }
+                None => None,
+            };
+
+            // Check if we should force deploy a new contract
+            let account = if args.force_deploy {
+                info!("Forced deployment flag is set, deploying a new contract");
+                let new_account = match deploy_new_account(
+                    &mut client,
+                    deploy_seed,
+                    args.deploy_anchor_block,
+                    &retry_policy,
+                    DEPLOYMENT_FILE,
+                )
+                .await
+                {
+                    Ok(account) => account,
+                    Err(e) => bail!("Failed to deploy MNS account: {}", e),
+                };
+                info!("Client initialized and new MNS account deployed successfully");
+                new_account
+            } else if let Some(deployed_account_id) = deployed_account_id {
+                // Try to import existing account or create a new one
+                match client.import_account_by_id(deployed_account_id).await {
+                    Ok(()) => {
+                        // Successfully imported, now retrieve it
+                        match client.get_account(deployed_account_id).await {
+                            Ok(Some(account_record)) => {
+                                info!("Successfully imported existing MNS contract account");
+                                account_record.account().clone()
+                            }
+                            Ok(None) if args.read_only => {
+                                bail!(
+                                    "Imported account from blockchain but it's not present in client, cannot redeploy in read-only mode"
+                                )
+                            }
+                            Ok(None) => {
+                                // Treat a store inconsistency like a missing account and redeploy
+                                warn!(
+                                    "Imported account from blockchain but it's not present in client, deploying a new one"
+                                );
+                                let new_account = match deploy_new_account(
+                                    &mut client,
+                                    deploy_seed,
+                                    args.deploy_anchor_block,
+                                    &retry_policy,
+                                    DEPLOYMENT_FILE,
+                                )
+                                .await
+                                {
+                                    Ok(account) => account,
+                                    Err(e) => bail!("Failed to redeploy MNS account: {}", e),
+                                };
+                                info!("Client initialized and MNS account redeployed successfully");
+                                new_account
+                            }
+                            Err(err) => bail!("Failed to retrieve imported account: {}", err),
                         }
-                        Err(err) => panic!("Failed to retrieve imported account: {}", err),
+                    }
+                    Err(err) if args.read_only => {
+                        bail!(
+                            "Account not found on chain, cannot deploy in read-only mode: {}",
+                            err
+                        )
+                    }
+                    Err(err) => {
+                        // Account doesn't exist on chain, create and deploy a new one
+                        info!("Account not found on chain: {}", err);
+                        let new_account = match deploy_new_account(
+                            &mut client,
+                            deploy_seed,
+                            args.deploy_anchor_block,
+                            &retry_policy,
+                            DEPLOYMENT_FILE,
+                        )
+                        .await
+                        {
+                            Ok(account) => account,
+                            Err(e) => bail!("Failed to deploy MNS account: {}", e),
+                        };
+                        info!("Client initialized and MNS account deployed successfully");
+                        new_account
                     }
                 }
-                Err(err) => {
-                    // Account doesn't exist on chain, create and deploy a new one
-                    info!("Account not found on chain: {}", err);
-                    let new_account = create_account(&mut client).await;
-                    let _ = deploy_account(&mut client, &new_account).await;
-                    info!("Client initialized and MNS account deployed successfully");
-                    new_account
+            } else if args.read_only {
+                bail!(
+                    "No {} to load the contract from, cannot deploy in read-only mode",
+                    DEPLOYMENT_FILE
+                )
+            } else {
+                info!("No {} yet, deploying a new contract", DEPLOYMENT_FILE);
+                let new_account = match deploy_new_account(
+                    &mut client,
+                    deploy_seed,
+                    args.deploy_anchor_block,
+                    &retry_policy,
+                    DEPLOYMENT_FILE,
+                )
+                .await
+                {
+                    Ok(account) => account,
+                    Err(e) => bail!("Failed to deploy MNS account: {}", e),
+                };
+                info!("Client initialized and MNS account deployed successfully");
+                new_account
+            };
+
+            // Shard 0 is the account above, names hash onto the others
+            let mut shards = vec![account];
+            for shard in 1..args.shards {
+                match load_or_deploy_shard(
+                    &mut client,
+                    shard,
+                    deploy_seed,
+                    args.deploy_anchor_block,
+                    &retry_policy,
+                    args.read_only,
+                )
+                .await
+                {
+                    Ok(account) => shards.push(account),
+                    Err(e) => bail!("Failed to set up shard {}: {}", shard, e),
+                }
+            }
+            // A wrong contract id or deployment file would otherwise only show up as failing
+            // lookups
+            for account in &shards {
+                match verify_mns_account(account) {
+                    Ok(()) => {}
+                    Err(e) if args.allow_code_mismatch => warn!("{}", e),
+                    Err(e) => bail!("{}, pass --allow-code-mismatch to start anyway", e),
                 }
+                // the scripts index slots directly, so a different layout is never usable
+                verify_storage_layout(account)?;
+            }
+            if args.self_test {
+                for account in &shards {
+                    service::self_test(&mut client, account.id(), &retry_policy)
+                        .await
+                        .with_context(|| format!("Self-test failed on {}", account.id()))?;
+                }
+                info!("Self-test passed on {} shard(s)", shards.len());
+            }
+            Ok::<_, anyhow::Error>((client, shards))
+        }
+        .await;
+        let (mut client, shards) = match startup {
+            Ok(started) => started,
+            Err(e) => {
+                let _ = startup_failed.send(e);
+                return;
             }
         };
+        let account = shards[0].clone();
+        let shard_id = |name: &Name| shards[shard_for(name, shards.len())].id();
+        let shard_ids: Vec<AccountId> = shards.iter().map(|shard| shard.id()).collect();
 
-        // Process client operations from the queue
-        while let Some(request) = rx.recv().await {
-            match request {
-                ClientRequest::Lookup { params, respond } => {
-                    let name = params.get("name").cloned().unwrap_or_default();
-                    info!("Processing lookup request with name: {}", name);
+        let _ = contract_ids.set(shards.iter().map(|shard| shard.id().to_hex()).collect());
+        ready.store(true, Ordering::Release);
+        info!(
+            "MNS contract {} ready with {} shard(s), accepting client requests",
+            account.id(),
+            shards.len()
+        );
 
-                    let result = service::lookup(&mut client, account.id(), name).await;
-                    if let Err(ref e) = result {
-                        info!("Lookup error: {:?}", e);
+        // Process client operations from the queue, syncing periodically in between
+        let mut sync_interval = tokio::time::interval(Duration::from_secs(args.sync_interval));
+        sync_interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut synced_at = None;
+        // tokio intervals cannot tick every zero seconds, a zero period disables the branch
+        let keep_alive_period = Duration::from_secs(args.rpc_keep_alive_secs);
+        let mut keep_alive_interval =
+            tokio::time::interval(keep_alive_period.max(Duration::from_secs(1)));
+        keep_alive_interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut node_used_at = Instant::now();
+        loop {
+            tokio::select! {
+                _ = sync_interval.tick() => {
+                    match retry::sync_state(&mut client, &retry_policy).await {
+                        Ok(_) => {
+                            synced_at = SystemTime::now()
+                                .duration_since(UNIX_EPOCH)
+                                .ok()
+                                .map(|elapsed| elapsed.as_secs());
+                        }
+                        Err(e) => warn!("Periodic state sync failed: {}", e),
                     }
-                    let _ = respond.send(result);
+                    node_used_at = Instant::now();
+                    events::refresh(&mut client, &tx_events).await;
                 }
-                ClientRequest::Register { params, respond } => {
-                    let name = params.get("name").cloned().unwrap_or_default();
-                    let address = params.get("address").cloned().unwrap_or_default();
-                    info!(
-                        "Processing register request with name: {} and address: {}",
-                        name, address
+                _ = keep_alive_interval.tick(), if !keep_alive_period.is_zero() => {
+                    if node_used_at.elapsed() >= keep_alive_period {
+                        if let Err(e) = keep_alive(&rpc).await {
+                            warn!("{}", e);
+                        }
+                        node_used_at = Instant::now();
+                    }
+                }
+                request = rx.recv() => {
+                    let Some(request) = request else {
+                        break;
+                    };
+                    // Everything logged while handling the request carries its id
+                    let span = info_span!(
+                        "client_request",
+                        request_id = request.request_id(),
+                        kind = request.kind()
                     );
+                    async {
+                        match request {
+                            ClientRequest::Lookup { name, respond, .. } => {
+                                info!("Processing lookup request with name: {}", name);
+
+                                let result =
+                                    service::lookup(&mut client, shard_id(&name), name).await;
+                                if let Err(ref e) = result {
+                                    info!("Lookup error: {:?}", e);
+                                }
+                                let _ = respond.send(result);
+                            }
+                            ClientRequest::Register {
+                                name,
+                                address,
+                                owner,
+                                respond,
+                                ..
+                            } => {
+                                info!(
+                                    "Processing register request with name: {} and address: {}",
+                                    name, address
+                                );
+
+                                let result = service::register(
+                                    &mut client,
+                                    shard_id(&name),
+                                    name.clone(),
+                                    address.clone(),
+                                    owner,
+                                    &retry_policy,
+                                )
+                                .await;
+                                if let Ok(response) = &result {
+                                    info!(
+                                        "Successfully registered {} with address {}",
+                                        name, address
+                                    );
+                                    if let Some(transaction_id) = &response.transaction_id {
+                                        tx_events.update(
+                                            transaction_id,
+                                            TransactionState::Pending,
+                                            None,
+                                        );
+                                    }
+                                } else if let Err(ref e) = result {
+                                    info!("Registration error: {:?}", e);
+                                }
+                                let _ = respond.send(result);
+                            }
+                            ClientRequest::Availability { name, respond, .. } => {
+                                info!("Processing availability check for name: {}", name);
+
+                                let result =
+                                    service::is_available(&mut client, shard_id(&name), name).await;
+                                if let Err(ref e) = result {
+                                    info!("Availability check error: {:?}", e);
+                                }
+                                let _ = respond.send(result);
+                            }
+                            ClientRequest::Health { respond, .. } => {
+                                info!("Processing deep health check");
+
+                                // a sync is the cheapest call that proves the RPC is reachable
+                                let rpc_ok = match client.sync_state().await {
+                                    Ok(_) => true,
+                                    Err(e) => {
+                                        warn!("Health check sync failed: {}", e);
+                                        false
+                                    }
+                                };
+                                let block_height =
+                                    client.get_sync_height().await.ok().map(|h| h.as_u32());
+                                let _ = respond.send(WorkerHealth {
+                                    rpc_ok,
+                                    block_height,
+                                });
+                            }
+                            ClientRequest::Storage {
+                                cursor,
+                                limit,
+                                respond,
+                                ..
+                            } => {
+                                info!("Processing storage request (limit {})", limit);
+
+                                let result =
+                                    service::list_entries(&mut client, &shard_ids, cursor, limit)
+                                        .await;
+                                if let Err(ref e) = result {
+                                    info!("Storage error: {:?}", e);
+                                }
+                                let _ = respond.send(result);
+                            }
+                            ClientRequest::DebugLookup { name, respond, .. } => {
+                                info!("Processing debug lookup request with name: {}", name);
 
-                    let result =
-                        service::register(&mut client, account.id(), name.clone(), address.clone())
-                            .await;
-                    if let Ok(_) = &result {
-                        info!("Successfully registered {} with address {}", name, address);
-                    } else if let Err(ref e) = result {
-                        info!("Registration error: {:?}", e);
+                                let result =
+                                    service::debug_lookup(&mut client, shard_id(&name), name).await;
+                                if let Err(ref e) = result {
+                                    info!("Debug lookup error: {:?}", e);
+                                }
+                                let _ = respond.send(result);
+                            }
+                            ClientRequest::ExportStorage {
+                                cursor,
+                                limit,
+                                respond,
+                                ..
+                            } => {
+                                info!("Processing storage export (limit {})", limit);
+
+                                let result = service::export_storage(
+                                    &mut client,
+                                    &shard_ids,
+                                    cursor,
+                                    limit,
+                                )
+                                .await;
+                                if let Err(ref e) = result {
+                                    info!("Storage export error: {:?}", e);
+                                }
+                                let _ = respond.send(result);
+                            }
+                            ClientRequest::SyncInfo { respond, .. } => {
+                                info!("Processing sync info request");
+
+                                let result = client
+                                    .get_sync_height()
+                                    .await
+                                    .map(|height| SyncInfoResponse {
+                                        block_num: height.as_u32(),
+                                        synced_at,
+                                    })
+                                    .map_err(|e| {
+                                        AppError::Internal(format!(
+                                            "Failed to read sync height: {}",
+                                            e
+                                        ))
+                                    });
+                                let _ = respond.send(result);
+                            }
+                        }
                     }
-                    let _ = respond.send(result);
+                    .instrument(span)
+                    .await;
                 }
             }
         }
@@ -156,12 +972,40 @@ async fn main() {
 
     // Run the LocalSet and the server concurrently
     let server = async {
-        info!("Starting server on 0.0.0.0:3001");
-        let listener = TcpListener::bind("0.0.0.0:3001").await.unwrap();
-        info!("Server listening on 0.0.0.0:3001");
-        axum::serve(listener, app).await.unwrap();
+        match tls {
+            Some((cert, key)) => {
+                info!("Starting HTTPS server on 0.0.0.0:3001");
+                let config = RustlsConfig::from_pem_file(&cert, &key)
+                    .await
+                    .with_context(|| {
+                        format!("Failed to load TLS certificate {} and key {}", cert, key)
+                    })?;
+                let addr = SocketAddr::from(([0, 0, 0, 0], 3001));
+                info!("Server listening on https://0.0.0.0:3001");
+                axum_server::bind_rustls(addr, config)
+                    .serve(app.into_make_service())
+                    .await
+                    .context("HTTPS server failed")?;
+            }
+            None => {
+                info!("Starting server on 0.0.0.0:3001");
+                let listener = TcpListener::bind("0.0.0.0:3001")
+                    .await
+                    .context("Failed to bind 0.0.0.0:3001")?;
+                info!("Server listening on 0.0.0.0:3001");
+                axum::serve(listener, app).await.context("Server failed")?;
+            }
+        }
+        Ok(())
     };
 
     info!("Server initialized and ready to accept connections");
-    local.run_until(server).await;
+    local
+        .run_until(async {
+            tokio::select! {
+                result = server => result,
+                Ok(e) = startup_failure => Err(e),
+            }
+        })
+        .await
 }
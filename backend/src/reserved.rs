@@ -0,0 +1,82 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+use crate::error::{AppError, Result};
+
+/// Names held back from public registration, loaded from `--reserved-names`.
+///
+/// The file lists one name per line, `#` starts a comment. An entry ending in `*` reserves
+/// every name starting with the rest, so `admin*` covers `admin.miden` and
+/// `administrator.miden`. Matching ignores ASCII case.
+#[derive(Debug, Default)]
+pub struct ReservedNames {
+    exact: HashSet<String>,
+    prefixes: Vec<String>,
+}
+
+impl ReservedNames {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)
+            .map_err(|e| AppError::Internal(format!("Failed to read {}: {}", path.display(), e)))?;
+        Ok(Self::parse(&contents))
+    }
+
+    pub fn parse(contents: &str) -> Self {
+        let mut reserved = Self::default();
+        for line in contents.lines() {
+            let entry = line
+                .split('#')
+                .next()
+                .unwrap_or("")
+                .trim()
+                .to_ascii_lowercase();
+            if entry.is_empty() {
+                continue;
+            }
+            match entry.strip_suffix('*') {
+                Some(prefix) => reserved.prefixes.push(prefix.to_string()),
+                None => {
+                    reserved.exact.insert(entry);
+                }
+            }
+        }
+        reserved
+    }
+
+    pub fn len(&self) -> usize {
+        self.exact.len() + self.prefixes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn is_reserved(&self, name: &str) -> bool {
+        let name = name.to_ascii_lowercase();
+        self.exact.contains(&name) || self.prefixes.iter().any(|prefix| name.starts_with(prefix))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::reserved::ReservedNames;
+
+    #[test]
+    fn test_reserved_names() {
+        let reserved = ReservedNames::parse(
+            "# operator names\n\
+             admin.miden\n\
+             \n\
+             miden*   # the project itself\n",
+        );
+        assert_eq!(reserved.len(), 2);
+
+        assert!(reserved.is_reserved("admin.miden"));
+        assert!(reserved.is_reserved("Admin.Miden"));
+        assert!(reserved.is_reserved("midenfoundation.miden"));
+        assert!(!reserved.is_reserved("admins.miden"));
+        assert!(!reserved.is_reserved("alice.miden"));
+    }
+}
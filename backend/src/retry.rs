@@ -0,0 +1,302 @@
+use futures::future::{FutureExt, LocalBoxFuture};
+use log::warn;
+use miden_client::{
+    Client, ClientError, rpc::RpcError, sync::SyncSummary, transaction::TransactionResult,
+};
+use std::time::Duration;
+
+/// Substrings of RPC error messages that indicate a transient network condition
+const TRANSIENT_MARKERS: [&str; 7] = [
+    "unavailable",
+    "deadline",
+    "timeout",
+    "timed out",
+    "connection",
+    "resource exhausted",
+    "resourceexhausted",
+];
+
+/// Longest `Retry-After` delay honoured unless the policy sets its own
+pub const DEFAULT_MAX_RETRY_AFTER: Duration = Duration::from_secs(10);
+
+/// Retry policy for RPC calls made by the client
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub base_delay: Duration,
+    /// Cap on a delay the node asks for, the wait blocks the single client worker
+    pub max_retry_after: Duration,
+}
+
+impl RetryPolicy {
+    pub fn new(max_retries: u32, base_delay_ms: u64) -> Self {
+        Self {
+            max_retries,
+            base_delay: Duration::from_millis(base_delay_ms),
+            max_retry_after: DEFAULT_MAX_RETRY_AFTER,
+        }
+    }
+
+    pub fn with_max_retry_after(mut self, max_retry_after: Duration) -> Self {
+        self.max_retry_after = max_retry_after;
+        self
+    }
+
+    /// Delay to wait before the given retry attempt (0-based), doubling each time
+    pub fn delay_for(&self, attempt: u32) -> Duration {
+        self.base_delay.saturating_mul(2u32.saturating_pow(attempt))
+    }
+}
+
+/// Returns true if the error is a transient network failure worth retrying.
+///
+/// Execution and proving failures are deterministic and are never retried.
+pub fn is_transient(err: &ClientError) -> bool {
+    match err {
+        ClientError::RpcError(RpcError::ConnectionError(_)) => true,
+        ClientError::RpcError(RpcError::RequestError(_, msg)) => {
+            let msg = msg.to_lowercase();
+            TRANSIENT_MARKERS.iter().any(|marker| msg.contains(marker))
+        }
+        _ => false,
+    }
+}
+
+/// How a failed attempt should be retried
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Retry {
+    /// The failure is permanent
+    No,
+    /// Transient failure, wait for the policy's exponential backoff
+    Backoff,
+    /// The server asked to be called again after this long, e.g. with `Retry-After`
+    After(Duration),
+}
+
+/// Delay hint carried by a rate limiting or unavailability error message, such as
+/// `retry-after: 2` or `retry after 2s`, in seconds
+pub fn retry_after_hint(msg: &str) -> Option<Duration> {
+    let msg = msg.to_lowercase();
+    let start = msg
+        .find("retry-after")
+        .or_else(|| msg.find("retry after"))?
+        + "retry after".len();
+    let seconds: String = msg[start..]
+        .trim_start_matches([':', ' ', '='])
+        .chars()
+        .take_while(char::is_ascii_digit)
+        .collect();
+    seconds.parse().ok().map(Duration::from_secs)
+}
+
+/// Classify a client error, honouring a `Retry-After` hint from the node when it gives one
+pub fn classify(err: &ClientError) -> Retry {
+    if !is_transient(err) {
+        return Retry::No;
+    }
+    match err {
+        ClientError::RpcError(RpcError::RequestError(_, msg)) => {
+            retry_after_hint(msg).map_or(Retry::Backoff, Retry::After)
+        }
+        _ => Retry::Backoff,
+    }
+}
+
+/// Run `op` against `target` until it succeeds, fails permanently or the policy's retries
+/// are used up. `classify` decides how each failure is retried; a requested delay counts
+/// as a retry and is cut to the policy's `max_retry_after`.
+pub async fn with_retry<S, T, E, F>(
+    target: &mut S,
+    policy: &RetryPolicy,
+    what: &str,
+    classify: impl Fn(&E) -> Retry,
+    mut op: F,
+) -> std::result::Result<T, E>
+where
+    E: std::fmt::Display,
+    F: for<'a> FnMut(&'a mut S) -> LocalBoxFuture<'a, std::result::Result<T, E>>,
+{
+    let mut attempt = 0;
+    loop {
+        let e = match op(target).await {
+            Ok(value) => return Ok(value),
+            Err(e) => e,
+        };
+        let delay = match classify(&e) {
+            _ if attempt >= policy.max_retries => return Err(e),
+            Retry::No => return Err(e),
+            Retry::Backoff => policy.delay_for(attempt),
+            Retry::After(delay) => delay.min(policy.max_retry_after),
+        };
+        warn!(
+            "Transient error {} (attempt {}/{}), retrying in {:?}: {}",
+            what,
+            attempt + 1,
+            policy.max_retries,
+            delay,
+            e
+        );
+        tokio::time::sleep(delay).await;
+        attempt += 1;
+    }
+}
+
+/// Sync the client state, retrying transient RPC failures with exponential backoff
+pub async fn sync_state(
+    client: &mut Client,
+    policy: &RetryPolicy,
+) -> std::result::Result<SyncSummary, ClientError> {
+    with_retry(client, policy, "syncing state", classify, |client| {
+        client.sync_state().boxed_local()
+    })
+    .await
+}
+
+/// Submit a transaction, retrying transient RPC failures with exponential backoff or
+/// after the delay the node asks for
+pub async fn submit_transaction(
+    client: &mut Client,
+    tx_result: TransactionResult,
+    policy: &RetryPolicy,
+) -> std::result::Result<(), ClientError> {
+    with_retry(
+        client,
+        policy,
+        "submitting transaction",
+        classify,
+        |client| client.submit_transaction(tx_result.clone()).boxed_local(),
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::FutureExt;
+    use std::fmt;
+    use std::time::{Duration, Instant};
+
+    use super::{Retry, RetryPolicy, retry_after_hint, with_retry};
+
+    // Stand-in for an HTTP service answering 503 with a `Retry-After` hint once
+    struct FlakyServer {
+        calls: u32,
+        retry_after: Duration,
+    }
+
+    #[derive(Debug)]
+    enum HttpError {
+        Unavailable(Duration),
+        BadRequest,
+    }
+
+    impl fmt::Display for HttpError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                HttpError::Unavailable(after) => write!(f, "503, retry after {:?}", after),
+                HttpError::BadRequest => write!(f, "400"),
+            }
+        }
+    }
+
+    fn classify(err: &HttpError) -> Retry {
+        match err {
+            HttpError::Unavailable(after) => Retry::After(*after),
+            HttpError::BadRequest => Retry::No,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retry_honours_retry_after() {
+        let mut server = FlakyServer {
+            calls: 0,
+            retry_after: Duration::from_millis(50),
+        };
+        // the backoff alone would retry almost immediately
+        let policy = RetryPolicy::new(3, 1);
+
+        let started = Instant::now();
+        let result = with_retry(&mut server, &policy, "calling", classify, |server| {
+            async move {
+                server.calls += 1;
+                match server.calls {
+                    1 => Err(HttpError::Unavailable(server.retry_after)),
+                    _ => Ok(server.calls),
+                }
+            }
+            .boxed_local()
+        })
+        .await;
+
+        assert_eq!(result.unwrap(), 2);
+        assert!(started.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[tokio::test]
+    async fn test_retry_after_is_capped() {
+        let mut calls = 0;
+        let policy = RetryPolicy::new(2, 1).with_max_retry_after(Duration::from_millis(10));
+
+        // a day long hint must neither stall the caller nor retry without end
+        let started = Instant::now();
+        let result: Result<(), _> = with_retry(&mut calls, &policy, "calling", classify, |calls| {
+            async move {
+                *calls += 1;
+                Err(HttpError::Unavailable(Duration::from_secs(86_400)))
+            }
+            .boxed_local()
+        })
+        .await;
+
+        assert!(result.is_err());
+        assert_eq!(calls, 3);
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn test_retry_stops_on_permanent_errors() {
+        let mut calls = 0;
+        let policy = RetryPolicy::new(3, 1);
+
+        let result: Result<(), _> = with_retry(&mut calls, &policy, "calling", classify, |calls| {
+            async move {
+                *calls += 1;
+                Err(HttpError::BadRequest)
+            }
+            .boxed_local()
+        })
+        .await;
+
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn test_retry_after_hint() {
+        assert_eq!(
+            retry_after_hint("status: Unavailable, retry-after: 2"),
+            Some(Duration::from_secs(2))
+        );
+        assert_eq!(
+            retry_after_hint("Resource exhausted, retry after 5s"),
+            Some(Duration::from_secs(5))
+        );
+        assert_eq!(retry_after_hint("connection reset"), None);
+    }
+
+    #[test]
+    fn test_backoff_doubles() {
+        let policy = RetryPolicy::new(3, 100);
+        assert_eq!(policy.delay_for(0), Duration::from_millis(100));
+        assert_eq!(policy.delay_for(1), Duration::from_millis(200));
+        assert_eq!(policy.delay_for(2), Duration::from_millis(400));
+    }
+
+    #[test]
+    fn test_backoff_saturates() {
+        let policy = RetryPolicy::new(3, 100);
+        assert_eq!(
+            policy.delay_for(64),
+            Duration::from_millis(100).saturating_mul(u32::MAX)
+        );
+    }
+}
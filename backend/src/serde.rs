@@ -1,41 +1,160 @@
-use miden_client::{Felt, Word};
+use ::serde::{Deserialize, Serialize};
+use miden_client::{Felt, Word, ZERO, account::AccountId};
+use std::fmt;
+use std::ops::Deref;
+use std::str::FromStr;
+use unicode_segmentation::UnicodeSegmentation;
 
-pub fn str_to_word(s: &str) -> Word {
-    println!("Received string: {s}");
+use crate::error::{AppError, ErrorCode, Result};
 
-    let bytes = s.as_bytes();
+/// Validates a name against the configured length limit and the hard [`MAX_NAME_BYTES`]
+/// encoding limit.
+///
+/// The configured limit counts user-visible characters (grapheme clusters), so an emoji
+/// built from several code points counts once.
+pub fn validate_name(name: &str, max_len: usize) -> Result<()> {
+    let len = name.graphemes(true).count();
+    if len > max_len {
+        return Err(AppError::Coded {
+            code: ErrorCode::NameTooLong,
+            message: format!(
+                "Name '{}' is too long: {} characters, maximum is {}",
+                name, len, max_len
+            ),
+        });
+    }
 
-    println!("bytes: {:?}", bytes);
+    // the encoding backstop always applies, even if the configured limit is higher. Non
+    // ASCII characters take 2 to 4 bytes each, which is what trips up CJK and emoji names
+    if name.len() > MAX_NAME_BYTES {
+        return Err(AppError::Coded {
+            code: ErrorCode::NameTooLong,
+            message: format!(
+                "Name '{}' is too long: its {} characters take {} bytes in UTF-8, names are \
+                 limited to {} bytes",
+                name,
+                len,
+                name.len(),
+                MAX_NAME_BYTES
+            ),
+        });
+    }
 
-    assert!(bytes.len() <= 24, "string `{s}` is too large");
+    Ok(())
+}
 
-    let mut padded_bytes = vec![0u8; 32];
-    padded_bytes[..bytes.len()].copy_from_slice(bytes);
-    padded_bytes[31] = bytes.len() as u8;
+/// Longest name in bytes that fits the word encoding.
+pub const MAX_NAME_BYTES: usize = 24;
 
-    println!("padded: {:?}", padded_bytes);
+/// A name that passed validation and can always be encoded into a `Word`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Name(String);
 
-    assert!(padded_bytes.len() == 32);
+impl Name {
+    /// Validates a raw name: non-empty, no whitespace or control characters, no empty
+    /// `.`-separated labels, and within both the configured and the encoding length limits
+    pub fn parse(name: &str, max_len: usize) -> Result<Name> {
+        if name.is_empty() {
+            return Err(AppError::Coded {
+                code: ErrorCode::InvalidName,
+                message: "Name cannot be empty".to_string(),
+            });
+        }
 
-    let mut word = Word::default();
+        if name.chars().any(|c| c.is_whitespace() || c.is_control()) {
+            return Err(AppError::Coded {
+                code: ErrorCode::InvalidName,
+                message: format!(
+                    "Name '{}' contains whitespace or control characters",
+                    name.escape_debug()
+                ),
+            });
+        }
 
-    for i in 0..4 {
-        let chunk_start = i * 8;
-        let mut chunk = [0u8; 8];
-        chunk.copy_from_slice(&padded_bytes[chunk_start..chunk_start + 8]);
+        if name.split('.').any(str::is_empty) {
+            return Err(AppError::Coded {
+                code: ErrorCode::InvalidName,
+                message: format!("Name '{}' has an empty label", name),
+            });
+        }
 
-        let value = u64::from_be_bytes(chunk);
-        word[i] = Felt::new(value);
+        validate_name(name, max_len)?;
+
+        Ok(Name(name.to_string()))
+    }
+
+    /// The name this one is a subname of: `alice.miden` for `wallet.alice.miden`.
+    ///
+    /// The last label is the namespace rather than a name, so `alice.miden` has no parent.
+    pub fn parent(&self) -> Option<&str> {
+        let (_, parent) = self.0.split_once('.')?;
+        parent.contains('.').then_some(parent)
+    }
+
+    pub fn to_word(&self) -> Word {
+        str_to_word(&self.0)
+    }
+}
+
+impl Deref for Name {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for Name {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl From<Name> for String {
+    fn from(name: Name) -> String {
+        name.0
+    }
+}
+
+/// Encodes a string of at most [`MAX_NAME_BYTES`] bytes into a `Word`.
+///
+/// The 32-byte layout is `[data (0..24) | zero padding (24..31) | length (31)]`, split
+/// into four big-endian `u64` chunks. The data can never reach the length byte, so the
+/// length lives in the low byte of `w[3]` for every valid input.
+pub fn str_to_word(s: &str) -> Word {
+    let bytes = s.as_bytes();
+    assert!(bytes.len() <= MAX_NAME_BYTES, "string `{s}` is too large");
+
+    // bytes 24..31 stay zero, the length check above keeps the data out of them
+    let mut padded_bytes = [0u8; 32];
+    padded_bytes[..bytes.len()].copy_from_slice(bytes);
+    padded_bytes[31] = bytes.len() as u8;
+
+    let mut word = Word::default();
+    for (felt, chunk) in word.iter_mut().zip(padded_bytes.chunks_exact(8)) {
+        let chunk: [u8; 8] = chunk.try_into().expect("chunks are 8 bytes");
+        *felt = Felt::new(u64::from_be_bytes(chunk));
     }
 
     word
 }
 
-pub fn word_to_str(w: Word) -> String {
+/// Decodes a `Word` produced by [`str_to_word`], reading the length from byte 31.
+///
+/// Fails if the length byte is above [`MAX_NAME_BYTES`], which no encoded string has.
+pub fn word_to_str(w: Word) -> Result<String> {
     // Get the string length from the last byte of the last Felt
     let last_bytes = w[3].as_int().to_be_bytes();
     let len = last_bytes[7] as usize;
 
+    // a corrupted word could otherwise decode its own padding and length byte
+    if len > MAX_NAME_BYTES {
+        return Err(AppError::Internal(format!(
+            "Word reports a string length of {} bytes, maximum is {}",
+            len, MAX_NAME_BYTES
+        )));
+    }
+
     // Create a buffer for the full 32 bytes
     let mut bytes = Vec::with_capacity(32);
 
@@ -49,18 +168,238 @@ pub fn word_to_str(w: Word) -> String {
     bytes.truncate(len);
 
     // Convert to string
-    String::from_utf8(bytes).unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).into_owned())
+    Ok(String::from_utf8(bytes)
+        .unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).into_owned()))
+}
+
+/// Marker stored in the low byte of `w[3]` for words holding a native account id.
+///
+/// String encodings store their length there, which is never above [`MAX_NAME_BYTES`].
+const ACCOUNT_ID_MARKER: u64 = 0xff;
+
+/// Encodes an account id losslessly as `[prefix, suffix, 0, ACCOUNT_ID_MARKER]`.
+pub fn account_id_to_word(id: AccountId) -> Word {
+    let [prefix, suffix]: [Felt; 2] = id.into();
+    [prefix, suffix, ZERO, Felt::new(ACCOUNT_ID_MARKER)]
+}
+
+/// Decodes a word produced by [`account_id_to_word`], or `None` for any other word.
+pub fn word_to_account_id(w: Word) -> Option<AccountId> {
+    if w[3].as_int() != ACCOUNT_ID_MARKER || w[2] != ZERO {
+        return None;
+    }
+    AccountId::try_from([w[0], w[1]]).ok()
+}
+
+/// Encodes an address, storing hex account ids natively and anything else as a string.
+pub fn address_to_word(address: &str) -> Word {
+    match AccountId::from_hex(address) {
+        Ok(id) => account_id_to_word(id),
+        Err(_) => str_to_word(address),
+    }
+}
+
+/// Canonical form of an address before it is stored on-chain: account ids as
+/// [`AccountId::to_hex`] prints them, which is how lookups read them back, anything else
+/// as given.
+///
+/// `0x` hex that is not a valid account id is rejected instead of being stored as a string.
+pub fn canonical_address(address: &str) -> Result<String> {
+    let looks_like_id = address
+        .strip_prefix("0x")
+        .is_some_and(|hex| !hex.is_empty() && hex.chars().all(|c| c.is_ascii_hexdigit()));
+    if !looks_like_id {
+        return Ok(address.to_string());
+    }
+
+    AccountId::from_hex(address)
+        .map(|id| id.to_hex())
+        .map_err(|e| AppError::Coded {
+            code: ErrorCode::InvalidAddress,
+            message: format!("Address '{}' is not a valid account id: {}", address, e),
+        })
+}
+
+/// Decodes a word produced by [`address_to_word`].
+pub fn word_to_address(w: Word) -> Result<String> {
+    match word_to_account_id(w) {
+        Some(id) => Ok(id.to_hex()),
+        None => word_to_str(w),
+    }
+}
+
+/// Bit offset in `w[3]` of the version tag on on-chain address values.
+///
+/// This is byte 30 of the layout, which both the string padding and the account id
+/// encoding leave at zero, so untagged values decode with tag 0.
+const VERSION_TAG_SHIFT: u64 = 8;
+
+/// Where a registration lives, written as `"2"`, `"2.5"` or `"3"` on the wire and in the
+/// database.
+///
+/// Version 2 names are kept in the database, 2.5 names in the contract. Version 3 is only
+/// ever read back from contract values tagged by a newer writer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum RecordVersion {
+    #[serde(rename = "2")]
+    Web2,
+    #[serde(rename = "2.5")]
+    Web2_5,
+    #[serde(rename = "3")]
+    Web3,
+}
+
+impl RecordVersion {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RecordVersion::Web2 => "2",
+            RecordVersion::Web2_5 => "2.5",
+            RecordVersion::Web3 => "3",
+        }
+    }
+}
+
+impl FromStr for RecordVersion {
+    type Err = AppError;
+
+    fn from_str(version: &str) -> Result<Self> {
+        match version {
+            "2" => Ok(RecordVersion::Web2),
+            "2.5" => Ok(RecordVersion::Web2_5),
+            "3" => Ok(RecordVersion::Web3),
+            _ => Err(AppError::Coded {
+                code: ErrorCode::InvalidVersion,
+                message: format!("Invalid version '{}', expected one of: 2, 2.5, 3", version),
+            }),
+        }
+    }
+}
+
+impl fmt::Display for RecordVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Tag stored alongside an on-chain address for a registration version.
+pub fn version_to_tag(version: RecordVersion) -> Option<u8> {
+    match version {
+        RecordVersion::Web2 => None,
+        RecordVersion::Web2_5 => Some(1),
+        RecordVersion::Web3 => Some(2),
+    }
+}
+
+/// Registration version of a tag, values stored before tagging (tag 0) are 2.5.
+pub fn tag_to_version(tag: u8) -> Option<RecordVersion> {
+    match tag {
+        0 | 1 => Some(RecordVersion::Web2_5),
+        2 => Some(RecordVersion::Web3),
+        _ => None,
+    }
+}
+
+/// Encodes an address with its registration version tag, see [`address_to_word`].
+pub fn address_to_tagged_word(address: &str, version: RecordVersion) -> Result<Word> {
+    let tag = version_to_tag(version).ok_or_else(|| AppError::Coded {
+        code: ErrorCode::InvalidVersion,
+        message: format!("Version '{}' cannot be stored on-chain", version),
+    })?;
+
+    let mut word = address_to_word(address);
+    word[3] = Felt::new(word[3].as_int() | ((tag as u64) << VERSION_TAG_SHIFT));
+    Ok(word)
+}
+
+/// Decodes a word produced by [`address_to_tagged_word`] into the address and version.
+///
+/// Unknown tags fall back to 2.5 so a newer writer never makes a value unreadable.
+pub fn word_to_tagged_address(mut w: Word) -> Result<(String, RecordVersion)> {
+    let tag = (w[3].as_int() >> VERSION_TAG_SHIFT) as u8;
+    w[3] = Felt::new(w[3].as_int() & !(0xff << VERSION_TAG_SHIFT));
+    let version = tag_to_version(tag).unwrap_or(RecordVersion::Web2_5);
+    Ok((word_to_address(w)?, version))
+}
+
+/// Goldilocks prime, felts at or above it are not canonical.
+const FELT_MODULUS: u64 = 0xffff_ffff_0000_0001;
+
+/// Formats a word as `0x` followed by its four felts as 16 big-endian hex digits each.
+pub fn word_to_hex(w: Word) -> String {
+    let mut hex = String::with_capacity(66);
+    hex.push_str("0x");
+    for felt in w.iter() {
+        hex.push_str(&format!("{:016x}", felt.as_int()));
+    }
+    hex
+}
+
+/// Parses a word formatted by [`word_to_hex`], rejecting non-canonical felts.
+pub fn hex_to_word(s: &str) -> Result<Word> {
+    let digits = s.strip_prefix("0x").unwrap_or(s);
+    if digits.len() != 64 || !digits.is_ascii() {
+        return Err(AppError::BadRequest(format!(
+            "Word '{}' must be 64 hex digits",
+            s
+        )));
+    }
+
+    let mut word = Word::default();
+    for i in 0..4 {
+        let chunk = &digits[i * 16..(i + 1) * 16];
+        let value = u64::from_str_radix(chunk, 16)
+            .map_err(|_| AppError::BadRequest(format!("Word '{}' is not valid hex", s)))?;
+        if value >= FELT_MODULUS {
+            return Err(AppError::BadRequest(format!(
+                "Word '{}' has a non-canonical felt at index {}",
+                s, i
+            )));
+        }
+        word[i] = Felt::new(value);
+    }
+
+    Ok(word)
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::serde::{str_to_word, word_to_str};
+    use miden_client::Felt;
+    use proptest::prelude::*;
+
+    use crate::error::{AppError, ErrorCode};
+    use crate::serde::{
+        MAX_NAME_BYTES, Name, RecordVersion, address_to_tagged_word, address_to_word,
+        canonical_address, hex_to_word, str_to_word, validate_name, word_to_account_id,
+        word_to_address, word_to_hex, word_to_str, word_to_tagged_address,
+    };
+
+    // Truncate an arbitrary string to at most `MAX_NAME_BYTES` on a char boundary
+    fn truncate_to_max_bytes(mut s: String) -> String {
+        while s.len() > MAX_NAME_BYTES {
+            s.pop();
+        }
+        s
+    }
+
+    proptest! {
+        #[test]
+        fn prop_word_str_round_trip(s in any::<String>().prop_map(truncate_to_max_bytes)) {
+            prop_assert_eq!(word_to_str(str_to_word(&s)).unwrap(), s);
+        }
+
+        #[test]
+        fn prop_word_str_round_trip_multibyte(
+            s in "[a-z.世界é🦀]{0,24}".prop_map(truncate_to_max_bytes)
+        ) {
+            prop_assert_eq!(word_to_str(str_to_word(&s)).unwrap(), s);
+        }
+    }
 
     #[test]
     fn test_word_str_serde() {
         let s = "mirko.miden";
         let serialized = str_to_word(s);
-        let deserialized = word_to_str(serialized);
+        let deserialized = word_to_str(serialized).unwrap();
         assert_eq!(s, deserialized);
     }
 
@@ -91,7 +430,7 @@ mod tests {
 
         for &s in &test_cases {
             let serialized = str_to_word(s);
-            let deserialized = word_to_str(serialized);
+            let deserialized = word_to_str(serialized).unwrap();
             assert_eq!(s, deserialized, "Failed for string: {}", s);
         }
     }
@@ -100,7 +439,7 @@ mod tests {
     fn test_unicode() {
         let s = "Hello, 世界!"; // Contains non-ASCII Unicode chars
         let serialized = str_to_word(s);
-        let deserialized = word_to_str(serialized);
+        let deserialized = word_to_str(serialized).unwrap();
         assert_eq!(s, deserialized);
     }
 
@@ -109,7 +448,7 @@ mod tests {
         // Create a string exactly 24 bytes long
         let s = "123456789012345678901234"; // 24 ASCII chars = 24 bytes
         let serialized = str_to_word(s);
-        let deserialized = word_to_str(serialized);
+        let deserialized = word_to_str(serialized).unwrap();
         assert_eq!(s, deserialized);
     }
 
@@ -120,12 +459,216 @@ mod tests {
         let s = "123456789012345678世界"; // Should be exactly 24 bytes
         assert_eq!(
             s.as_bytes().len(),
-            24,
-            "Test string is not exactly 24 bytes"
+            MAX_NAME_BYTES,
+            "Test string is not exactly {} bytes",
+            MAX_NAME_BYTES
         );
 
         let serialized = str_to_word(s);
-        let deserialized = word_to_str(serialized);
+        let deserialized = word_to_str(serialized).unwrap();
         assert_eq!(s, deserialized);
     }
+
+    #[test]
+    fn test_validate_name_configured_limit() {
+        assert!(validate_name("alice.miden", 15).is_ok());
+        assert!(validate_name("averyveryverylongname", 15).is_err());
+    }
+
+    #[test]
+    fn test_validate_name_byte_backstop() {
+        // configured limit is higher than the encoding allows
+        assert!(validate_name("012345678901234567890123", 64).is_ok());
+        assert!(validate_name("0123456789012345678901234", 64).is_err());
+        // 9 characters but 27 bytes
+        let message = validate_name("世界世界世界世界世", 64)
+            .unwrap_err()
+            .to_string();
+        assert!(
+            message.contains("9 characters take 27 bytes"),
+            "{}",
+            message
+        );
+        assert!(
+            message.contains(&format!("limited to {} bytes", MAX_NAME_BYTES)),
+            "{}",
+            message
+        );
+    }
+
+    #[test]
+    fn test_validate_name_counts_graphemes() {
+        // a family emoji is one character made of 5 code points and 18 bytes
+        let family = "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}";
+        assert!(validate_name(family, 1).is_ok());
+        assert!(validate_name(&format!("{}a", family), 1).is_err());
+    }
+
+    #[test]
+    fn test_full_length_keeps_length_byte() {
+        let s = "abcdefghijklmnopqrstuvwx"; // 24 bytes, fills the whole data region
+        let word = str_to_word(s);
+
+        // padding bytes 24..31 are zero and byte 31 holds the length
+        let len = MAX_NAME_BYTES as u8;
+        assert_eq!(word[3].as_int().to_be_bytes(), [0, 0, 0, 0, 0, 0, 0, len]);
+        assert_eq!(word_to_str(word).unwrap(), s);
+    }
+
+    #[test]
+    fn test_word_to_str_rejects_bogus_lengths() {
+        let mut word = str_to_word("alice.miden");
+        for len in [25, 31, 32, 0xfe] {
+            word[3] = Felt::new(len);
+            assert!(word_to_str(word).is_err(), "accepted length {}", len);
+        }
+
+        // the largest encodable length still decodes
+        word[3] = Felt::new(MAX_NAME_BYTES as u64);
+        assert_eq!(word_to_str(word).unwrap().len(), MAX_NAME_BYTES);
+    }
+
+    #[test]
+    fn test_over_length_is_rejected() {
+        let s = "abcdefghijklmnopqrstuvwxy"; // 25 bytes would spill into the padding
+        let result = std::panic::catch_unwind(|| str_to_word(s));
+        let message = result.unwrap_err();
+        let message = message.downcast_ref::<String>().unwrap();
+        assert!(message.contains("is too large"), "{}", message);
+    }
+
+    #[test]
+    fn test_account_id_address_round_trip() {
+        let address = "0xdde9bd696d7c6400000432b139e732"; // 32 bytes as a string
+        let word = address_to_word(address);
+        assert!(word_to_account_id(word).is_some());
+        assert_eq!(word_to_address(word).unwrap(), address);
+    }
+
+    #[test]
+    fn test_canonical_address() {
+        let id = "0xdde9bd696d7c6400000432b139e732";
+        assert_eq!(canonical_address(id).unwrap(), id);
+        assert_eq!(
+            canonical_address("https://mirko.dev").unwrap(),
+            "https://mirko.dev"
+        );
+
+        // hex that was meant as an account id must not be stored as a string
+        assert!(canonical_address("0xdde9bd696d7c64").is_err());
+    }
+
+    #[test]
+    fn test_string_address_round_trip() {
+        let address = "https://mirko.dev";
+        let word = address_to_word(address);
+        assert!(word_to_account_id(word).is_none());
+        assert_eq!(word_to_address(word).unwrap(), address);
+    }
+
+    #[test]
+    fn test_hex_word_round_trip() {
+        let word = str_to_word("mirko.miden");
+        let hex = word_to_hex(word);
+        assert_eq!(hex.len(), 66);
+        assert!(hex.ends_with("0b")); // length byte
+        assert_eq!(hex_to_word(&hex).unwrap(), word);
+        assert_eq!(hex_to_word(&hex[2..]).unwrap(), word);
+    }
+
+    #[test]
+    fn test_hex_to_word_rejects_invalid() {
+        assert!(hex_to_word("0x1234").is_err());
+        assert!(hex_to_word(&"z".repeat(64)).is_err());
+        // first felt equals the field modulus
+        let non_canonical = format!("ffffffff00000001{}", "0".repeat(48));
+        assert!(hex_to_word(&non_canonical).is_err());
+    }
+
+    #[test]
+    fn test_tagged_address_round_trip() {
+        for address in ["https://mirko.dev", "0xdde9bd696d7c6400000432b139e732"] {
+            for version in [RecordVersion::Web2_5, RecordVersion::Web3] {
+                let word = address_to_tagged_word(address, version).unwrap();
+                assert_eq!(
+                    word_to_tagged_address(word).unwrap(),
+                    (address.to_string(), version)
+                );
+            }
+        }
+        assert!(address_to_tagged_word("0xabc", RecordVersion::Web2).is_err());
+    }
+
+    #[test]
+    fn test_untagged_address_is_legacy_version() {
+        let word = address_to_word("https://mirko.dev");
+        assert_eq!(
+            word_to_tagged_address(word).unwrap(),
+            ("https://mirko.dev".to_string(), RecordVersion::Web2_5)
+        );
+    }
+
+    #[test]
+    fn test_record_version_round_trip() {
+        for version in [
+            RecordVersion::Web2,
+            RecordVersion::Web2_5,
+            RecordVersion::Web3,
+        ] {
+            assert_eq!(
+                version.to_string().parse::<RecordVersion>().unwrap(),
+                version
+            );
+            let json = serde_json::to_string(&version).unwrap();
+            assert_eq!(json, format!("\"{}\"", version));
+        }
+        assert!(matches!(
+            "2.0".parse::<RecordVersion>(),
+            Err(AppError::Coded {
+                code: ErrorCode::InvalidVersion,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_name_parse() {
+        let name = Name::parse("alice.miden", MAX_NAME_BYTES).unwrap();
+        assert_eq!(&*name, "alice.miden");
+        assert_eq!(word_to_str(name.to_word()).unwrap(), "alice.miden");
+
+        for invalid in [
+            "",
+            "alice miden",
+            "alice\n",
+            ".miden",
+            "alice..miden",
+            "alice.",
+        ] {
+            assert!(
+                Name::parse(invalid, MAX_NAME_BYTES).is_err(),
+                "accepted {:?}",
+                invalid
+            );
+        }
+        assert!(Name::parse("averyveryverylongname", 15).is_err());
+        assert!(Name::parse("0123456789012345678901234", 64).is_err());
+    }
+
+    #[test]
+    fn test_name_parent() {
+        let parent = |name: &str| {
+            Name::parse(name, MAX_NAME_BYTES)
+                .unwrap()
+                .parent()
+                .map(str::to_string)
+        };
+        assert_eq!(parent("wallet.alice.miden").as_deref(), Some("alice.miden"));
+        assert_eq!(
+            parent("a.wallet.alice.miden").as_deref(),
+            Some("wallet.alice.miden")
+        );
+        assert_eq!(parent("alice.miden"), None);
+        assert_eq!(parent("alice"), None);
+    }
 }
@@ -1,69 +1,158 @@
 use std::collections::BTreeSet;
 
-use log::{error, info};
+use log::{debug, error, info, warn};
 use miden_client::{
-    Client, ZERO,
-    account::AccountId,
+    Client, Felt, Word, ZERO,
+    account::{AccountId, StorageSlot},
     transaction::{TransactionRequestBuilder, TransactionScript},
 };
 use miden_lib::transaction::TransactionKernel;
 use miden_objects::vm::AdviceInputs;
 
 use crate::{
-    error::{AppError, Result},
-    handler::{LookupResponse, RegisterResponse},
-    serde::{str_to_word, word_to_str},
-    utils::{LOOKUP_SCRIPT, MNS_CONTRACT, REGISTER_SCRIPT, create_library},
+    error::{AppError, ErrorCode, Result},
+    handler::{
+        DebugLookupResponse, ExportEntry, ExportResponse, LookupOrigin, LookupResponse,
+        RegisterResponse, ResolverRecord, StorageEntry, StorageResponse, record_type_for_version,
+    },
+    retry::{self, RetryPolicy},
+    serde::{
+        MAX_NAME_BYTES, Name, RecordVersion, account_id_to_word, address_to_tagged_word,
+        canonical_address, str_to_word, word_to_hex, word_to_str, word_to_tagged_address,
+    },
+    utils::{create_library, masm},
 };
 
+/// Advice inputs for the MNS transaction scripts.
+///
+/// The contract procedures read their arguments with `adv.push_mapval`, using the word on
+/// top of the stack as the advice map key, then load one word per `adv_loadw`:
+///
+/// - register: `[ADDRESS, NAME, OWNER]`, the address word first
+/// - lookup: `[NAME]`
+///
+/// Each word is laid out in encoding order, `w[0]` first. The default key is the all-zero
+/// word the scripts start with; any other key is pushed by a prelude in [`Self::script`].
+pub struct ScriptInputs {
+    key: Word,
+    felts: Vec<Felt>,
+}
+
+impl ScriptInputs {
+    pub const DEFAULT_KEY: Word = [ZERO, ZERO, ZERO, ZERO];
+
+    pub fn register(name: Word, address: Word, owner: Word) -> Self {
+        let mut felts = Vec::with_capacity(12);
+        felts.extend_from_slice(&address);
+        felts.extend_from_slice(&name);
+        felts.extend_from_slice(&owner);
+        Self {
+            key: Self::DEFAULT_KEY,
+            felts,
+        }
+    }
+
+    pub fn lookup(name: Word) -> Self {
+        Self {
+            key: Self::DEFAULT_KEY,
+            felts: name.to_vec(),
+        }
+    }
+
+    /// Store the inputs under a different advice map key
+    pub fn with_key(mut self, key: Word) -> Self {
+        self.key = key;
+        self
+    }
+
+    pub fn key(&self) -> Word {
+        self.key
+    }
+
+    pub fn felts(&self) -> &[Felt] {
+        &self.felts
+    }
+
+    /// The advice map entry to compile the script with
+    pub fn advice_entry(&self) -> (Word, Vec<Felt>) {
+        (self.key, self.felts.clone())
+    }
+
+    /// The script source, with the key pushed at the start of `begin` when it isn't zero
+    pub fn script(&self, source: &str) -> String {
+        if self.key == Self::DEFAULT_KEY {
+            return source.to_string();
+        }
+
+        // `push.a.b.c.d` leaves `d` on top, which `adv.push_mapval` reads as key `[a, b, c, d]`
+        let push = format!(
+            "begin\n    push.{}.{}.{}.{}",
+            self.key[0].as_int(),
+            self.key[1].as_int(),
+            self.key[2].as_int(),
+            self.key[3].as_int()
+        );
+        source.replacen("begin", &push, 1)
+    }
+}
+
+/// Register `name` in the contract account `account_id`, recording `owner` as the account
+/// that registered it, or the empty word when there is none
 pub async fn register(
     client: &mut Client,
     account_id: AccountId,
-    name: String,
+    name: Name,
     address: String,
+    owner: Option<AccountId>,
+    retry_policy: &RetryPolicy,
 ) -> Result<RegisterResponse> {
-    // Input validation
-    if name.is_empty() {
-        return Err(AppError::BadRequest("Name cannot be empty".to_string()));
-    }
+    // Input validation, the name was validated when it was parsed
     if address.is_empty() {
-        return Err(AppError::BadRequest("Address cannot be empty".to_string()));
+        return Err(AppError::Coded {
+            code: ErrorCode::MissingParameter,
+            message: "Address cannot be empty".to_string(),
+        });
+    }
+    // account ids are stored natively, the response carries the form lookups return
+    let address = canonical_address(&address)?;
+    // other addresses are stored with the name encoding, see `address_to_word`
+    if AccountId::from_hex(&address).is_err() && address.len() > MAX_NAME_BYTES {
+        return Err(AppError::Coded {
+            code: ErrorCode::InvalidAddress,
+            message: format!(
+                "Address '{}' is too long: non account id addresses are limited to {} bytes",
+                address, MAX_NAME_BYTES
+            ),
+        });
     }
 
-    // sync client to latest chain state
-    client.sync_state().await.map_err(|e| {
+    // sync client to latest chain state; registrations need up-to-the-block freshness
+    retry::sync_state(client, retry_policy).await.map_err(|e| {
         error!("Failed to sync client state: {}", e);
         AppError::Internal(format!("Failed to sync blockchain state: {}", e))
     })?;
 
     // compile code
     let assembler = TransactionKernel::assembler().with_debug_mode(true);
-    let component_lib = create_library(assembler.clone(), "mns::mns_contract", &MNS_CONTRACT)
+    let masm = masm();
+    let component_lib = create_library(assembler.clone(), "mns::mns_contract", &masm.contract)
         .map_err(|e| {
             error!("Failed to create library: {}", e);
             AppError::Internal(format!("Contract compilation error: {}", e))
         })?;
 
-    // build inputs
-    let felt_name = str_to_word(&name);
-    let felt_account_id = str_to_word(&address);
+    // build inputs, the stored value carries the version so lookups can report it
+    let felt_name = name.to_word();
+    let felt_account_id = address_to_tagged_word(&address, RecordVersion::Web2_5)?;
     info!("name: {:?}, id: {:?}", felt_name, felt_account_id);
 
+    // the MNS account only submits the transaction, so it never stands in for the owner
+    let felt_owner = owner.map_or([ZERO; 4], account_id_to_word);
+    let inputs = ScriptInputs::register(felt_name, felt_account_id, felt_owner);
+
     let tx_script = TransactionScript::compile(
-        REGISTER_SCRIPT.clone(),
-        [(
-            [ZERO, ZERO, ZERO, ZERO],
-            vec![
-                felt_account_id[0],
-                felt_account_id[1],
-                felt_account_id[2],
-                felt_account_id[3],
-                felt_name[0],
-                felt_name[1],
-                felt_name[2],
-                felt_name[3],
-            ],
-        )],
+        inputs.script(&masm.register),
+        [inputs.advice_entry()],
         assembler.with_library(&component_lib).map_err(|e| {
             error!("Failed to attach library: {}", e);
             AppError::Internal(format!("Script compilation error: {}", e))
@@ -100,54 +189,96 @@ pub async fn register(
     );
 
     // submit tx
-    client.submit_transaction(tx_result).await.map_err(|e| {
-        error!("Failed to submit transaction: {}", e);
-        AppError::Internal(format!("Transaction submission failed: {}", e))
-    })?;
+    retry::submit_transaction(client, tx_result, retry_policy)
+        .await
+        .map_err(|e| {
+            error!("Failed to submit transaction: {}", e);
+            AppError::Internal(format!("Transaction submission failed: {}", e))
+        })?;
 
     // build response
     let response = RegisterResponse {
-        name,
+        name: name.into(),
         address,
-        version: "2.5".to_string(),
+        version: RecordVersion::Web2_5,
         transaction_id: Some(tx_id.to_string()),
+        owner: owner.map(|owner| owner.to_hex()),
+        // the contract keys its map by the encoded name word as-is
+        storage_key: Some(word_to_hex(felt_name)),
+        owner_token: None,
     };
 
     Ok(response)
 }
 
-pub async fn lookup(
-    client: &mut Client,
-    account_id: AccountId,
-    name: String,
-) -> Result<LookupResponse> {
-    // Input validation
-    if name.is_empty() {
-        return Err(AppError::BadRequest("Name cannot be empty".to_string()));
+/// Encode a name for lookup, treating names that can never be registered as not found
+fn encode_lookup_name(name: &str) -> Result<Word> {
+    // `str_to_word` panics past `MAX_NAME_BYTES`, which would take down the client worker
+    if name.len() > MAX_NAME_BYTES {
+        return Err(AppError::NotFound(format!(
+            "Name '{}' not registered",
+            name
+        )));
     }
 
-    // sync client to latest chain state
-    client.sync_state().await.map_err(|e| {
-        error!("Failed to sync chain state: {}", e);
-        AppError::Internal(format!("Failed to sync blockchain state: {}", e))
-    })?;
+    Ok(str_to_word(name))
+}
+
+/// Decode the address and version left on the stack by the lookup script
+fn decode_lookup_stack(name: &str, stack: &[Felt]) -> Result<(String, RecordVersion)> {
+    // A short stack means the contract's output shape changed
+    if stack.len() < 4 {
+        warn!(
+            "Lookup for '{}' returned a stack of length {}, expected at least 4",
+            name,
+            stack.len()
+        );
+        return Err(AppError::NotFound(format!(
+            "Name '{}' not found or returned invalid data",
+            name
+        )));
+    }
+
+    let (address, version) = word_to_tagged_address([stack[3], stack[2], stack[1], stack[0]])?;
+
+    debug!("address: {:?}", address);
+
+    // Check if address is empty or zero (indicating name not found)
+    if address.trim().is_empty() || address == "0000000000000000" {
+        return Err(AppError::NotFound(format!(
+            "Name '{}' not registered",
+            name
+        )));
+    }
+
+    Ok((address, version))
+}
+
+// Run the lookup script for `name` and return the full output stack
+async fn execute_lookup(
+    client: &mut Client,
+    account_id: AccountId,
+    name: &Name,
+) -> Result<Vec<Felt>> {
+    // no explicit sync: the worker keeps the client state current on an interval
 
     // compile code
     let assembler = TransactionKernel::assembler().with_debug_mode(true);
-    let component_lib = create_library(assembler.clone(), "mns::mns_contract", &MNS_CONTRACT)
+    let masm = masm();
+    let component_lib = create_library(assembler.clone(), "mns::mns_contract", &masm.contract)
         .map_err(|e| {
             error!("Failed to create library: {}", e);
             AppError::Internal(format!("Contract compilation error: {}", e))
         })?;
 
     // build inputs
-    // TODO: problem here with some names that error out the tx_executor
-    println!("name: {name}");
-    let felt_name = str_to_word(&name);
-    println!("felt_name: {:?}", felt_name);
+    debug!("name: {name}");
+    let felt_name = encode_lookup_name(name)?;
+    debug!("felt_name: {:?}", felt_name);
+    let inputs = ScriptInputs::lookup(felt_name);
     let tx_script = TransactionScript::compile(
-        LOOKUP_SCRIPT.clone(),
-        [([ZERO, ZERO, ZERO, ZERO], felt_name.to_vec())],
+        inputs.script(&masm.lookup),
+        [inputs.advice_entry()],
         assembler.with_library(&component_lib).map_err(|e| {
             error!("Failed to attach library: {}", e);
             AppError::Internal(format!("Script compilation error: {}", e))
@@ -160,7 +291,7 @@ pub async fn lookup(
 
     // execute transaction locally
 
-    println!("Inputs: id {}", account_id,);
+    debug!("Inputs: id {}", account_id);
 
     let stack = client
         .execute_program(
@@ -170,37 +301,554 @@ pub async fn lookup(
             BTreeSet::default(),
         )
         .await
+        // names the script cannot take are turned away by `encode_lookup_name`, so a failure
+        // here is a server fault and must not read as a free name in `is_available`
         .map_err(|e| {
-            error!("Failed to execute program: {}", e);
+            error!("Failed to execute program for name '{}': {}", name, e);
             AppError::Internal(format!("Program execution failed: {}", e))
         })?;
 
-    if stack.len() < 4 {
-        return Err(AppError::NotFound(format!(
-            "Name '{}' not found or returned invalid data",
-            name
-        )));
+    Ok(stack.to_vec())
+}
+
+pub async fn lookup(
+    client: &mut Client,
+    account_id: AccountId,
+    name: Name,
+) -> Result<LookupResponse> {
+    let stack = execute_lookup(client, account_id, &name).await?;
+    let decoded = decode_lookup_stack(&name, &stack)?;
+    Ok(contract_response(decoded))
+}
+
+// Lookup response for an address and version read from the contract
+fn contract_response((address, version): (String, RecordVersion)) -> LookupResponse {
+    LookupResponse {
+        records: vec![ResolverRecord {
+            record_type: record_type_for_version(version).to_string(),
+            value: address.clone(),
+        }],
+        address,
+        version,
+        source: LookupOrigin::Contract,
+        history: None,
+        coin: None,
     }
+}
 
-    let address = word_to_str([stack[3], stack[2], stack[1], stack[0]]);
+/// Names resolved per execution of `lookup_batch.masm`, the 16 element output stack holds
+/// one address word per name
+pub const LOOKUP_BATCH_SIZE: usize = 4;
 
-    println!("address: {:?}", address);
+/// Advice map key of name `index` in a batch lookup, `[0, 0, 0, index]`
+fn batch_key(index: usize) -> Word {
+    [ZERO, ZERO, ZERO, Felt::new(index as u64)]
+}
 
-    // Check if address is empty or zero (indicating name not found)
-    if address.trim().is_empty() || address == "0000000000000000" {
-        return Err(AppError::NotFound(format!(
-            "Name '{}' not registered",
-            name
+/// Look up several names with one program execution per [`LOOKUP_BATCH_SIZE`] names.
+///
+/// Results are in the order of `names`. A batch the contract fails to execute is retried
+/// name by name, so one bad name doesn't fail its neighbours.
+pub async fn lookup_batch(
+    client: &mut Client,
+    account_id: AccountId,
+    names: &[Name],
+) -> Vec<Result<LookupResponse>> {
+    let mut results = Vec::with_capacity(names.len());
+    for batch in names.chunks(LOOKUP_BATCH_SIZE) {
+        match execute_lookup_batch(client, account_id, batch).await {
+            Ok(stack) => results.extend(
+                decode_batch_stack(batch, &stack)
+                    .into_iter()
+                    .map(|decoded| decoded.map(contract_response)),
+            ),
+            // executor failures are all `Internal`, one name cannot fail the rest of its batch
+            Err(AppError::Internal(_)) => {
+                for name in batch {
+                    results.push(lookup(client, account_id, name.clone()).await);
+                }
+            }
+            Err(e) => results.extend(batch.iter().map(|_| Err(e.clone()))),
+        }
+    }
+    results
+}
+
+// Run the batch lookup script for up to `LOOKUP_BATCH_SIZE` names and return the full
+// output stack
+async fn execute_lookup_batch(
+    client: &mut Client,
+    account_id: AccountId,
+    names: &[Name],
+) -> Result<Vec<Felt>> {
+    let assembler = TransactionKernel::assembler().with_debug_mode(true);
+    let masm = masm();
+    let component_lib = create_library(assembler.clone(), "mns::mns_contract", &masm.contract)
+        .map_err(|e| {
+            error!("Failed to create library: {}", e);
+            AppError::Internal(format!("Contract compilation error: {}", e))
+        })?;
+
+    // unencodable names and unused slots look up the empty name, which is never registered
+    let advice_entries = (0..LOOKUP_BATCH_SIZE).map(|i| {
+        let word = names
+            .get(i)
+            .and_then(|name| encode_lookup_name(name).ok())
+            .unwrap_or(ScriptInputs::DEFAULT_KEY);
+        (batch_key(i), word.to_vec())
+    });
+    let tx_script = TransactionScript::compile(
+        masm.lookup_batch.clone(),
+        advice_entries,
+        assembler.with_library(&component_lib).map_err(|e| {
+            error!("Failed to attach library: {}", e);
+            AppError::Internal(format!("Script compilation error: {}", e))
+        })?,
+    )
+    .map_err(|e| {
+        error!("Failed to compile transaction script: {}", e);
+        AppError::Internal(format!("Transaction script error: {}", e))
+    })?;
+
+    let stack = client
+        .execute_program(
+            account_id,
+            tx_script,
+            AdviceInputs::default(),
+            BTreeSet::default(),
+        )
+        .await
+        .map_err(|e| {
+            error!(
+                "Failed to execute batch lookup program for {} names: {}",
+                names.len(),
+                e
+            );
+            AppError::Internal(format!("Program execution failed: {}", e))
+        })?;
+
+    Ok(stack.to_vec())
+}
+
+/// Decode the address and version of each name from the batch lookup stack, where the
+/// word of name `i` starts at `(LOOKUP_BATCH_SIZE - 1 - i) * 4`
+fn decode_batch_stack(names: &[Name], stack: &[Felt]) -> Vec<Result<(String, RecordVersion)>> {
+    names
+        .iter()
+        .enumerate()
+        .map(|(i, name)| {
+            let start = (LOOKUP_BATCH_SIZE - 1 - i) * 4;
+            decode_lookup_stack(name, stack.get(start..).unwrap_or_default())
+        })
+        .collect()
+}
+
+/// Run the lookup script and report its raw output stack next to the decoded address
+pub async fn debug_lookup(
+    client: &mut Client,
+    account_id: AccountId,
+    name: Name,
+) -> Result<DebugLookupResponse> {
+    let key = encode_lookup_name(&name)?;
+    let stack = execute_lookup(client, account_id, &name).await?;
+
+    // decoding failures are part of the diagnosis, not an error of the endpoint
+    let decoded = decode_lookup_stack(&name, &stack);
+
+    Ok(DebugLookupResponse {
+        name: name.into(),
+        key: word_to_hex(key),
+        stack: stack
+            .iter()
+            .map(|felt| format!("0x{:016x}", felt.as_int()))
+            .collect(),
+        address: decoded.as_ref().ok().map(|(address, _)| address.clone()),
+        version: decoded.as_ref().ok().map(|(_, version)| *version),
+        error: decoded.err().map(|e| e.to_string()),
+    })
+}
+
+/// Read the raw names map of every shard in key order, `limit` entries after `cursor` at
+/// a time.
+///
+/// The cursor is the last key of the previous page, so entries written while an indexer
+/// walks the map never shift the pages it has yet to read. A name lives in a single
+/// shard, so keys stay unique once the shards are merged.
+pub async fn export_storage(
+    client: &mut Client,
+    account_ids: &[AccountId],
+    cursor: Option<Word>,
+    limit: usize,
+) -> Result<ExportResponse> {
+    let entries = shards_map_entries(client, account_ids).await?;
+    Ok(export_page(entries, cursor, limit))
+}
+
+fn export_page(entries: Vec<(Word, Word)>, cursor: Option<Word>, limit: usize) -> ExportResponse {
+    let page = entries_after(entries, cursor)
+        .into_iter()
+        .take(limit + 1)
+        .map(|(key, _, value)| {
+            let entry = ExportEntry {
+                key: key.clone(),
+                value: word_to_hex(value),
+            };
+            (key, entry)
+        })
+        .collect();
+    let (entries, next_cursor) = cut_page(page, limit);
+
+    ExportResponse {
+        entries,
+        next_cursor,
+    }
+}
+
+// Map entries with a key above `cursor` in key order, each with its hex key
+fn entries_after(entries: Vec<(Word, Word)>, cursor: Option<Word>) -> Vec<(String, Word, Word)> {
+    // hex keys have a fixed width, so their string order is the order of the key bytes
+    let cursor = cursor.map(word_to_hex);
+    let mut entries: Vec<(String, Word, Word)> = entries
+        .into_iter()
+        .map(|(key, value)| (word_to_hex(key), key, value))
+        .filter(|(key, ..)| cursor.as_ref().is_none_or(|cursor| key > cursor))
+        .collect();
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+    entries
+}
+
+// Cut up to `limit + 1` keyed entries to a page of `limit`, the cursor of the next page
+// is the last key kept and `None` when nothing follows
+fn cut_page<T>(mut page: Vec<(String, T)>, limit: usize) -> (Vec<T>, Option<String>) {
+    let next_cursor = if page.len() > limit {
+        page.truncate(limit);
+        page.last().map(|(key, _)| key.clone())
+    } else {
+        None
+    };
+    (
+        page.into_iter().map(|(_, entry)| entry).collect(),
+        next_cursor,
+    )
+}
+
+/// Whether a name is still free in the contract, distinguishing "not registered" from a
+/// failed lookup
+pub async fn is_available(client: &mut Client, account_id: AccountId, name: Name) -> Result<bool> {
+    match lookup(client, account_id, name).await {
+        Ok(_) => Ok(false),
+        Err(AppError::NotFound(_)) => Ok(true),
+        Err(e) => Err(e),
+    }
+}
+
+/// Register a throwaway name and read it back, proving the register and lookup scripts
+/// agree against the live chain. The name stays registered in the contract
+pub async fn self_test(
+    client: &mut Client,
+    account_id: AccountId,
+    retry_policy: &RetryPolicy,
+) -> Result<()> {
+    let name = Name::parse(
+        &format!("selftest{}.miden", rand::random::<u32>()),
+        MAX_NAME_BYTES,
+    )?;
+    let address = account_id.to_hex();
+    info!("Self-test registering {} in {}", name, account_id);
+
+    // the server registers the name itself, so its own account is the owner
+    register(
+        client,
+        account_id,
+        name.clone(),
+        address.clone(),
+        Some(account_id),
+        retry_policy,
+    )
+    .await?;
+    retry::sync_state(client, retry_policy)
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to sync blockchain state: {}", e)))?;
+
+    let found = lookup(client, account_id, name.clone()).await?;
+    if found.address != address {
+        return Err(AppError::Internal(format!(
+            "Self-test lookup of {} returned {} instead of {}",
+            name, found.address, address
         )));
     }
 
-    // build response
-    let response = LookupResponse {
-        address,
-        // TODO: Change this when we have web3 addresses too (will need to find a way to
-        // differentiate them)
-        version: "2.5".to_string(),
+    Ok(())
+}
+
+// Every raw key/value pair of the names map, read from the local copy of the account
+async fn names_map_entries(
+    client: &mut Client,
+    account_id: AccountId,
+) -> Result<Vec<(Word, Word)>> {
+    let account_record = client
+        .get_account(account_id)
+        .await
+        .map_err(|e| {
+            error!("Failed to read account: {}", e);
+            AppError::Internal(format!("Failed to read account: {}", e))
+        })?
+        .ok_or_else(|| AppError::Internal("MNS account not found in client".to_string()))?;
+
+    // the names map is the first storage slot of the contract
+    match account_record.account().storage().slots().first() {
+        Some(StorageSlot::Map(map)) => Ok(map
+            .entries()
+            .map(|(key, value)| ((*key).into(), *value))
+            .collect()),
+        _ => Err(AppError::Internal(
+            "MNS account storage slot 0 is not a map".to_string(),
+        )),
+    }
+}
+
+// Names map entries of all `account_ids`, in shard order
+async fn shards_map_entries(
+    client: &mut Client,
+    account_ids: &[AccountId],
+) -> Result<Vec<(Word, Word)>> {
+    let mut entries = Vec::new();
+    for account_id in account_ids {
+        entries.extend(names_map_entries(client, *account_id).await?);
+    }
+    Ok(entries)
+}
+
+/// Read a page of the name -> address storage maps of every shard directly from the
+/// account storage, `limit` names after `cursor` in key order like [`export_storage`]
+pub async fn list_entries(
+    client: &mut Client,
+    account_ids: &[AccountId],
+    cursor: Option<Word>,
+    limit: usize,
+) -> Result<StorageResponse> {
+    let entries = shards_map_entries(client, account_ids).await?;
+    Ok(storage_page(entries, cursor, limit))
+}
+
+fn storage_page(entries: Vec<(Word, Word)>, cursor: Option<Word>, limit: usize) -> StorageResponse {
+    let total = entries.len();
+
+    // a corrupted entry is skipped rather than failing the whole listing
+    let page = entries_after(entries, cursor)
+        .into_iter()
+        .filter_map(|(hex_key, key, value)| {
+            match (word_to_str(key), word_to_tagged_address(value)) {
+                (Ok(name), Ok((address, _))) => Some((hex_key, StorageEntry { name, address })),
+                (Err(e), _) | (_, Err(e)) => {
+                    warn!("Skipping undecodable storage entry: {}", e);
+                    None
+                }
+            }
+        })
+        .take(limit + 1)
+        .collect();
+    let (entries, next_cursor) = cut_page(page, limit);
+
+    StorageResponse {
+        entries,
+        total,
+        next_cursor,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use miden_client::{Felt, ONE, ZERO};
+
+    use crate::error::AppError;
+    use crate::serde::{
+        MAX_NAME_BYTES, Name, RecordVersion, address_to_tagged_word, str_to_word, word_to_hex,
+    };
+    use crate::service::{
+        LOOKUP_BATCH_SIZE, ScriptInputs, decode_batch_stack, decode_lookup_stack,
+        encode_lookup_name, export_page, storage_page,
     };
 
-    Ok(response)
+    #[test]
+    fn test_lookup_encoding_of_unencodable_names() {
+        let names = [
+            "0123456789012345678901234",       // 25 ASCII bytes
+            "paulhenry.miden.paulhenry.miden", // long dotted name
+            "世界世界世界世界世",              // 9 characters, 27 bytes
+            "123456789012345678901234\u{0}",   // trailing byte past the limit
+        ];
+
+        for name in names {
+            assert!(
+                matches!(encode_lookup_name(name), Err(AppError::NotFound(_))),
+                "Expected not found for name: {}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn test_lookup_encoding_of_valid_names() {
+        for name in [
+            "a",
+            "mirko.miden",
+            "123456789012345678901234",
+            "123456789012345678世界",
+        ] {
+            assert!(
+                encode_lookup_name(name).is_ok(),
+                "Failed for name: {}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn test_decode_short_stack() {
+        for len in 0..4 {
+            let stack = vec![ZERO; len];
+            assert!(matches!(
+                decode_lookup_stack("alice.miden", &stack),
+                Err(AppError::NotFound(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_decode_stack() {
+        // the script leaves the address word reversed on top of the stack
+        let word = str_to_word("0xabc");
+        let mut stack: Vec<Felt> = word.iter().rev().copied().collect();
+        stack.extend([ZERO; 12]);
+        assert_eq!(
+            decode_lookup_stack("alice.miden", &stack).unwrap(),
+            ("0xabc".to_string(), RecordVersion::Web2_5)
+        );
+
+        // tagged values report their stored version
+        let word = address_to_tagged_word("0xabc", RecordVersion::Web3).unwrap();
+        let mut stack: Vec<Felt> = word.iter().rev().copied().collect();
+        stack.extend([ZERO; 12]);
+        assert_eq!(
+            decode_lookup_stack("alice.miden", &stack).unwrap(),
+            ("0xabc".to_string(), RecordVersion::Web3)
+        );
+
+        // an empty map value means the name isn't registered
+        assert!(matches!(
+            decode_lookup_stack("alice.miden", &[ZERO; 16]),
+            Err(AppError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_decode_batch_stack() {
+        let names: Vec<Name> = ["alice.miden", "bob.miden", "carol.miden"]
+            .into_iter()
+            .map(|name| Name::parse(name, MAX_NAME_BYTES).unwrap())
+            .collect();
+
+        // name 0 is deepest, the unused fourth slot on top holds an empty value
+        let mut stack = vec![ZERO; 4];
+        for address in ["0x333", "", "0x111"] {
+            let word = match address {
+                "" => [ZERO; 4],
+                address => address_to_tagged_word(address, RecordVersion::Web2_5).unwrap(),
+            };
+            stack.extend(word.iter().rev());
+        }
+        assert_eq!(stack.len(), LOOKUP_BATCH_SIZE * 4);
+
+        let decoded = decode_batch_stack(&names, &stack);
+        assert_eq!(
+            decoded[0].as_ref().unwrap(),
+            &("0x111".to_string(), RecordVersion::Web2_5)
+        );
+        assert!(matches!(decoded[1], Err(AppError::NotFound(_))));
+        assert_eq!(decoded[2].as_ref().unwrap().0, "0x333");
+    }
+
+    #[test]
+    fn test_register_inputs_layout() {
+        let name = str_to_word("alice.miden");
+        let address = address_to_tagged_word("0xabc", RecordVersion::Web2_5).unwrap();
+        let owner = [ONE, ONE, ZERO, ZERO];
+        let inputs = ScriptInputs::register(name, address, owner);
+
+        assert_eq!(inputs.key(), ScriptInputs::DEFAULT_KEY);
+        assert_eq!(&inputs.felts()[..4], &address);
+        assert_eq!(&inputs.felts()[4..8], &name);
+        assert_eq!(&inputs.felts()[8..], &owner);
+        assert_eq!(ScriptInputs::lookup(name).felts(), &name);
+    }
+
+    #[test]
+    fn test_custom_key_prelude() {
+        let source = "use.mns::mns_contract\n\nbegin\n    call.mns_contract::lookup\nend\n";
+        let inputs = ScriptInputs::lookup(str_to_word("alice.miden"));
+
+        // the default key is already on the stack
+        assert_eq!(inputs.script(source), source);
+
+        let inputs = inputs.with_key([ONE, ZERO, ZERO, Felt::new(7)]);
+        assert_eq!(inputs.advice_entry().0, [ONE, ZERO, ZERO, Felt::new(7)]);
+        assert!(
+            inputs
+                .script(source)
+                .contains("begin\n    push.1.0.0.7\n    call")
+        );
+    }
+
+    #[test]
+    fn test_export_page_follows_cursor() {
+        // keys out of order, as the storage map may yield them
+        let entries: Vec<_> = ["carol", "alice", "bob"]
+            .iter()
+            .map(|name| (str_to_word(name), str_to_word("0xabc")))
+            .collect();
+        let mut keys: Vec<_> = entries.iter().map(|(key, _)| *key).collect();
+        keys.sort_by_key(|key| word_to_hex(*key));
+
+        let first = export_page(entries.clone(), None, 2);
+        assert_eq!(first.entries.len(), 2);
+        assert_eq!(first.entries[0].key, word_to_hex(keys[0]));
+        assert_eq!(first.next_cursor, Some(word_to_hex(keys[1])));
+
+        let second = export_page(entries.clone(), Some(keys[1]), 2);
+        assert_eq!(second.entries.len(), 1);
+        assert_eq!(second.entries[0].key, word_to_hex(keys[2]));
+        assert_eq!(second.entries[0].value, word_to_hex(str_to_word("0xabc")));
+        assert_eq!(second.next_cursor, None);
+
+        // a page ending exactly on the last entry has no continuation
+        assert_eq!(export_page(entries, None, 3).next_cursor, None);
+    }
+
+    #[test]
+    fn test_storage_page_follows_cursor() {
+        let address = address_to_tagged_word("0xabc", RecordVersion::Web2_5).unwrap();
+        let entries: Vec<_> = ["carol.miden", "alice.miden", "bob.miden"]
+            .iter()
+            .map(|name| (str_to_word(name), address))
+            .collect();
+        let mut names = ["carol.miden", "alice.miden", "bob.miden"];
+        names.sort_by_key(|name| word_to_hex(str_to_word(name)));
+
+        let first = storage_page(entries.clone(), None, 2);
+        assert_eq!(first.total, 3);
+        let listed: Vec<_> = first
+            .entries
+            .iter()
+            .map(|entry| entry.name.as_str())
+            .collect();
+        assert_eq!(listed, names[..2]);
+        assert_eq!(first.next_cursor, Some(word_to_hex(str_to_word(names[1]))));
+
+        let second = storage_page(entries, Some(str_to_word(names[1])), 2);
+        assert_eq!(second.entries.len(), 1);
+        assert_eq!(second.entries[0].name, names[2]);
+        assert_eq!(second.entries[0].address, "0xabc");
+        assert_eq!(second.next_cursor, None);
+    }
 }
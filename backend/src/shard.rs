@@ -0,0 +1,75 @@
+//! Selection of the contract account holding a name when registrations are sharded.
+//!
+//! The mapping must never change for a given shard count, names registered on one shard
+//! are only found there. It uses FNV-1a rather than `std`'s hasher, whose output is not
+//! guaranteed to be stable across Rust releases.
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// Stable 64-bit FNV-1a hash of a name
+fn fnv1a(name: &str) -> u64 {
+    name.bytes().fold(FNV_OFFSET_BASIS, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(FNV_PRIME)
+    })
+}
+
+/// Index of the shard holding `name` out of `shards`, always 0 for a single shard
+pub fn shard_for(name: &str, shards: usize) -> usize {
+    if shards <= 1 {
+        return 0;
+    }
+    (fnv1a(name) % shards as u64) as usize
+}
+
+/// File recording the deployed account of a shard, shard 0 keeps the unsharded file name
+pub fn deployment_file(shard: usize) -> String {
+    match shard {
+        0 => crate::utils::DEPLOYMENT_FILE.to_string(),
+        n => format!("deployment.shard{}.json", n),
+    }
+}
+
+/// Seed of a shard's account derived from the configured deploy seed, so every shard gets
+/// a distinct but reproducible account id
+pub fn shard_seed(seed: Option<[u8; 32]>, shard: usize) -> Option<[u8; 32]> {
+    seed.map(|mut seed| {
+        for (byte, shard_byte) in seed.iter_mut().zip((shard as u64).to_le_bytes()) {
+            *byte ^= shard_byte;
+        }
+        seed
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::shard::{deployment_file, shard_for, shard_seed};
+
+    #[test]
+    fn test_shard_for_is_stable() {
+        assert_eq!(shard_for("alice.miden", 1), 0);
+        // pinned values, changing them strands every name registered on a shard
+        assert_eq!(shard_for("alice.miden", 4), 0);
+        assert_eq!(shard_for("bob.miden", 4), 3);
+        assert_eq!(super::fnv1a(""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(super::fnv1a("a"), 0xaf63_dc4c_8601_ec8c);
+
+        let names = (0..1000).map(|i| format!("user{}.miden", i));
+        let mut counts = [0; 4];
+        for name in names {
+            counts[shard_for(&name, 4)] += 1;
+        }
+        assert!(counts.iter().all(|count| *count > 150), "{:?}", counts);
+    }
+
+    #[test]
+    fn test_shard_deployments_are_distinct() {
+        assert_eq!(deployment_file(0), "deployment.json");
+        assert_eq!(deployment_file(2), "deployment.shard2.json");
+
+        let seed = Some([7u8; 32]);
+        assert_eq!(shard_seed(seed, 0), seed);
+        assert_ne!(shard_seed(seed, 1), seed);
+        assert_eq!(shard_seed(None, 1), None);
+    }
+}
@@ -0,0 +1,86 @@
+use futures::future::{BoxFuture, FutureExt, Shared};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+/// Coalesces concurrent calls for the same key into one in-flight future
+pub struct SingleFlight<T: Clone> {
+    inflight: Arc<Mutex<HashMap<String, Shared<BoxFuture<'static, T>>>>>,
+}
+
+impl<T: Clone + Send + Sync + 'static> SingleFlight<T> {
+    pub fn new() -> Self {
+        Self {
+            inflight: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Join the flight running for `key`, or start one with `make` if there is none.
+    ///
+    /// The flight removes itself once it completes, so results are never reused by
+    /// later calls, and it keeps running for the remaining waiters if its starter is dropped.
+    pub async fn run<F, Fut>(&self, key: &str, make: F) -> T
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = T> + Send + 'static,
+    {
+        let flight = {
+            let mut inflight = self.inflight.lock().unwrap_or_else(|e| e.into_inner());
+
+            match inflight.get(key) {
+                Some(flight) => flight.clone(),
+                None => {
+                    let registry = self.inflight.clone();
+                    let owned_key = key.to_string();
+                    let future = make();
+                    let flight = async move {
+                        let result = future.await;
+                        registry
+                            .lock()
+                            .unwrap_or_else(|e| e.into_inner())
+                            .remove(&owned_key);
+                        result
+                    }
+                    .boxed()
+                    .shared();
+                    inflight.insert(key.to_string(), flight.clone());
+                    flight
+                }
+            }
+        };
+
+        flight.await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use crate::singleflight::SingleFlight;
+
+    #[tokio::test]
+    async fn test_concurrent_calls_share_one_flight() {
+        let flights = SingleFlight::new();
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        let run = || {
+            let calls = calls.clone();
+            flights.run("alice.miden", move || async move {
+                calls.fetch_add(1, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                42
+            })
+        };
+
+        let (a, b, c) = tokio::join!(run(), run(), run());
+        assert_eq!((a, b, c), (42, 42, 42));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // a finished flight is not reused
+        assert_eq!(run().await, 42);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+}
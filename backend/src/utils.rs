@@ -1,3 +1,4 @@
+use arc_swap::ArcSwap;
 use lazy_static::lazy_static;
 use log::{error, info};
 use miden_assembly::{
@@ -5,41 +6,158 @@ use miden_assembly::{
     ast::{Module, ModuleKind},
 };
 use miden_client::{
-    Client,
+    Client, Word,
     account::{
-        Account, AccountBuilder, AccountStorageMode, AccountType, StorageSlot,
-        component::AccountComponent,
+        Account, AccountBuilder, AccountCode, AccountId, AccountStorageMode, AccountType,
+        StorageSlot, component::AccountComponent,
     },
     builder::ClientBuilder,
-    rpc::{Endpoint, TonicRpcClient},
+    rpc::{Endpoint, NodeRpcClient, TonicRpcClient},
     transaction::{TransactionRequestBuilder, TransactionScript},
 };
 use miden_lib::transaction::TransactionKernel;
+use miden_objects::{account::AccountIdAnchor, block::BlockNumber};
 use rand::RngCore;
-use std::{fs, path::Path, sync::Arc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{collections::BTreeMap, fs, path::Path, sync::Arc};
 
-use crate::error::{AppError, Result};
+use crate::{
+    error::{AppError, Result},
+    retry::{self, RetryPolicy},
+    shard,
+};
+
+/// File recording the last deployed MNS account, a new one is deployed when it is missing
+pub const DEPLOYMENT_FILE: &str = "deployment.json";
+pub const DEFAULT_KEYSTORE_DIR: &str = "./keystore";
+
+/// Details of a deployed MNS contract account
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Deployment {
+    pub account_id: String,
+    pub seed: [u64; 4],
+    pub commitment: String,
+}
+
+/// Contract and transaction script sources, read from the MASM directory
+#[derive(Default)]
+pub struct MasmSources {
+    pub contract: String,
+    pub register: String,
+    pub lookup: String,
+    pub lookup_batch: String,
+    pub deploy: String,
+}
+
+impl MasmSources {
+    /// Read every source from `dir` and check that they all compile together
+    pub fn load(dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref();
+        let read = |file: &str| {
+            fs::read_to_string(dir.join(file)).map_err(|e| {
+                AppError::Internal(format!(
+                    "Failed to read {}: {}",
+                    dir.join(file).display(),
+                    e
+                ))
+            })
+        };
+
+        let sources = Self {
+            contract: read("mns.masm")?,
+            register: read("register.masm")?,
+            lookup: read("lookup.masm")?,
+            lookup_batch: read("lookup_batch.masm")?,
+            deploy: read("deploy.masm")?,
+        };
+
+        let assembler = TransactionKernel::assembler().with_debug_mode(true);
+        let library = create_library(assembler.clone(), "mns::mns_contract", &sources.contract)?;
+        let assembler = assembler
+            .with_library(&library)
+            .map_err(|e| AppError::Internal(format!("Script compilation error: {}", e)))?;
+        for (file, script) in [
+            ("register.masm", &sources.register),
+            ("lookup.masm", &sources.lookup),
+            ("lookup_batch.masm", &sources.lookup_batch),
+            ("deploy.masm", &sources.deploy),
+        ] {
+            TransactionScript::compile(script.clone(), [], assembler.clone())
+                .map_err(|e| AppError::Internal(format!("Failed to compile {}: {}", file, e)))?;
+        }
+
+        Ok(sources)
+    }
+
+    /// SHA-256 of each source file as loaded, hex encoded and keyed by file name
+    pub fn sha256(&self) -> BTreeMap<&'static str, String> {
+        [
+            ("mns.masm", &self.contract),
+            ("register.masm", &self.register),
+            ("lookup.masm", &self.lookup),
+            ("lookup_batch.masm", &self.lookup_batch),
+            ("deploy.masm", &self.deploy),
+        ]
+        .into_iter()
+        .map(|(file, source)| {
+            let digest = Sha256::digest(source.as_bytes());
+            let hex = digest.iter().map(|byte| format!("{:02x}", byte)).collect();
+            (file, hex)
+        })
+        .collect()
+    }
+
+    /// Assemble every source and render the result, for `--dump-masm-ir`.
+    /// The contract is dumped as its MAST forest, scripts as the programs the VM runs.
+    pub fn dump_ir(&self) -> Result<String> {
+        let assembler = TransactionKernel::assembler().with_debug_mode(true);
+        let library = create_library(assembler.clone(), "mns::mns_contract", &self.contract)?;
+        let assembler = assembler
+            .with_library(&library)
+            .map_err(|e| AppError::Internal(format!("Script compilation error: {}", e)))?;
+
+        let mut out = format!("# mns.masm\n{:#?}\n", library.mast_forest());
+        for (file, script) in [
+            ("register.masm", &self.register),
+            ("lookup.masm", &self.lookup),
+            ("lookup_batch.masm", &self.lookup_batch),
+            ("deploy.masm", &self.deploy),
+        ] {
+            let program = assembler
+                .clone()
+                .assemble_program(script.as_str())
+                .map_err(|e| AppError::Internal(format!("Failed to compile {}: {}", file, e)))?;
+            out.push_str(&format!("\n# {}\n{}\n", file, program));
+        }
+
+        Ok(out)
+    }
+}
 
 lazy_static! {
-    pub static ref MNS_CONTRACT: String = {
-        let path = Path::new("masm/mns.masm");
-        fs::read_to_string(path).unwrap()
-    };
-    pub static ref REGISTER_SCRIPT: String = {
-        let path = Path::new("masm/register.masm");
-        fs::read_to_string(path).unwrap()
-    };
-    pub static ref LOOKUP_SCRIPT: String = {
-        let path = Path::new("masm/lookup.masm");
-        fs::read_to_string(path).unwrap()
-    };
-    pub static ref DEPLOY_SCRIPT: String = {
-        let path = Path::new("masm/deploy.masm");
-        fs::read_to_string(path).unwrap()
-    };
+    static ref MASM: ArcSwap<MasmSources> = ArcSwap::from_pointee(MasmSources::default());
+}
+
+/// The current MASM sources, unaffected by reloads while the returned value is held
+pub fn masm() -> Arc<MasmSources> {
+    MASM.load_full()
+}
+
+/// Load the MASM sources from `dir`, keeping the current ones if anything fails to compile
+pub fn load_masm(dir: impl AsRef<Path>) -> Result<()> {
+    let sources = MasmSources::load(&dir)?;
+    MASM.store(Arc::new(sources));
+    info!("Loaded MASM sources from {}", dir.as_ref().display());
+    Ok(())
 }
 
-pub async fn create_client() -> Client {
+pub async fn create_client(timeout_ms: u64, keystore_dir: &str) -> Result<Client> {
+    create_client_with_rpc(rpc_client(timeout_ms), keystore_dir).await
+}
+
+/// RPC client of the Miden node, shared by a [`Client`] and [`keep_alive`]
+pub fn rpc_client(timeout_ms: u64) -> Arc<TonicRpcClient> {
     // let endpoint = Endpoint::new(
     //     "https".to_string(),
     //     "rpc.devnet.miden.io".to_string(),
@@ -48,19 +166,42 @@ pub async fn create_client() -> Client {
 
     let endpoint = Endpoint::new("http".to_string(), "localhost".to_string(), Some(3002));
 
-    let timeout_ms = 10_000;
-    let rpc_api = Arc::new(TonicRpcClient::new(&endpoint, timeout_ms));
+    Arc::new(TonicRpcClient::new(&endpoint, timeout_ms))
+}
+
+/// Send the node a request for its latest block header, keeping the connection of `rpc`
+/// in use.
+///
+/// `TonicRpcClient` builds its channel itself and takes no HTTP/2 keep-alive settings, so
+/// gateways that drop idle connections are kept from it with a real request instead of
+/// HTTP/2 pings.
+pub async fn keep_alive(rpc: &TonicRpcClient) -> Result<()> {
+    rpc.get_block_header_by_number(None, false)
+        .await
+        .map(|_| ())
+        .map_err(|e| AppError::Internal(format!("Keep-alive request failed: {}", e)))
+}
+
+pub async fn create_client_with_rpc(
+    rpc_api: Arc<TonicRpcClient>,
+    keystore_dir: &str,
+) -> Result<Client> {
     let client = ClientBuilder::new()
         .with_rpc(rpc_api)
-        .with_filesystem_keystore("./keystore")
+        .with_filesystem_keystore(keystore_dir)
         .in_debug_mode(true)
         .build()
         .await
-        .unwrap();
+        .map_err(|e| {
+            AppError::Internal(format!(
+                "Failed to create client with keystore at {}: {}",
+                keystore_dir, e
+            ))
+        })?;
 
-    info!("Created client");
+    info!("Created client with keystore at {}", keystore_dir);
 
-    client
+    Ok(client)
 }
 
 pub fn create_library(
@@ -88,56 +229,188 @@ pub fn create_library(
     Ok(library)
 }
 
-pub async fn create_account(client: &mut Client) -> Account {
-    // compile code
-    let assembler = TransactionKernel::assembler().with_debug_mode(true);
-    let component = AccountComponent::compile(
-        MNS_CONTRACT.clone(),
-        assembler,
-        vec![
-            StorageSlot::empty_map(),
-            StorageSlot::empty_value(),
-            StorageSlot::empty_value(),
-        ],
-    )
-    .unwrap()
-    .with_supports_all_types();
+/// Parse a `--deploy-seed` value: 32 bytes as 64 hex digits, with an optional `0x` prefix
+pub fn parse_deploy_seed(hex: &str) -> Result<[u8; 32]> {
+    let digits = hex.strip_prefix("0x").unwrap_or(hex);
+    if digits.len() != 64 || !digits.is_ascii() {
+        return Err(AppError::BadRequest(format!(
+            "Deploy seed must be 64 hex digits, got {} characters",
+            digits.len()
+        )));
+    }
 
-    // seed and anchor block
     let mut seed = [0_u8; 32];
-    client.rng().fill_bytes(&mut seed);
-    let anchor_block = client.get_latest_epoch_block().await.unwrap();
+    for (i, byte) in seed.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&digits[i * 2..i * 2 + 2], 16)
+            .map_err(|_| AppError::BadRequest(format!("Deploy seed '{}' is not valid hex", hex)))?;
+    }
 
-    // build new account
-    let (contract, seed) = AccountBuilder::new(seed)
-        .anchor((&anchor_block).try_into().unwrap())
+    Ok(seed)
+}
+
+// Storage of the MNS component, in the slot order the contract procedures index
+fn mns_storage_slots() -> Vec<StorageSlot> {
+    vec![
+        StorageSlot::empty_map(),
+        StorageSlot::empty_value(),
+        StorageSlot::empty_value(),
+        // name -> owner account id
+        StorageSlot::empty_map(),
+    ]
+}
+
+fn slot_kind(slot: &StorageSlot) -> &'static str {
+    match slot {
+        StorageSlot::Value(_) => "value",
+        StorageSlot::Map(_) => "map",
+    }
+}
+
+// Describe the first difference between `slots` and the MNS storage layout
+fn check_storage_layout(slots: &[StorageSlot]) -> std::result::Result<(), String> {
+    let expected = mns_storage_slots();
+    if slots.len() != expected.len() {
+        return Err(format!(
+            "has {} storage slots, the MNS contract expects {}",
+            slots.len(),
+            expected.len()
+        ));
+    }
+    for (index, (actual, expected)) in slots.iter().zip(&expected).enumerate() {
+        if slot_kind(actual) != slot_kind(expected) {
+            return Err(format!(
+                "storage slot {} is a {}, the MNS contract expects a {}",
+                index,
+                slot_kind(actual),
+                slot_kind(expected)
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Check that `account` has the storage slots the register and lookup scripts index, which
+/// would otherwise only show up as opaque VM errors
+pub fn verify_storage_layout(account: &Account) -> Result<()> {
+    check_storage_layout(account.storage().slots())
+        .map_err(|mismatch| AppError::Internal(format!("Account {} {}", account.id(), mismatch)))
+}
+
+/// The MNS account component compiled from the current contract source
+pub fn mns_component() -> Result<AccountComponent> {
+    let assembler = TransactionKernel::assembler().with_debug_mode(true);
+    let component =
+        AccountComponent::compile(masm().contract.clone(), assembler, mns_storage_slots())
+            .map_err(|e| AppError::Internal(format!("Contract compilation error: {}", e)))?;
+
+    Ok(component.with_supports_all_types())
+}
+
+// Account code of an MNS account of `account_type` built from the current contract source
+fn mns_code(account_type: AccountType) -> Result<AccountCode> {
+    AccountCode::from_components(&[mns_component()?], account_type)
+        .map_err(|e| AppError::Internal(format!("Failed to build contract code: {}", e)))
+}
+
+/// Code commitment of contract accounts deployed from the current source, hex encoded
+pub fn mns_code_commitment() -> Result<String> {
+    Ok(mns_code(AccountType::RegularAccountImmutableCode)?
+        .commitment()
+        .to_hex())
+}
+
+/// Check that `account` runs the MNS contract, by comparing its code commitment with the
+/// code built from the current contract source
+pub fn verify_mns_account(account: &Account) -> Result<()> {
+    let expected = mns_code(account.account_type())?;
+
+    let actual = account.code().commitment();
+    if actual != expected.commitment() {
+        return Err(AppError::Internal(format!(
+            "Account {} is not an MNS contract built from these sources: code commitment {} \
+             does not match {}",
+            account.id(),
+            actual.to_hex(),
+            expected.commitment().to_hex()
+        )));
+    }
+
+    Ok(())
+}
+
+/// Build a new public, immutable MNS account from `seed` anchored at the epoch block
+/// `anchor`, without network access.
+///
+/// The same code, seed and anchor always yield the same account id.
+pub fn build_mns_account(seed: [u8; 32], anchor: AccountIdAnchor) -> Result<(Account, Word)> {
+    AccountBuilder::new(seed)
+        .anchor(anchor)
         .account_type(AccountType::RegularAccountImmutableCode)
         .storage_mode(AccountStorageMode::Public)
-        .with_component(component.clone())
+        .with_component(mns_component()?)
         .build()
-        .unwrap();
+        .map_err(|e| AppError::Internal(format!("Failed to build MNS account: {}", e)))
+}
+
+/// Create the MNS account anchored at the epoch block of `anchor_block`, from `init_seed`
+/// when given so the same code, seed and anchor always yield the same account id, or from
+/// the client rng otherwise
+pub async fn create_account(
+    client: &mut Client,
+    init_seed: Option<[u8; 32]>,
+    anchor_block: u32,
+) -> Result<(Account, Word)> {
+    // seed and anchor block
+    let seed = init_seed.unwrap_or_else(|| {
+        let mut seed = [0_u8; 32];
+        client.rng().fill_bytes(&mut seed);
+        seed
+    });
+    // a fixed anchor rather than the latest epoch block keeps the id stable across deploys
+    let anchor_block = client
+        .get_epoch_block(BlockNumber::from(anchor_block))
+        .await
+        .map_err(|e| {
+            error!("Failed to fetch anchor block {}: {}", anchor_block, e);
+            AppError::Internal(format!("Failed to fetch anchor block: {}", e))
+        })?;
+    let anchor: AccountIdAnchor = (&anchor_block)
+        .try_into()
+        .map_err(|e| AppError::Internal(format!("Invalid anchor block: {}", e)))?;
+
+    // build new account
+    let (contract, seed) = build_mns_account(seed, anchor)?;
 
     // add account to client
     client
         .add_account(&contract.clone(), Some(seed), false)
         .await
-        .unwrap();
+        .map_err(|e| {
+            error!("Failed to add mns account to the client: {}", e);
+            AppError::Internal(format!("Failed to add account: {}", e))
+        })?;
 
     info!("Created mns account");
 
-    contract
+    Ok((contract, seed))
 }
 
-pub async fn deploy_account(client: &mut Client, account: &Account) -> Result<()> {
+pub async fn deploy_account(
+    client: &mut Client,
+    account: &Account,
+    retry_policy: &RetryPolicy,
+) -> Result<()> {
     // sync client to latest chain state
-    client.sync_state().await.map_err(|e| {
+    retry::sync_state(client, retry_policy).await.map_err(|e| {
         error!("Failed to sync client state: {}", e);
         AppError::Internal(format!("Failed to sync blockchain state: {}", e))
     })?;
 
     // compile code
     let assembler = TransactionKernel::assembler().with_debug_mode(true);
-    let component_lib = create_library(assembler.clone(), "mns::mns_contract", &MNS_CONTRACT)
+    let masm = masm();
+    let component_lib = create_library(assembler.clone(), "mns::mns_contract", &masm.contract)
         .map_err(|e| {
             error!("Failed to create library: {}", e);
             AppError::Internal(format!("Contract compilation error: {}", e))
@@ -145,7 +418,7 @@ pub async fn deploy_account(client: &mut Client, account: &Account) -> Result<()
 
     // build script
     let tx_script = TransactionScript::compile(
-        DEPLOY_SCRIPT.clone(),
+        masm.deploy.clone(),
         [],
         assembler.with_library(&component_lib).map_err(|e| {
             error!("Failed to attach library: {}", e);
@@ -188,16 +461,122 @@ pub async fn deploy_account(client: &mut Client, account: &Account) -> Result<()
     info!("contract storage: {:?}", account.storage());
 
     // submit tx
-    client.submit_transaction(tx_result).await.map_err(|e| {
-        error!("Failed to submit transaction: {}", e);
-        AppError::Internal(format!("Transaction submission failed: {}", e))
-    })?;
+    retry::submit_transaction(client, tx_result, retry_policy)
+        .await
+        .map_err(|e| {
+            error!("Failed to submit transaction: {}", e);
+            AppError::Internal(format!("Transaction submission failed: {}", e))
+        })?;
 
     info!("Successfully deployed mns account");
 
     Ok(())
 }
 
+/// Create, deploy and persist a new MNS account, recording it in `deployment_file`
+pub async fn deploy_new_account(
+    client: &mut Client,
+    init_seed: Option<[u8; 32]>,
+    anchor_block: u32,
+    retry_policy: &RetryPolicy,
+    deployment_file: &str,
+) -> Result<Account> {
+    let (account, seed) = create_account(client, init_seed, anchor_block).await?;
+    deploy_account(client, &account, retry_policy).await?;
+    save_deployment(deployment_file, &account, seed);
+    Ok(account)
+}
+
+/// Import the account of an additional shard from its deployment file, deploying a new one
+/// when there is none and `read_only` is not set
+pub async fn load_or_deploy_shard(
+    client: &mut Client,
+    shard: usize,
+    deploy_seed: Option<[u8; 32]>,
+    anchor_block: u32,
+    retry_policy: &RetryPolicy,
+    read_only: bool,
+) -> Result<Account> {
+    let deployment_file = shard::deployment_file(shard);
+    if let Some(deployment) = load_deployment(&deployment_file) {
+        let account_id = AccountId::from_hex(&deployment.account_id).map_err(|e| {
+            AppError::Internal(format!("Invalid account id in {}: {}", deployment_file, e))
+        })?;
+        client
+            .import_account_by_id(account_id)
+            .await
+            .map_err(|e| AppError::Internal(format!("Failed to import shard {}: {}", shard, e)))?;
+        if let Some(record) = client
+            .get_account(account_id)
+            .await
+            .map_err(|e| AppError::Internal(format!("Failed to load shard {}: {}", shard, e)))?
+        {
+            info!("Imported shard {} account {}", shard, account_id);
+            return Ok(record.account().clone());
+        }
+    }
+
+    if read_only {
+        return Err(AppError::Internal(format!(
+            "No account for shard {}, cannot deploy in read-only mode",
+            shard
+        )));
+    }
+
+    info!("Deploying a new account for shard {}", shard);
+    deploy_new_account(
+        client,
+        shard::shard_seed(deploy_seed, shard),
+        anchor_block,
+        retry_policy,
+        &deployment_file,
+    )
+    .await
+}
+
+/// Write the deployed account details to disk so restarts reuse the same contract
+pub fn save_deployment(path: impl AsRef<Path>, account: &Account, seed: Word) {
+    let deployment = Deployment {
+        account_id: account.id().to_hex(),
+        seed: seed.map(|felt| felt.as_int()),
+        commitment: account.commitment().to_hex(),
+    };
+
+    let path = path.as_ref();
+    let result = serde_json::to_string_pretty(&deployment)
+        .map_err(|e| e.to_string())
+        .and_then(|json| fs::write(path, json).map_err(|e| e.to_string()));
+
+    match result {
+        Ok(()) => info!(
+            "Saved deployment {} to {}",
+            deployment.account_id,
+            path.display()
+        ),
+        Err(e) => error!("Failed to save deployment to {}: {}", path.display(), e),
+    }
+}
+
+/// Read the deployed account details, if a deployment file exists
+pub fn load_deployment(path: impl AsRef<Path>) -> Option<Deployment> {
+    let path = path.as_ref();
+    if !path.exists() {
+        return None;
+    }
+
+    match fs::read_to_string(path).map(|json| serde_json::from_str::<Deployment>(&json)) {
+        Ok(Ok(deployment)) => Some(deployment),
+        Ok(Err(e)) => {
+            error!("Invalid deployment file {}: {}", path.display(), e);
+            None
+        }
+        Err(e) => {
+            error!("Failed to read deployment file {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
 pub fn remove_store() {
     let file_path = "store.sqlite3";
 
@@ -212,3 +591,81 @@ pub fn remove_store() {
         println!("The file {} does not exist.", file_path);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use miden_client::account::{AccountStorageMode, AccountType, StorageSlot};
+    use miden_objects::account::AccountIdAnchor;
+
+    use crate::utils::{
+        MasmSources, build_mns_account, check_storage_layout, load_masm, mns_storage_slots,
+        parse_deploy_seed, verify_mns_account, verify_storage_layout,
+    };
+
+    #[test]
+    fn test_parse_deploy_seed() {
+        let hex = "00".repeat(31) + "ff";
+        let seed = parse_deploy_seed(&hex).unwrap();
+        assert_eq!(seed[31], 0xff);
+        assert_eq!(parse_deploy_seed(&format!("0x{}", hex)).unwrap(), seed);
+
+        assert!(parse_deploy_seed("0xabcd").is_err());
+        assert!(parse_deploy_seed(&"zz".repeat(32)).is_err());
+    }
+
+    #[test]
+    fn test_check_storage_layout() {
+        assert!(check_storage_layout(&mns_storage_slots()).is_ok());
+
+        let missing = check_storage_layout(&[StorageSlot::empty_map()]).unwrap_err();
+        assert_eq!(missing, "has 1 storage slots, the MNS contract expects 4");
+
+        let mut swapped = mns_storage_slots();
+        swapped[0] = StorageSlot::empty_value();
+        let swapped = check_storage_layout(&swapped).unwrap_err();
+        assert_eq!(
+            swapped,
+            "storage slot 0 is a value, the MNS contract expects a map"
+        );
+    }
+
+    #[test]
+    fn test_masm_sha256() {
+        let sources = MasmSources {
+            contract: "abc".to_string(),
+            ..MasmSources::default()
+        };
+        let hashes = sources.sha256();
+
+        assert_eq!(hashes.len(), 5);
+        assert_eq!(
+            hashes["mns.masm"],
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            hashes["deploy.masm"],
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+    }
+
+    #[test]
+    fn test_build_mns_account_offline() {
+        load_masm("masm").unwrap();
+        let anchor = AccountIdAnchor::PRE_GENESIS;
+
+        let (account, _) = build_mns_account([7; 32], anchor).unwrap();
+        assert_eq!(
+            account.account_type(),
+            AccountType::RegularAccountImmutableCode
+        );
+        assert_eq!(account.id().storage_mode(), AccountStorageMode::Public);
+        verify_mns_account(&account).unwrap();
+        verify_storage_layout(&account).unwrap();
+
+        // the id only depends on the code, seed and anchor
+        let (again, _) = build_mns_account([7; 32], anchor).unwrap();
+        assert_eq!(again.id(), account.id());
+        let (other, _) = build_mns_account([8; 32], anchor).unwrap();
+        assert_ne!(other.id(), account.id());
+    }
+}